@@ -5,6 +5,7 @@ extern crate ntp;
 
 use chrono::TimeZone;
 
+#[allow(deprecated)]
 fn local_time(timestamp: ntp::protocol::TimestampFormat) -> chrono::DateTime<chrono::Local> {
     let unix_time = ntp::unix_time::Instant::from(timestamp);
     chrono::Local.timestamp(unix_time.secs(), unix_time.subsec_nanos() as _)
@@ -0,0 +1,288 @@
+//! An asynchronous NTP client built on tokio, available with the `tokio` feature.
+//!
+//! An **AsyncClient** sends every request to servers of the same address family from a single
+//! socket. A background task receives the responses and hands each one to the request to its
+//! source whose transmit timestamp it echoes, so many servers can be queried concurrently.
+//!
+//! ```no_run
+//! use ntp::async_client::AsyncClient;
+//! use ntp::client::Client;
+//! use std::time::Duration;
+//!
+//! #[tokio::main(flavor = "current_thread")]
+//! async fn main() {
+//!     let client = AsyncClient::new(Client::new().timeout(Duration::from_secs(2)))
+//!         .await
+//!         .unwrap();
+//!     let (a, b) = tokio::join!(
+//!         client.request("0.pool.ntp.org:123"),
+//!         client.request("1.pool.ntp.org:123"),
+//!     );
+//!     println!("offsets: {} s, {} s", a.unwrap().offset, b.unwrap().offset);
+//! }
+//! ```
+
+use crate::client::{self, Client};
+use crate::exchange::{Action, Exchange};
+use crate::measurement::Measurement;
+use crate::protocol::{PacketView, TimestampFormat};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::net::{self, ToSocketAddrs, UdpSocket};
+use tokio::sync::{mpsc, OnceCell};
+use tokio::task::JoinHandle;
+use tokio::time;
+
+// A datagram along with its source and the time at which it was received.
+type Received = (Vec<u8>, SocketAddr, TimestampFormat);
+
+// The key of a pending request: its transmit timestamp and the address of the server.
+type Key = (TimestampFormat, SocketAddr);
+
+/// An asynchronous NTP client that behaves like the blocking `Client` it is configured with.
+///
+/// If a bind address is configured, all requests share one socket bound to it. Otherwise the
+/// requests to the servers of each address family share a socket bound to the unspecified address
+/// of that family on first use, as the blocking client binds to. As the sockets are shared, data
+/// minimization does not choose a fresh source port for each request, and concurrent requests to
+/// the same server must have distinct transmit timestamps.
+///
+/// Must be created and used within a tokio runtime.
+pub struct AsyncClient {
+    config: Client,
+    // The endpoints for IPv4 and IPv6 servers, in that order.
+    endpoints: [OnceCell<Endpoint>; 2],
+}
+
+// A socket shared by requests, and the task receiving their responses on it.
+struct Endpoint {
+    socket: Arc<UdpSocket>,
+    pending: Pending,
+    receiver: JoinHandle<()>,
+}
+
+// The requests awaiting a response, keyed by their transmit timestamp and server, or `None` once
+// the task receiving their responses has stopped.
+type Pending = Arc<Mutex<Option<HashMap<Key, mpsc::UnboundedSender<Received>>>>>;
+
+// Inherent implementations.
+
+impl AsyncClient {
+    /// Create a client configured by `config`, binding the socket for the configured bind address
+    /// if any and starting to receive responses on it.
+    pub async fn new(config: Client) -> io::Result<Self> {
+        let client = AsyncClient {
+            config,
+            endpoints: [OnceCell::new(), OnceCell::new()],
+        };
+        if let Some(bind_addr) = client.config.bind_addr {
+            client.endpoint(&bind_addr).await?;
+        }
+        Ok(client)
+    }
+
+    /// The local address of the socket requests to `peer` are sent from.
+    ///
+    ///   returns an error of kind `NotConnected` if no request to a server of the same address
+    ///   family has bound it yet.
+    pub fn local_addr(&self, peer: &SocketAddr) -> io::Result<SocketAddr> {
+        match self.slot(peer).get() {
+            Some(endpoint) => endpoint.socket.local_addr(),
+            None => {
+                let err_msg = "no socket is bound for the address family";
+                Err(io::Error::new(io::ErrorKind::NotConnected, err_msg))
+            }
+        }
+    }
+
+    /// Send a request to the ntp server at `addr`, retrying as configured.
+    ///
+    ///   returns the error of the last attempt if the server cannot be reached, the response is
+    ///   invalid, or the response is rejected. A rejected response is reported as an `io::Error`
+    ///   of kind `InvalidData` wrapping a `ResponseError`.
+    pub async fn request<A: ToSocketAddrs>(&self, addr: A) -> io::Result<Measurement> {
+        let addr = match net::lookup_host(addr).await?.next() {
+            Some(addr) => addr,
+            None => {
+                let err_msg = "address resolved to no socket addresses";
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, err_msg));
+            }
+        };
+        let endpoint = self.endpoint(&addr).await?;
+        let mut exchange = Exchange::new(self.config.clone(), addr, Instant::now());
+        let mut registration = None;
+        loop {
+            match exchange.poll(Instant::now(), self.config.now()) {
+                Action::Send { addr, bytes } => {
+                    let transmit = exchange.transmit_timestamp().expect("request is pending");
+                    let key = (transmit, addr);
+                    registration = None;
+                    let result = match endpoint.register(key) {
+                        Ok(receiver) => {
+                            let pending = &endpoint.pending;
+                            registration = Some((receiver, Deregister { pending, key }));
+                            let send = endpoint.socket.send_to(&bytes, addr);
+                            with_timeout(self.config.write_timeout, send).await
+                        }
+                        Err(err) => Err(err),
+                    };
+                    if let Err(err) = result {
+                        exchange.fail(err, Instant::now());
+                    }
+                }
+                Action::Receive { deadline } => {
+                    let (receiver, _) = registration.as_mut().expect("request is registered");
+                    let received = match deadline.map(time::Instant::from_std) {
+                        Some(deadline) => time::timeout_at(deadline, receiver.recv()).await,
+                        None => Ok(receiver.recv().await),
+                    };
+                    let now = Instant::now();
+                    match received {
+                        // The exchange times out the attempt once the deadline has passed.
+                        Err(_) => continue,
+                        Ok(Some((bytes, src, time))) => exchange.receive(src, &bytes, time, now),
+                        Ok(None) => {
+                            let err_msg = "receiving task stopped";
+                            let err = io::Error::new(io::ErrorKind::BrokenPipe, err_msg);
+                            exchange.fail(err, now);
+                        }
+                    }
+                }
+                Action::Sleep { until } => time::sleep_until(time::Instant::from_std(until)).await,
+                Action::Done(result) => return result,
+            }
+        }
+    }
+
+    // The endpoint that requests to `peer` are sent from, binding it on first use.
+    async fn endpoint(&self, peer: &SocketAddr) -> io::Result<&Endpoint> {
+        let bind_addr = self.config.bind_addr.unwrap_or_else(|| client::unspecified(peer));
+        let bind = async {
+            let socket = Arc::new(UdpSocket::bind(bind_addr).await?);
+            let pending = Pending::new(Mutex::new(Some(HashMap::new())));
+            let task = receive(socket.clone(), pending.clone(), self.config.clone());
+            let receiver = tokio::spawn(task);
+            Ok(Endpoint {
+                socket,
+                pending,
+                receiver,
+            })
+        };
+        self.slot(peer).get_or_try_init(|| bind).await
+    }
+
+    // The slot of the endpoint that requests to `peer` are sent from.
+    fn slot(&self, peer: &SocketAddr) -> &OnceCell<Endpoint> {
+        let addr = self.config.bind_addr.as_ref().unwrap_or(peer);
+        &self.endpoints[addr.is_ipv6() as usize]
+    }
+}
+
+impl Endpoint {
+    // Register the request with the transmit timestamp and server of `key` to receive its
+    // response.
+    fn register(&self, key: Key) -> io::Result<mpsc::UnboundedReceiver<Received>> {
+        let mut pending = self.pending.lock().unwrap();
+        let pending = match pending.as_mut() {
+            Some(pending) => pending,
+            None => {
+                let err_msg = "receiving task stopped";
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, err_msg));
+            }
+        };
+        if pending.contains_key(&key) {
+            let err_msg = "transmit timestamp in use by another request to the server";
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, err_msg));
+        }
+        let (sender, receiver) = mpsc::unbounded_channel();
+        pending.insert(key, sender);
+        Ok(receiver)
+    }
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        self.receiver.abort();
+    }
+}
+
+// Removes a request from the pending requests when the attempt completes or is cancelled.
+struct Deregister<'a> {
+    pending: &'a Pending,
+    key: Key,
+}
+
+impl<'a> Drop for Deregister<'a> {
+    fn drop(&mut self) {
+        if let Ok(mut pending) = self.pending.lock() {
+            if let Some(pending) = pending.as_mut() {
+                pending.remove(&self.key);
+            }
+        }
+    }
+}
+
+// Utility functions.
+
+// Receive datagrams on `socket` and hand each to the pending request to its source whose transmit
+// timestamp it echoes as its origin timestamp, along with the time of the clock of `config` at
+// which it arrived. Datagrams matching no pending request are dropped, and the exchange of the
+// request drops those that are not its response.
+//
+// The task stops on the first error other than an ICMP error reported for an earlier datagram,
+// failing the pending requests and any later ones.
+async fn receive(socket: Arc<UdpSocket>, pending: Pending, config: Client) {
+    let mut buf = [0u8; crate::RECV_BUFFER_SIZE];
+    loop {
+        let (len, src) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(ref err)
+                if err.kind() == io::ErrorKind::ConnectionReset
+                    || err.kind() == io::ErrorKind::ConnectionRefused =>
+            {
+                debug!("recv failed: {}", err);
+                continue;
+            }
+            Err(err) => {
+                warn!("stopped receiving responses: {}", err);
+                // Dropping the senders fails the requests awaiting them.
+                pending.lock().unwrap().take();
+                return;
+            }
+        };
+        let destination = config.now();
+        let origin = match PacketView::new(&buf[..len]) {
+            Ok(view) => view.origin_timestamp(),
+            Err(err) => {
+                debug!("dropping invalid response from {}: {}", src, err);
+                continue;
+            }
+        };
+        match pending.lock().unwrap().as_ref().and_then(|pending| pending.get(&(origin, src))) {
+            Some(sender) => {
+                let _ = sender.send((buf[..len].to_vec(), src, destination));
+            }
+            None => debug!("dropping unmatched response from {}", src),
+        }
+    }
+}
+
+// Await `future`, failing with `TimedOut` if it takes longer than `timeout`.
+async fn with_timeout<T, F>(timeout: Option<std::time::Duration>, future: F) -> io::Result<T>
+where
+    F: std::future::Future<Output = io::Result<T>>,
+{
+    match timeout {
+        Some(timeout) => match time::timeout(timeout, future).await {
+            Ok(result) => result,
+            Err(_) => {
+                let err_msg = "timed out waiting for the server";
+                Err(io::Error::new(io::ErrorKind::TimedOut, err_msg))
+            }
+        },
+        None => future.await,
+    }
+}
@@ -0,0 +1,252 @@
+//! Symmetric key authentication of NTP packets.
+//!
+//! A MAC is computed over the packet header and any extension fields and is appended to the packet
+//! together with the Key Identifier of the key that produced it. Both peers must share the key
+//! under the same Key Identifier.
+//!
+//! The legacy MD5 and SHA-1 digests described in RFC 5905 are computed over the key followed by
+//! the packet. AES-128-CMAC, as recommended by RFC 8573, is computed over the packet alone.
+//!
+//! A **Signer** protects the requests of a `client::Client`, which drops any response that is not
+//! signed with the same key.
+
+use aes::Aes128;
+use cmac::{Cmac, Mac as MacTrait};
+use md5::{Digest, Md5};
+use crate::exchange::Protection;
+use crate::protocol::{ExtendedPacket, Mac, Packet, ReadBytes, WriteBytes};
+use sha1::Sha1;
+use std::collections::HashMap;
+use std::{fmt, io};
+
+/// The algorithm used to compute the message digest of a packet.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum DigestAlgorithm {
+    /// MD5 over the key and the packet. Deprecated by RFC 8573.
+    Md5,
+    /// SHA-1 over the key and the packet. Deprecated by RFC 8573.
+    Sha1,
+    /// AES-CMAC with a 128-bit key, as recommended by RFC 8573.
+    AesCmac128,
+}
+
+/// A symmetric key shared with a peer.
+#[derive(Clone, Eq, PartialEq)]
+pub struct Key {
+    algorithm: DigestAlgorithm,
+    secret: Vec<u8>,
+}
+
+/// A set of symmetric keys indexed by their Key Identifier.
+///
+/// Key Identifier 0 is reserved for crypto-NAKs and cannot be used to sign packets.
+#[derive(Clone, Default)]
+pub struct KeyStore {
+    keys: HashMap<u32, Key>,
+}
+
+/// The **Protection** of requests signed with one key of a **KeyStore**, for use with
+/// `Client::request_protected`.
+#[derive(Debug)]
+pub struct Signer<'a> {
+    keys: &'a KeyStore,
+    key_id: u32,
+    response: Option<ExtendedPacket>,
+}
+
+// Inherent implementations.
+
+impl DigestAlgorithm {
+    /// The length of the message digest in octets.
+    pub fn digest_len(&self) -> usize {
+        match *self {
+            DigestAlgorithm::Md5 | DigestAlgorithm::AesCmac128 => 16,
+            DigestAlgorithm::Sha1 => 20,
+        }
+    }
+}
+
+impl Key {
+    /// Create a new **Key** for the given algorithm.
+    ///
+    /// Returns an error if `secret` is empty, or if it is not 16 octets long for AES-128-CMAC.
+    pub fn new(algorithm: DigestAlgorithm, secret: Vec<u8>) -> io::Result<Key> {
+        if secret.is_empty() {
+            let err_msg = "empty key";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err_msg));
+        }
+        if algorithm == DigestAlgorithm::AesCmac128 && secret.len() != 16 {
+            let err_msg = "AES-128-CMAC keys must be 16 octets";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err_msg));
+        }
+        Ok(Key { algorithm, secret })
+    }
+
+    /// The algorithm this key is used with.
+    pub fn algorithm(&self) -> DigestAlgorithm {
+        self.algorithm
+    }
+
+    /// Compute the message digest of `message` with this key.
+    pub fn digest(&self, message: &[u8]) -> Vec<u8> {
+        match self.algorithm {
+            DigestAlgorithm::Md5 => {
+                let mut hasher = Md5::new();
+                hasher.update(&self.secret);
+                hasher.update(message);
+                hasher.finalize().to_vec()
+            }
+            DigestAlgorithm::Sha1 => {
+                let mut hasher = Sha1::new();
+                hasher.update(&self.secret);
+                hasher.update(message);
+                hasher.finalize().to_vec()
+            }
+            DigestAlgorithm::AesCmac128 => {
+                let mut mac = <Cmac<Aes128> as MacTrait>::new_from_slice(&self.secret)
+                    .expect("key length is checked on construction");
+                mac.update(message);
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+}
+
+impl KeyStore {
+    /// Create an empty **KeyStore**.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert a key, returning the key previously stored under `key_id`, if any.
+    pub fn insert(&mut self, key_id: u32, key: Key) -> Option<Key> {
+        self.keys.insert(key_id, key)
+    }
+
+    /// Remove the key stored under `key_id`.
+    pub fn remove(&mut self, key_id: u32) -> Option<Key> {
+        self.keys.remove(&key_id)
+    }
+
+    /// The key stored under `key_id`.
+    pub fn get(&self, key_id: u32) -> Option<&Key> {
+        self.keys.get(&key_id)
+    }
+
+    /// Sign `packet` with the key stored under `key_id`, replacing any existing MAC.
+    pub fn sign(&self, key_id: u32, packet: &mut ExtendedPacket) -> io::Result<()> {
+        if key_id == 0 {
+            let err_msg = "key id 0 is reserved for crypto-NAKs";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err_msg));
+        }
+        let key = match self.get(key_id) {
+            Some(key) => key,
+            None => {
+                let err_msg = "unknown key id";
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, err_msg));
+            }
+        };
+        packet.mac = None;
+        let digest = key.digest(&packet.authenticated_bytes()?);
+        packet.mac = Some(Mac { key_id, digest });
+        Ok(())
+    }
+
+    /// Verify the MAC of `packet`, returning the Key Identifier it was signed with.
+    ///
+    /// Returns an error of kind `InvalidData` if the packet has no MAC, is a crypto-NAK, was
+    /// signed with an unknown key or if the digest does not match.
+    pub fn verify(&self, packet: &ExtendedPacket) -> io::Result<u32> {
+        let mac = match packet.mac {
+            Some(ref mac) if mac.is_crypto_nak() => {
+                let err_msg = "crypto-NAK received";
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+            }
+            Some(ref mac) => mac,
+            None => {
+                let err_msg = "packet is not authenticated";
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+            }
+        };
+        let key = match self.get(mac.key_id) {
+            Some(key) => key,
+            None => {
+                let err_msg = "packet signed with unknown key id";
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+            }
+        };
+        let expected = key.digest(&packet.authenticated_bytes()?);
+        if !constant_time_eq(&expected, &mac.digest) {
+            let err_msg = "MAC verification failed";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+        }
+        Ok(mac.key_id)
+    }
+}
+
+impl<'a> Signer<'a> {
+    /// Sign requests with the key stored in `keys` under `key_id`, and accept only responses
+    /// signed with the same key.
+    pub fn new(keys: &'a KeyStore, key_id: u32) -> Self {
+        Signer {
+            keys,
+            key_id,
+            response: None,
+        }
+    }
+
+    /// The last response whose MAC was verified, if any.
+    pub fn response(&self) -> Option<&ExtendedPacket> {
+        self.response.as_ref()
+    }
+
+    /// Consume the **Signer**, returning the last response whose MAC was verified, if any.
+    pub fn into_response(self) -> Option<ExtendedPacket> {
+        self.response
+    }
+}
+
+// Trait implementations.
+
+impl<'a> Protection for Signer<'a> {
+    fn protect(&mut self, request: &Packet) -> io::Result<Vec<u8>> {
+        let mut packet = ExtendedPacket::from(*request);
+        self.keys.sign(self.key_id, &mut packet)?;
+        let mut bytes = Vec::with_capacity(packet.packed_size_bytes());
+        bytes.write_bytes(&packet)?;
+        Ok(bytes)
+    }
+
+    fn verify(&mut self, bytes: &[u8], _: &Packet) -> io::Result<()> {
+        let packet: ExtendedPacket = (&bytes[..]).read_bytes()?;
+        if self.keys.verify(&packet)? != self.key_id {
+            let err_msg = "response signed with a different key";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+        }
+        self.response = Some(packet);
+        Ok(())
+    }
+}
+
+// Debug implementations.
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Key")
+            .field("algorithm", &self.algorithm)
+            .field("secret", &"<redacted>")
+            .finish()
+    }
+}
+
+impl fmt::Debug for KeyStore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.keys.keys()).finish()
+    }
+}
+
+// Utility functions.
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,154 @@
+//! Sampling a single server with a burst of requests.
+//!
+//! A single sample is at the mercy of queueing delays and lost packets. A **Burst** sends several
+//! requests to one server at a fixed spacing and keeps the sample with the lowest round-trip
+//! delay, which is the one least disturbed by queueing. The spread of the other samples around it
+//! is reported as the jitter of the burst.
+//!
+//! By default a burst is the iburst sent by the reference implementation to synchronize quickly
+//! on startup: 8 requests 2 seconds apart. A burst is cut short if the server responds with a
+//! RATE, DENY or RSTR kiss-o'-death, as the server is asking to be sent fewer requests or none at
+//! all.
+//!
+//! ```no_run
+//! use ntp::burst::Burst;
+//! use ntp::client::Client;
+//!
+//! let report = Burst::new(Client::new()).run("pool.ntp.org:123").unwrap();
+//! println!("offset: {} s, jitter: {} s", report.best.offset, report.jitter);
+//! ```
+
+use crate::client::{self, Client, ResponseError};
+use crate::measurement::Measurement;
+use crate::protocol::KissOfDeath;
+use crate::transport::{Transport, UdpTransport};
+use std::io;
+use std::net::ToSocketAddrs;
+use std::thread;
+use std::time::Duration;
+
+/// The number of requests sent in an iburst.
+pub const IBURST_COUNT: u32 = 8;
+
+/// The interval between the requests of an iburst.
+pub const IBURST_SPACING: Duration = Duration::from_secs(2);
+
+/// A burst of requests to a single server, configured by chaining its builder methods.
+#[derive(Clone, Debug)]
+pub struct Burst {
+    client: Client,
+    count: u32,
+    spacing: Duration,
+}
+
+/// The outcome of a **Burst**.
+#[derive(Clone, Debug)]
+pub struct BurstReport {
+    /// The sample with the lowest round-trip delay.
+    pub best: Measurement,
+    /// The root mean square difference between the offsets of the other samples and the best, in
+    /// seconds. Zero if only one sample was received.
+    pub jitter: f64,
+    /// Every valid sample received, in the order the requests were sent.
+    pub measurements: Vec<Measurement>,
+    /// The number of requests sent.
+    pub sent: u32,
+    /// The code of the kiss-o'-death that cut the burst short, if any.
+    pub kiss: Option<KissOfDeath>,
+}
+
+// Inherent implementations.
+
+impl Burst {
+    /// Create an iburst of `IBURST_COUNT` requests configured by `client`, `IBURST_SPACING`
+    /// apart.
+    pub fn new(client: Client) -> Self {
+        Burst {
+            client,
+            count: IBURST_COUNT,
+            spacing: IBURST_SPACING,
+        }
+    }
+
+    /// Set the number of requests sent.
+    pub fn count(mut self, count: u32) -> Self {
+        self.count = count;
+        self
+    }
+
+    /// Set the interval between requests.
+    pub fn spacing(mut self, spacing: Duration) -> Self {
+        self.spacing = spacing;
+        self
+    }
+
+    /// Send the burst to the ntp server at `addr`.
+    ///
+    ///   returns the error of the last request if no valid response is received. Each request is
+    ///   retried as configured on the client, except after a kiss-o'-death.
+    pub fn run<A: ToSocketAddrs>(&self, addr: A) -> io::Result<BurstReport> {
+        self.run_with(&mut UdpTransport::new(&self.client), addr)
+    }
+
+    /// Send the burst to the ntp server at `addr` over `transport`, as with
+    /// `Client::request_with`.
+    pub fn run_with<T, A>(&self, transport: &mut T, addr: A) -> io::Result<BurstReport>
+    where
+        T: Transport,
+        A: ToSocketAddrs,
+    {
+        let addr = client::resolve(addr)?;
+        let mut measurements = Vec::new();
+        let mut sent = 0;
+        let mut kiss = None;
+        let mut last_err = None;
+        for _ in 0..self.count {
+            if sent > 0 {
+                thread::sleep(self.spacing);
+            }
+            sent += 1;
+            match self.client.request_with(transport, addr) {
+                Ok(measurement) => measurements.push(measurement),
+                Err(err) => {
+                    let rejection = ResponseError::from_io_error(&err);
+                    debug!("burst request {} to {} failed: {}", sent, addr, err);
+                    last_err = Some(err);
+                    if let Some(ResponseError::KissOfDeath(code)) = rejection {
+                        if let KissOfDeath::Rate | KissOfDeath::Deny | KissOfDeath::Rstr = code {
+                            kiss = Some(code);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        let best = match measurements.iter().min_by(|a, b| a.delay.total_cmp(&b.delay)) {
+            Some(best) => *best,
+            None => {
+                return Err(last_err.unwrap_or_else(|| {
+                    let err_msg = "burst sends no requests";
+                    io::Error::new(io::ErrorKind::InvalidInput, err_msg)
+                }))
+            }
+        };
+        Ok(BurstReport {
+            best,
+            jitter: jitter(&best, &measurements),
+            measurements,
+            sent,
+            kiss,
+        })
+    }
+}
+
+// Utility functions.
+
+// The root mean square difference between the offsets of `measurements` other than `best` and
+// the offset of `best`.
+pub(crate) fn jitter(best: &Measurement, measurements: &[Measurement]) -> f64 {
+    if measurements.len() < 2 {
+        return 0.0;
+    }
+    let sum: f64 = measurements.iter().map(|m| (m.offset - best.offset).powi(2)).sum();
+    (sum / (measurements.len() - 1) as f64).sqrt()
+}
@@ -0,0 +1,477 @@
+//! A configurable, blocking NTP client.
+//!
+//! ```no_run
+//! extern crate ntp;
+//!
+//! use std::time::{Duration, Instant};
+//!
+//! fn main() {
+//!     let client = ntp::client::Client::new()
+//!         .timeout(Duration::from_secs(2))
+//!         .retries(3)
+//!         .max_delay(Duration::from_millis(500));
+//!     let measurement = client.request("pool.ntp.org:123").unwrap();
+//!     println!("offset: {} s, delay: {} s", measurement.offset, measurement.delay);
+//! }
+//! ```
+//!
+//! # Data minimization
+//!
+//! By default requests carry the local time in the transmit timestamp, revealing the state of the
+//! local clock to every server queried. With `Client::data_minimization` enabled, the client
+//! follows RFC 9109 and the NTP data minimization draft: the transmit timestamp is a random 64-bit
+//! cookie, every other header field except the version and mode is zero, each request is sent from
+//! a fresh, randomly chosen source port, and T1 is recorded locally.
+
+use crate::clock::{Clock, SystemClock};
+use crate::exchange::{Action, Exchange, Protection, Unprotected};
+use crate::measurement::Measurement;
+use crate::protocol::{
+    self, KissOfDeath, LeapIndicator, Mode, Packet, ReferenceIdentifier, ShortFormat, Stratum,
+    TimestampFormat, Version, MAXDIST, MAXSTRAT,
+};
+use rand::{self, Rng};
+use std::{error, fmt, io};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::transport::{Transport, UdpTransport};
+
+/// The default read and write timeout.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The default delay before the first retry, doubled for each subsequent retry.
+pub const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+
+// The range of dynamic ports from which source ports are chosen in data minimization mode.
+const DYNAMIC_PORTS: (u16, u16) = (49152, 65535);
+
+// The number of random source ports tried before giving up on binding a socket.
+const BIND_ATTEMPTS: u32 = 16;
+
+/// A blocking NTP client, configured by chaining its builder methods.
+///
+/// Each request is sent from a new socket. If no bind address is set, the socket is bound to the
+/// unspecified address of the same family as the server, so IPv6 servers are reachable from
+/// IPv6-only hosts.
+#[derive(Clone, Debug)]
+pub struct Client {
+    pub(crate) read_timeout: Option<Duration>,
+    pub(crate) write_timeout: Option<Duration>,
+    pub(crate) bind_addr: Option<SocketAddr>,
+    version: Version,
+    poll: i8,
+    precision: i8,
+    pub(crate) retries: u32,
+    pub(crate) backoff: Duration,
+    max_delay: Option<Duration>,
+    data_minimization: bool,
+    clock: Arc<dyn Clock + Send + Sync>,
+}
+
+impl Client {
+    /// Create a client with 5 second timeouts that sends NTPv4 requests and makes a single
+    /// attempt.
+    pub fn new() -> Self {
+        Client {
+            read_timeout: Some(DEFAULT_TIMEOUT),
+            write_timeout: Some(DEFAULT_TIMEOUT),
+            bind_addr: None,
+            version: Version::V4,
+            poll: 0,
+            precision: 0,
+            retries: 0,
+            backoff: DEFAULT_BACKOFF,
+            max_delay: None,
+            data_minimization: false,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Set both the read and the write timeout.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        self.read_timeout(Some(timeout)).write_timeout(Some(timeout))
+    }
+
+    /// Set the time to wait for a response to each attempt. `None` waits indefinitely.
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Set the time to wait for a request to be sent. `None` waits indefinitely.
+    pub fn write_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.write_timeout = timeout;
+        self
+    }
+
+    /// Bind the socket to `addr` instead of the unspecified address with an ephemeral port.
+    pub fn bind_addr(mut self, addr: SocketAddr) -> Self {
+        self.bind_addr = Some(addr);
+        self
+    }
+
+    /// Set the version sent in requests.
+    pub fn version(mut self, version: Version) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Set the poll exponent sent in requests.
+    pub fn poll(mut self, poll: i8) -> Self {
+        self.poll = poll;
+        self
+    }
+
+    /// Set the precision exponent sent in requests.
+    pub fn precision(mut self, precision: i8) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Set the number of times a request is retried after a failed attempt.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Set the delay before the first retry. The delay doubles with each further retry.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Reject responses whose round-trip delay exceeds `max_delay`.
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = Some(max_delay);
+        self
+    }
+
+    /// Send requests that reveal nothing about the local clock, as described in the module
+    /// documentation. The configured poll and precision are not sent in this mode.
+    pub fn data_minimization(mut self, enabled: bool) -> Self {
+        self.data_minimization = enabled;
+        self
+    }
+
+    /// Read the local time from `clock` rather than the system clock.
+    pub fn clock<C: Clock + Send + Sync + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Send a blocking request to the ntp server at `addr`, retrying as configured.
+    ///
+    ///   returns the error of the last attempt if the server cannot be reached, the response is
+    ///   invalid, or the response is rejected. A rejected response is reported as an `io::Error`
+    ///   of kind `InvalidData` wrapping a `ResponseError`.
+    pub fn request<A: ToSocketAddrs>(&self, addr: A) -> io::Result<Measurement> {
+        self.request_with(&mut UdpTransport::new(self), addr)
+    }
+
+    /// Send a blocking request to the ntp server at `addr` over `transport`, retrying as
+    /// configured.
+    ///
+    /// The bind address and write timeout are not applied, and in data minimization mode the
+    /// source port is left to the transport.
+    pub fn request_with<T, A>(&self, transport: &mut T, addr: A) -> io::Result<Measurement>
+    where
+        T: Transport,
+        A: ToSocketAddrs,
+    {
+        self.request_protected_with(transport, addr, Unprotected)
+    }
+
+    /// Send a blocking request to the ntp server at `addr` as with `request`, protecting it with
+    /// `protection`. Responses that `protection` cannot verify are dropped like spoofed ones.
+    pub fn request_protected<A, P>(&self, addr: A, protection: P) -> io::Result<Measurement>
+    where
+        A: ToSocketAddrs,
+        P: Protection,
+    {
+        self.request_protected_with(&mut UdpTransport::new(self), addr, protection)
+    }
+
+    /// Send a blocking request to the ntp server at `addr` over `transport` as with
+    /// `request_with`, protecting it with `protection`.
+    pub fn request_protected_with<T, A, P>(
+        &self,
+        transport: &mut T,
+        addr: A,
+        protection: P,
+    ) -> io::Result<Measurement>
+    where
+        T: Transport,
+        A: ToSocketAddrs,
+        P: Protection,
+    {
+        let addr = resolve(addr)?;
+        let now = Instant::now();
+        let mut exchange = Exchange::with_protection(self.clone(), addr, now, protection);
+        loop {
+            match exchange.poll(Instant::now(), self.now()) {
+                Action::Send { addr, bytes } => {
+                    if let Err(err) = transport.send_to(&bytes, addr) {
+                        exchange.fail(err, Instant::now());
+                    }
+                }
+                Action::Receive { deadline } => {
+                    let timeout = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+                    if timeout == Some(Duration::from_secs(0)) {
+                        continue;
+                    }
+                    let mut buf = [0u8; crate::RECV_BUFFER_SIZE];
+                    match transport.recv_from(&mut buf, timeout) {
+                        Ok((len, src)) => {
+                            let time = self.now();
+                            exchange.receive(src, &buf[..len], time, Instant::now());
+                        }
+                        // The exchange times out the attempt once the deadline has passed.
+                        Err(ref err)
+                            if err.kind() == io::ErrorKind::WouldBlock
+                                || err.kind() == io::ErrorKind::TimedOut => {}
+                        Err(err) => exchange.fail(err, Instant::now()),
+                    }
+                }
+                Action::Sleep { until } => {
+                    thread::sleep(until.saturating_duration_since(Instant::now()))
+                }
+                Action::Done(result) => return result,
+            }
+        }
+    }
+
+    // Bind a new socket to send a request to `addr` from.
+    pub(crate) fn bind_for(&self, addr: &SocketAddr) -> io::Result<UdpSocket> {
+        let sock = if self.data_minimization {
+            bind_random_port(self.bind_addr, addr)?
+        } else {
+            bind(self.bind_addr, addr)?
+        };
+        sock.set_write_timeout(self.write_timeout)?;
+        Ok(sock)
+    }
+
+    // Compute and check the measurement of the `response` to `request`, sent at `origin` and
+    // received at `destination`.
+    pub(crate) fn measure(
+        &self,
+        request: &Packet,
+        origin: TimestampFormat,
+        response: &Packet,
+        destination: TimestampFormat,
+    ) -> Result<Measurement, ResponseError> {
+        let measurement = if self.data_minimization {
+            Measurement::with_origin(response, origin, destination)
+        } else {
+            Measurement::new(response, destination)
+        };
+        validate(&measurement, request.transmit_timestamp)?;
+
+        if let Some(max_delay) = self.max_delay {
+            if measurement.delay > max_delay.as_secs_f64() {
+                return Err(ResponseError::ExcessiveDelay(measurement.delay));
+            }
+        }
+        Ok(measurement)
+    }
+
+    // The current time of the local clock.
+    pub(crate) fn now(&self) -> TimestampFormat {
+        self.clock.now()
+    }
+
+    // Create a request sent at the local time `time`.
+    pub(crate) fn request_packet_at(&self, time: TimestampFormat) -> Packet {
+        if self.data_minimization {
+            let cookie: u64 = rand::thread_rng().gen();
+            return Packet {
+                leap_indicator: LeapIndicator::NoWarning,
+                version: self.version,
+                mode: Mode::Client,
+                stratum: Stratum::UNSPECIFIED,
+                poll: 0,
+                precision: 0,
+                root_delay: ShortFormat::default(),
+                root_dispersion: ShortFormat::default(),
+                reference_id: ReferenceIdentifier::SecondaryOrClient([0; 4]),
+                reference_timestamp: TimestampFormat::default(),
+                origin_timestamp: TimestampFormat::default(),
+                receive_timestamp: TimestampFormat::default(),
+                transmit_timestamp: TimestampFormat {
+                    seconds: (cookie >> 32) as u32,
+                    fraction: cookie as u32,
+                },
+            };
+        }
+        Packet {
+            leap_indicator: LeapIndicator::default(),
+            version: self.version,
+            mode: Mode::Client,
+            stratum: Stratum::UNSPECIFIED,
+            poll: self.poll,
+            precision: self.precision,
+            root_delay: ShortFormat::default(),
+            root_dispersion: ShortFormat::default(),
+            reference_id: ReferenceIdentifier::PrimarySource(protocol::PrimarySource::Null),
+            reference_timestamp: TimestampFormat::default(),
+            origin_timestamp: TimestampFormat::default(),
+            receive_timestamp: TimestampFormat::default(),
+            transmit_timestamp: time,
+        }
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Client::new()
+    }
+}
+
+/// The reason a response was rejected by the client.
+///
+/// Converts into an `io::Error` of kind `InvalidData`, and may be recovered from it with
+/// `ResponseError::from_io_error`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResponseError {
+    /// The response arrived from an address other than the one the request was sent to.
+    UnexpectedSource(SocketAddr),
+    /// The response mode is not `Mode::Server`.
+    UnexpectedMode(Mode),
+    /// The origin timestamp does not echo the transmit timestamp of the request.
+    OriginMismatch,
+    /// The transmit timestamp is zero.
+    ZeroTransmit,
+    /// The server replied with a kiss-o'-death packet carrying the given code.
+    KissOfDeath(KissOfDeath),
+    /// The leap indicator is `LeapIndicator::Unknown`, so the server clock is unsynchronized.
+    Unsynchronized,
+    /// The stratum is not in the range 1 to 15.
+    InvalidStratum(Stratum),
+    /// The root distance, in seconds, is not below `MAXDIST`.
+    ExcessiveRootDistance(f64),
+    /// The round-trip delay, in seconds, exceeds the configured maximum.
+    ExcessiveDelay(f64),
+}
+
+impl ResponseError {
+    /// Recover a **ResponseError** from an `io::Error` returned by a request.
+    pub fn from_io_error(err: &io::Error) -> Option<Self> {
+        err.get_ref().and_then(|err| err.downcast_ref::<ResponseError>()).cloned()
+    }
+}
+
+impl fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ResponseError::UnexpectedSource(addr) => {
+                write!(f, "response from unexpected source {}", addr)
+            }
+            ResponseError::UnexpectedMode(mode) => {
+                write!(f, "response with unexpected mode {:?}", mode)
+            }
+            ResponseError::OriginMismatch => {
+                write!(f, "response origin timestamp does not match the request")
+            }
+            ResponseError::ZeroTransmit => write!(f, "response transmit timestamp is zero"),
+            ResponseError::KissOfDeath(code) => write!(f, "kiss-o'-death response {}", code),
+            ResponseError::Unsynchronized => write!(f, "server clock is unsynchronized"),
+            ResponseError::InvalidStratum(stratum) => {
+                write!(f, "response with invalid stratum {}", stratum.0)
+            }
+            ResponseError::ExcessiveRootDistance(distance) => {
+                write!(f, "root distance of {} s exceeds the maximum", distance)
+            }
+            ResponseError::ExcessiveDelay(delay) => {
+                write!(f, "round-trip delay of {} s exceeds the maximum", delay)
+            }
+        }
+    }
+}
+
+impl error::Error for ResponseError {}
+
+impl From<ResponseError> for io::Error {
+    fn from(err: ResponseError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// Apply the packet sanity tests of RFC 5905 to the response of a request sent with the transmit
+/// timestamp `transmit`.
+pub fn validate(
+    measurement: &Measurement,
+    transmit: TimestampFormat,
+) -> Result<(), ResponseError> {
+    let packet = &measurement.packet;
+    if packet.mode != Mode::Server {
+        return Err(ResponseError::UnexpectedMode(packet.mode));
+    }
+    if packet.origin_timestamp != transmit {
+        return Err(ResponseError::OriginMismatch);
+    }
+    if packet.transmit_timestamp == TimestampFormat::default() {
+        return Err(ResponseError::ZeroTransmit);
+    }
+    if packet.stratum.is_kiss_of_death() {
+        if let ReferenceIdentifier::KissOfDeath(code) = packet.reference_id {
+            return Err(ResponseError::KissOfDeath(code));
+        }
+    }
+    if packet.leap_indicator == LeapIndicator::Unknown {
+        return Err(ResponseError::Unsynchronized);
+    }
+    if packet.stratum.0 == 0 || packet.stratum.0 >= MAXSTRAT {
+        return Err(ResponseError::InvalidStratum(packet.stratum));
+    }
+    if measurement.root_distance >= MAXDIST as f64 {
+        return Err(ResponseError::ExcessiveRootDistance(measurement.root_distance));
+    }
+    Ok(())
+}
+
+// Utility functions.
+
+/// Resolve `addr` to its first socket address.
+pub(crate) fn resolve<A: ToSocketAddrs>(addr: A) -> io::Result<SocketAddr> {
+    match addr.to_socket_addrs()?.next() {
+        Some(addr) => Ok(addr),
+        None => {
+            let err_msg = "address resolved to no socket addresses";
+            Err(io::Error::new(io::ErrorKind::InvalidInput, err_msg))
+        }
+    }
+}
+
+/// Bind a UDP socket to `bind_addr`, or to the unspecified address of the same family as `peer`.
+pub(crate) fn bind(bind_addr: Option<SocketAddr>, peer: &SocketAddr) -> io::Result<UdpSocket> {
+    let bind_addr = bind_addr.unwrap_or_else(|| unspecified(peer));
+    UdpSocket::bind(bind_addr)
+}
+
+/// As `bind`, but choosing a random dynamic port unless `bind_addr` specifies one.
+fn bind_random_port(bind_addr: Option<SocketAddr>, peer: &SocketAddr) -> io::Result<UdpSocket> {
+    let mut bind_addr = bind_addr.unwrap_or_else(|| unspecified(peer));
+    if bind_addr.port() != 0 {
+        return UdpSocket::bind(bind_addr);
+    }
+    let mut rng = rand::thread_rng();
+    for _ in 0..BIND_ATTEMPTS {
+        bind_addr.set_port(rng.gen_range(DYNAMIC_PORTS.0..=DYNAMIC_PORTS.1));
+        match UdpSocket::bind(bind_addr) {
+            Err(ref err) if err.kind() == io::ErrorKind::AddrInUse => continue,
+            result => return result,
+        }
+    }
+    let err_msg = "no free source port found";
+    Err(io::Error::new(io::ErrorKind::AddrInUse, err_msg))
+}
+
+// The unspecified address, with an ephemeral port, of the same family as `peer`.
+pub(crate) fn unspecified(peer: &SocketAddr) -> SocketAddr {
+    match *peer {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    }
+}
@@ -0,0 +1,189 @@
+//! Sources of the local time used to timestamp requests and responses.
+//!
+//! The client takes the transmit time T1 and the destination time T4 of an exchange from a
+//! **Clock**, as does the NTS server for its receive and transmit timestamps. Besides the system
+//! clock, a **ManualClock** and a **SimulatedClock** let tests and simulations control time
+//! exactly. The **ManualClock** is only available on targets with 64-bit atomics.
+//!
+//! ```
+//! use ntp::clock::{Clock, ManualClock, SimulatedClock};
+//! use ntp::protocol::TimestampFormat;
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! let manual = Arc::new(ManualClock::new(TimestampFormat { seconds: 100, fraction: 0 }));
+//! let fast = SimulatedClock::new(manual.clone()).offset(0.5).drift(50e-6);
+//! manual.advance(Duration::from_secs(1000));
+//! assert_eq!(manual.now(), TimestampFormat { seconds: 1100, fraction: 0 });
+//!
+//! // Half a second ahead, plus 50 ms of drift.
+//! let ahead = ntp::measurement::difference(fast.now(), manual.now());
+//! assert!((ahead - 0.55).abs() < 1e-6);
+//! ```
+
+use crate::measurement::{difference, timestamp_from_u64, timestamp_to_u64, TWO_POW_32};
+use crate::protocol::TimestampFormat;
+use core::fmt;
+#[cfg(target_has_atomic = "64")]
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::sync::Arc;
+#[cfg(feature = "std")]
+use crate::unix_time::Instant;
+
+/// A source of the current local time.
+pub trait Clock {
+    /// The current time as an NTP timestamp.
+    fn now(&self) -> TimestampFormat;
+}
+
+/// The system clock, read with `std::time::SystemTime`.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+/// The system clock, read with `clock_gettime(CLOCK_REALTIME)` at full nanosecond precision.
+#[cfg(all(feature = "std", unix))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RealtimeClock;
+
+/// A clock that only moves when it is set or advanced.
+#[cfg(target_has_atomic = "64")]
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    time: AtomicU64,
+}
+
+/// A clock running at a fixed offset from a `reference` clock, and drifting away from it at a
+/// fixed frequency error.
+#[derive(Clone, Debug)]
+pub struct SimulatedClock<C> {
+    reference: C,
+    epoch: TimestampFormat,
+    offset: f64,
+    drift: f64,
+}
+
+// Inherent implementations.
+
+#[cfg(target_has_atomic = "64")]
+impl ManualClock {
+    /// Create a clock stopped at `time`.
+    pub fn new(time: TimestampFormat) -> Self {
+        ManualClock {
+            time: AtomicU64::new(timestamp_to_u64(time)),
+        }
+    }
+
+    /// Set the clock to `time`.
+    pub fn set(&self, time: TimestampFormat) {
+        self.time.store(timestamp_to_u64(time), Ordering::SeqCst);
+    }
+
+    /// Move the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        let fraction = ((duration.subsec_nanos() as u64) << 32) / 1_000_000_000;
+        let step = (duration.as_secs() << 32).wrapping_add(fraction);
+        self.time.fetch_add(step, Ordering::SeqCst);
+    }
+}
+
+impl<C: Clock> SimulatedClock<C> {
+    /// Create a clock that agrees with `reference`.
+    pub fn new(reference: C) -> Self {
+        let epoch = reference.now();
+        SimulatedClock {
+            reference,
+            epoch,
+            offset: 0.0,
+            drift: 0.0,
+        }
+    }
+
+    /// Run `offset` seconds ahead of the reference clock, or behind it if negative.
+    pub fn offset(mut self, offset: f64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Gain `drift` seconds per second on the reference clock from the time of creation, so
+    /// `50e-6` runs 50 PPM fast.
+    pub fn drift(mut self, drift: f64) -> Self {
+        self.drift = drift;
+        self
+    }
+}
+
+// Clock implementations.
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn now(&self) -> TimestampFormat {
+        Instant::now().into()
+    }
+}
+
+#[cfg(all(feature = "std", unix))]
+impl Clock for RealtimeClock {
+    fn now(&self) -> TimestampFormat {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // Safe as `ts` is a valid timespec to write to, and CLOCK_REALTIME is always supported.
+        let ret = unsafe { libc::clock_gettime(libc::CLOCK_REALTIME, &mut ts) };
+        if ret != 0 {
+            panic!("clock_gettime failed: {}", std::io::Error::last_os_error());
+        }
+        // `time_t` is only 32 bits wide on some targets.
+        #[allow(clippy::unnecessary_cast)]
+        let (secs, nanos) = (ts.tv_sec as i64, ts.tv_nsec as i32);
+        // An **Instant** before the epoch counts both of its components down from it.
+        let instant = match secs {
+            secs if secs < 0 && nanos > 0 => Instant::new(secs + 1, nanos - 1_000_000_000),
+            secs => Instant::new(secs, nanos),
+        };
+        instant.into()
+    }
+}
+
+#[cfg(target_has_atomic = "64")]
+impl Clock for ManualClock {
+    fn now(&self) -> TimestampFormat {
+        timestamp_from_u64(self.time.load(Ordering::SeqCst))
+    }
+}
+
+impl<C: Clock> Clock for SimulatedClock<C> {
+    fn now(&self) -> TimestampFormat {
+        let now = self.reference.now();
+        let elapsed = difference(now, self.epoch);
+        add_secs(now, self.offset + self.drift * elapsed)
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now(&self) -> TimestampFormat {
+        (**self).now()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> TimestampFormat {
+        (**self).now()
+    }
+}
+
+impl fmt::Debug for dyn Clock + Send + Sync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Clock(..)")
+    }
+}
+
+// Utility functions.
+
+fn add_secs(t: TimestampFormat, secs: f64) -> TimestampFormat {
+    timestamp_from_u64(timestamp_to_u64(t).wrapping_add((secs * TWO_POW_32) as i64 as u64))
+}
@@ -0,0 +1,123 @@
+//! The cluster and combine algorithms of RFC 5905 sections 11.2.2 and 11.2.3.
+//!
+//! The truechimers found by clock selection are ranked by merit, their stratum first and their
+//! root distance second. The cluster algorithm then repeatedly discards the survivor whose offset
+//! is furthest from the others, measured by its selection jitter, until no survivor stands out
+//! more than the jitter of the best peer or only `NMIN` survivors remain. The best survivor
+//! becomes the system peer.
+//!
+//! The combine algorithm averages the offsets of the survivors weighted by the inverse of their
+//! root distance to give the system offset. The system jitter combines the jitter of the system
+//! peer with the spread of the survivors around it.
+//!
+//! `mitigate` runs selection, clustering and combining in turn.
+//!
+//! ```
+//! use ntp::cluster::mitigate;
+//! use ntp::protocol::Stratum;
+//! use ntp::selection::Candidate;
+//!
+//! let candidate = |offset, root_distance, stratum| Candidate {
+//!     offset,
+//!     jitter: 0.001,
+//!     root_distance,
+//!     stratum,
+//! };
+//! let candidates = [
+//!     candidate(0.010, 0.02, Stratum::SECONDARY_MIN),
+//!     candidate(0.012, 0.04, Stratum::PRIMARY),
+//!     candidate(0.500, 0.05, Stratum::PRIMARY),
+//! ];
+//! let estimate = mitigate(&candidates).unwrap();
+//! assert_eq!(estimate.system_peer, 1);
+//! assert!(estimate.offset > 0.010 && estimate.offset < 0.012);
+//! ```
+
+use crate::protocol::{MAXDIST, MINDISP, NMIN};
+use crate::selection::{self, Candidate, Selection};
+
+/// The system variables resulting from mitigation. All durations are in seconds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SystemEstimate {
+    /// The combined offset of the survivors.
+    pub offset: f64,
+    /// The system jitter.
+    pub jitter: f64,
+    /// The index of the system peer among the candidates.
+    pub system_peer: usize,
+    /// The indices of the survivors among the candidates, best first.
+    pub survivors: Vec<usize>,
+}
+
+// Utility functions.
+
+/// Select, cluster and combine `candidates` into a system estimate.
+///
+/// Returns `None` if no majority of the candidates agree.
+pub fn mitigate(candidates: &[Candidate]) -> Option<SystemEstimate> {
+    let selection = selection::select(candidates)?;
+    let survivors = cluster(candidates, &selection);
+    combine(candidates, &survivors)
+}
+
+/// Prune the truechimers of `selection` among `candidates` to the survivors, returning their
+/// indices ordered by merit, best first.
+pub fn cluster(candidates: &[Candidate], selection: &Selection) -> Vec<usize> {
+    let merit = |i: usize| {
+        let candidate = &candidates[i];
+        candidate.stratum.0 as f64 * MAXDIST as f64 + candidate.root_distance
+    };
+    let mut survivors: Vec<usize> = selection.truechimers().collect();
+    survivors.sort_by(|&a, &b| merit(a).total_cmp(&merit(b)));
+
+    while survivors.len() > NMIN {
+        // The survivor furthest from the others, by the root mean square of the differences
+        // between its offset and theirs.
+        let (worst, max_jitter) = survivors
+            .iter()
+            .enumerate()
+            .map(|(i, &p)| {
+                let sum: f64 = survivors
+                    .iter()
+                    .map(|&q| (candidates[p].offset - candidates[q].offset).powi(2))
+                    .sum();
+                (i, (sum / (survivors.len() - 1) as f64).sqrt())
+            })
+            .fold((0, f64::NEG_INFINITY), |max, next| if next.1 > max.1 { next } else { max });
+        let min_jitter = survivors
+            .iter()
+            .map(|&p| candidates[p].jitter)
+            .fold(f64::INFINITY, f64::min);
+        if max_jitter < min_jitter {
+            break;
+        }
+        survivors.remove(worst);
+    }
+    survivors
+}
+
+/// Combine the `survivors` among `candidates`, ordered best first, into a system estimate.
+///
+/// Root distances below `MINDISP` are taken to be `MINDISP`, so that no survivor has an infinite
+/// weight. Returns `None` if there are no survivors.
+pub fn combine(candidates: &[Candidate], survivors: &[usize]) -> Option<SystemEstimate> {
+    let system_peer = *survivors.first()?;
+    let peer = &candidates[system_peer];
+    let mut weight = 0.0;
+    let mut offset = 0.0;
+    let mut spread = 0.0;
+    for &i in survivors {
+        let candidate = &candidates[i];
+        let distance = candidate.root_distance.max(MINDISP);
+        weight += 1.0 / distance;
+        offset += candidate.offset / distance;
+        spread += (candidate.offset - peer.offset).powi(2) / distance;
+    }
+    let selection_jitter = (spread / weight).sqrt();
+    Some(SystemEstimate {
+        offset: offset / weight,
+        jitter: (peer.jitter.powi(2) + selection_jitter.powi(2)).sqrt(),
+        system_peer,
+        survivors: survivors.to_vec(),
+    })
+}
@@ -0,0 +1,285 @@
+//! The clock discipline algorithm of RFC 5905 section 12.
+//!
+//! A **ClockDiscipline** is a hybrid phase-locked and frequency-locked loop that turns the system
+//! offsets produced by mitigation into corrections of the local clock. It never touches the clock
+//! itself. Each update instead returns the **Command**s a driver should carry out, so the same
+//! discipline can steer the operating system clock or a simulated one.
+//!
+//! The discipline moves between the states of the reference implementation:
+//!
+//! - `State::Nset` - no frequency estimate exists. The first offset starts a direct measurement of
+//!   the frequency, or the clock is stepped if the offset exceeds `STEPT`.
+//! - `State::Fset` - the frequency was initialized, for example from a file saved by an earlier
+//!   run. The first offset is corrected straight away.
+//! - `State::Freq` - the frequency is being measured. Offsets are ignored until `WATCH` seconds
+//!   have passed, after which the frequency is set from the offset accumulated meanwhile.
+//! - `State::Sync` - the clock is synchronized, and offsets are slewed out while the loops refine
+//!   the frequency.
+//! - `State::Spik` - an offset above `STEPT` arrived while synchronized. Further large offsets are
+//!   ignored as spikes until they persist for `WATCH` seconds, when the clock is stepped.
+//!
+//! Offsets above `PANICT` are never corrected. The poll exponent grows towards `MAXPOLL` while the
+//! offsets stay within `PGATE` times the clock jitter, and shrinks towards `MINPOLL` otherwise.
+//!
+//! ```
+//! use ntp::discipline::{ClockDiscipline, Command, State, Update};
+//! use ntp::protocol::TimestampFormat;
+//!
+//! let at = |seconds| TimestampFormat { seconds, fraction: 0 };
+//! let mut discipline = ClockDiscipline::new();
+//! assert_eq!(discipline.update(0.010, at(0)), Update::Ignore);
+//! assert_eq!(discipline.state(), State::Freq);
+//!
+//! // After the stepout threshold the frequency is set from the offset accumulated since.
+//! match discipline.update(0.019, at(1000)) {
+//!     Update::Adjust(commands) => {
+//!         assert_eq!(commands[0], Command::Slew(0.019));
+//!         assert!(matches!(commands[1], Command::SetFrequency(f) if (f - 9e-6).abs() < 1e-7));
+//!     }
+//!     update => panic!("unexpected {:?}", update),
+//! }
+//! assert_eq!(discipline.state(), State::Sync);
+//! ```
+
+use crate::measurement::difference;
+use crate::protocol::{
+    TimestampFormat, ALLAN, AVG, FLL, LIMIT, MAXFREQ, MAXPOLL, MINPOLL, PANICT, PGATE, PLL,
+    STEPT, WATCH,
+};
+
+/// The state of a **ClockDiscipline**.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    /// The frequency has not been set.
+    Nset,
+    /// The frequency has been set from a saved value, but the time has not been corrected.
+    Fset,
+    /// A possible spike is being held until the stepout threshold.
+    Spik,
+    /// The frequency is being measured.
+    Freq,
+    /// The clock is synchronized.
+    Sync,
+}
+
+/// A correction of the local clock.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    /// Step the clock forward by the given number of seconds, or backward if negative.
+    Step(f64),
+    /// Gradually slew the clock forward by the given number of seconds, or backward if negative.
+    Slew(f64),
+    /// Set the frequency correction of the clock to the given number of seconds per second.
+    SetFrequency(f64),
+}
+
+/// The response of a **ClockDiscipline** to an offset.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Update {
+    /// The offset was held as a suspected spike or while the frequency is being measured.
+    Ignore,
+    /// The offset exceeds `PANICT`. The clock should be set by hand instead.
+    Panic,
+    /// Carry out the commands in order.
+    Adjust(Vec<Command>),
+}
+
+/// The clock discipline of the local clock.
+#[derive(Clone, Debug)]
+pub struct ClockDiscipline {
+    state: State,
+    poll: u8,
+    precision: i8,
+    // The epoch of the last update the state was reset at.
+    updated: Option<TimestampFormat>,
+    // The offset of the last update accepted and the offset at the start of the current state.
+    offset: f64,
+    base: f64,
+    frequency: f64,
+    jitter: f64,
+    wander: f64,
+    // The poll-adjust counter, between -LIMIT and LIMIT.
+    count: i32,
+}
+
+// Inherent implementations.
+
+impl ClockDiscipline {
+    /// Create a discipline without a frequency estimate, polling at `MINPOLL`.
+    pub fn new() -> Self {
+        ClockDiscipline {
+            state: State::Nset,
+            poll: MINPOLL,
+            precision: -20,
+            updated: None,
+            offset: 0.0,
+            base: 0.0,
+            frequency: 0.0,
+            jitter: 0.0,
+            wander: 0.0,
+            count: 0,
+        }
+    }
+
+    /// Create a discipline starting from the frequency correction `frequency` in seconds per
+    /// second, such as one saved by an earlier run.
+    pub fn with_frequency(frequency: f64) -> Self {
+        ClockDiscipline {
+            state: State::Fset,
+            frequency: frequency.clamp(-MAXFREQ, MAXFREQ),
+            ..ClockDiscipline::new()
+        }
+    }
+
+    /// Set the precision exponent of the local clock, the lower bound of the clock jitter.
+    pub fn precision(mut self, precision: i8) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// The current state.
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// The poll exponent the system should poll its peers at, between `MINPOLL` and `MAXPOLL`.
+    pub fn poll(&self) -> u8 {
+        self.poll
+    }
+
+    /// The current frequency correction in seconds per second.
+    pub fn frequency(&self) -> f64 {
+        self.frequency
+    }
+
+    /// The clock jitter, the exponentially weighted root mean square of the differences between
+    /// successive offsets, in seconds.
+    pub fn jitter(&self) -> f64 {
+        self.jitter
+    }
+
+    /// The clock wander, the exponentially weighted root mean square of the frequency
+    /// corrections, in seconds per second.
+    pub fn wander(&self) -> f64 {
+        self.wander
+    }
+
+    /// Discipline the clock with the system `offset` in seconds, measured at the local time
+    /// `epoch`.
+    pub fn update(&mut self, offset: f64, epoch: TimestampFormat) -> Update {
+        if offset.abs() > PANICT {
+            return Update::Panic;
+        }
+        let mu = self.updated.map_or(0.0, |updated| difference(epoch, updated));
+        let mut frequency = 0.0;
+        let mut commands = Vec::new();
+        if offset.abs() > STEPT {
+            match self.state {
+                // Ignore the first outlier while synchronized.
+                State::Sync => {
+                    self.state = State::Spik;
+                    return Update::Ignore;
+                }
+                // Ignore outliers until the stepout threshold, then step the time, correcting the
+                // frequency by the apparent offset accumulated while measuring it.
+                State::Freq => {
+                    if mu < WATCH {
+                        return Update::Ignore;
+                    }
+                    frequency = (offset - self.base - self.offset) / mu;
+                }
+                State::Spik => {
+                    if mu < WATCH {
+                        return Update::Ignore;
+                    }
+                }
+                State::Nset | State::Fset => (),
+            }
+            debug!("stepping the clock by {} s", offset);
+            commands.push(Command::Step(offset));
+            self.count = 0;
+            self.poll = MINPOLL;
+            if self.state == State::Nset {
+                self.reset(State::Freq, 0.0, epoch);
+                return Update::Adjust(commands);
+            }
+            self.reset(State::Sync, 0.0, epoch);
+        } else {
+            let jitter = (offset - self.offset).abs().max(f64::from(self.precision).exp2());
+            let squared = self.jitter.powi(2);
+            self.jitter = (squared + (jitter.powi(2) - squared) / AVG).sqrt();
+            match self.state {
+                // Start measuring the frequency directly.
+                State::Nset => {
+                    self.offset = offset;
+                    self.reset(State::Freq, offset, epoch);
+                    return Update::Ignore;
+                }
+                // Correct the time, but leave the saved frequency until the next update.
+                State::Fset => self.offset = offset,
+                State::Freq => {
+                    if mu < WATCH {
+                        return Update::Ignore;
+                    }
+                    frequency = (offset - self.base - self.offset) / mu;
+                }
+                // The frequency-locked loop contributes from half the Allan intercept, and the
+                // phase-locked loop integrates over at most the poll interval.
+                State::Sync | State::Spik => {
+                    let interval = f64::from(self.poll).exp2();
+                    if interval > ALLAN / 2.0 {
+                        let gain = f64::from(FLL - self.poll).max(AVG);
+                        frequency += (offset - self.offset) / (mu.max(ALLAN) * gain);
+                    }
+                    let time_constant = 4.0 * PLL * interval;
+                    frequency += offset * mu.min(interval) / time_constant.powi(2);
+                }
+            }
+            self.reset(State::Sync, offset, epoch);
+            commands.push(Command::Slew(offset));
+        }
+
+        frequency += self.frequency;
+        self.frequency = frequency.clamp(-MAXFREQ, MAXFREQ);
+        let squared = self.wander.powi(2);
+        self.wander = (squared + (frequency.powi(2) - squared) / AVG).sqrt();
+        commands.push(Command::SetFrequency(self.frequency));
+
+        // Lengthen the poll interval while the offsets are small compared to the jitter, and
+        // shorten it otherwise, with some hysteresis.
+        if self.offset.abs() < PGATE * self.jitter {
+            self.count += i32::from(self.poll);
+            if self.count > LIMIT {
+                self.count = LIMIT;
+                if self.poll < MAXPOLL {
+                    self.count = 0;
+                    self.poll += 1;
+                }
+            }
+        } else {
+            self.count -= i32::from(self.poll) << 1;
+            if self.count < -LIMIT {
+                self.count = -LIMIT;
+                if self.poll > MINPOLL {
+                    self.count = 0;
+                    self.poll -= 1;
+                }
+            }
+        }
+        Update::Adjust(commands)
+    }
+
+    // Enter `state` at the offset `offset` and local time `epoch`.
+    fn reset(&mut self, state: State, offset: f64, epoch: TimestampFormat) {
+        self.state = state;
+        self.base = offset - self.offset;
+        self.offset = offset;
+        self.updated = Some(epoch);
+    }
+}
+
+impl Default for ClockDiscipline {
+    fn default() -> Self {
+        ClockDiscipline::new()
+    }
+}
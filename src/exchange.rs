@@ -0,0 +1,300 @@
+//! The request and response logic of the client as a state machine that performs no I/O.
+//!
+//! An **Exchange** covers every attempt of a single request to one server. It is driven by
+//! polling it for the next **Action** and feeding back what happened:
+//!
+//! - `Action::Send` - send the datagram to the server, then report a failure with `fail`.
+//! - `Action::Receive` - wait for a datagram until the deadline, then pass it to `receive`.
+//!   Datagrams that are not a response to the request, such as spoofed or stale packets, are
+//!   dropped and the exchange keeps waiting.
+//! - `Action::Sleep` - wait until the deadline before retrying.
+//! - `Action::Done` - the exchange finished with the given result.
+//!
+//! Time is always supplied by the caller: a monotonic `Instant` for deadlines, and the NTP
+//! timestamp of the local clock for the transmit and receive times of datagrams. This makes the
+//! exchange usable with any event loop, and deterministic to test. The blocking `Client` and the
+//! tokio `AsyncClient` are drivers over it.
+//!
+//! An exchange created with `Exchange::with_protection` hands each request to a **Protection**,
+//! such as the symmetric key MAC of `auth::Signer` or NTS, to be signed before it is sent, and
+//! drops responses that the protection cannot verify.
+//!
+//! ```no_run
+//! use ntp::client::Client;
+//! use ntp::clock::{Clock, SystemClock};
+//! use ntp::exchange::{Action, Exchange};
+//! use std::net::UdpSocket;
+//! use std::time::Instant;
+//!
+//! let addr = "203.0.113.1:123".parse().unwrap();
+//! let sock = UdpSocket::bind("0.0.0.0:0").unwrap();
+//! let mut exchange = Exchange::new(Client::new(), addr, Instant::now());
+//! let measurement = loop {
+//!     match exchange.poll(Instant::now(), SystemClock.now()) {
+//!         Action::Send { addr, bytes } => {
+//!             sock.send_to(&bytes, addr).unwrap();
+//!         }
+//!         Action::Receive { deadline } => {
+//!             let timeout = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+//!             sock.set_read_timeout(timeout.filter(|t| !t.is_zero())).unwrap();
+//!             let mut buf = [0u8; 1024];
+//!             if let Ok((len, src)) = sock.recv_from(&mut buf) {
+//!                 let time = SystemClock.now();
+//!                 exchange.receive(src, &buf[..len], time, Instant::now());
+//!             }
+//!         }
+//!         Action::Sleep { until } => {
+//!             std::thread::sleep(until.saturating_duration_since(Instant::now()));
+//!         }
+//!         Action::Done(result) => break result.unwrap(),
+//!     }
+//! };
+//! println!("offset: {} s", measurement.offset);
+//! ```
+
+use crate::client::{Client, ResponseError};
+use crate::measurement::Measurement;
+use crate::protocol::{ConstPackedSizeBytes, Packet, TimestampFormat};
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// What the driver of an **Exchange** should do next.
+#[derive(Debug)]
+pub enum Action {
+    /// Send `bytes` to `addr`.
+    Send { addr: SocketAddr, bytes: Vec<u8> },
+    /// Wait for a response until `deadline`, or indefinitely if there is none.
+    Receive { deadline: Option<Instant> },
+    /// Wait until `until` before sending the next attempt.
+    Sleep { until: Instant },
+    /// The exchange is finished. Polling again returns an error.
+    Done(io::Result<Measurement>),
+}
+
+/// Authentication of the requests and responses of an **Exchange** beyond the sanity checks of
+/// the packet header.
+pub trait Protection {
+    /// Encode the request header `request` of a new attempt, along with the extension fields or
+    /// MAC that protect it.
+    fn protect(&mut self, request: &Packet) -> io::Result<Vec<u8>>;
+
+    /// Verify that the datagram `bytes`, whose header `response` passed the sanity checks, is an
+    /// authentic response to the last protected request. Datagrams that fail are dropped.
+    fn verify(&mut self, bytes: &[u8], response: &Packet) -> io::Result<()>;
+}
+
+/// The **Protection** of plain requests, which sends the packet header alone and accepts every
+/// response.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Unprotected;
+
+/// The state of a request to a single server, including any retries.
+#[derive(Debug)]
+pub struct Exchange<P = Unprotected> {
+    config: Client,
+    addr: SocketAddr,
+    protection: P,
+    attempt: u32,
+    backoff: Duration,
+    // Why the last datagram dropped during the current attempt was not a response to it.
+    dropped: Option<io::Error>,
+    state: State,
+}
+
+#[derive(Debug)]
+enum State {
+    // The next attempt is to be sent at the given time.
+    Ready(Instant),
+    // The request was sent at `origin` and a response is expected before `deadline`.
+    Waiting {
+        request: Packet,
+        origin: TimestampFormat,
+        deadline: Option<Instant>,
+    },
+    Done(io::Result<Measurement>),
+    Finished,
+}
+
+// Inherent implementations.
+
+impl Exchange {
+    /// Start an exchange with the server at `addr`, configured by `config`. The first attempt is
+    /// sent on the first poll.
+    pub fn new(config: Client, addr: SocketAddr, now: Instant) -> Self {
+        Exchange::with_protection(config, addr, now, Unprotected)
+    }
+}
+
+impl<P: Protection> Exchange<P> {
+    /// Start an exchange as with `Exchange::new`, protecting each request with `protection`.
+    pub fn with_protection(config: Client, addr: SocketAddr, now: Instant, protection: P) -> Self {
+        let backoff = config.backoff;
+        Exchange {
+            config,
+            addr,
+            protection,
+            attempt: 0,
+            backoff,
+            dropped: None,
+            state: State::Ready(now),
+        }
+    }
+
+    /// The address of the server.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The transmit timestamp of the request awaiting a response, if any. A response must echo it
+    /// as its origin timestamp.
+    pub fn transmit_timestamp(&self) -> Option<TimestampFormat> {
+        match self.state {
+            State::Waiting { ref request, .. } => Some(request.transmit_timestamp),
+            _ => None,
+        }
+    }
+
+    /// Whether or not the exchange has finished.
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, State::Done(_) | State::Finished)
+    }
+
+    /// Return the next action, given the monotonic time `now` and the local clock `time`.
+    pub fn poll(&mut self, now: Instant, time: TimestampFormat) -> Action {
+        match self.state {
+            State::Ready(at) if now < at => return Action::Sleep { until: at },
+            State::Ready(_) => {
+                let request = self.config.request_packet_at(time);
+                let bytes = match self.protection.protect(&request) {
+                    Ok(bytes) => bytes,
+                    // Protecting the request again would fail the same way.
+                    Err(err) => {
+                        self.state = State::Done(Err(err));
+                        return self.poll(now, time);
+                    }
+                };
+                // A deadline too far away to represent is never reached.
+                let timeout = self.config.read_timeout;
+                let deadline = timeout.and_then(|timeout| now.checked_add(timeout));
+                self.dropped = None;
+                self.state = State::Waiting {
+                    request,
+                    origin: time,
+                    deadline,
+                };
+                return Action::Send {
+                    addr: self.addr,
+                    bytes,
+                };
+            }
+            State::Waiting { deadline, .. } => match deadline {
+                Some(deadline) if now >= deadline => {
+                    let err = self.dropped.take().unwrap_or_else(|| {
+                        let err_msg = "timed out waiting for the server";
+                        io::Error::new(io::ErrorKind::TimedOut, err_msg)
+                    });
+                    self.fail(err, now);
+                    return self.poll(now, time);
+                }
+                _ => return Action::Receive { deadline },
+            },
+            State::Done(_) | State::Finished => (),
+        }
+        match std::mem::replace(&mut self.state, State::Finished) {
+            State::Done(result) => Action::Done(result),
+            _ => {
+                let err_msg = "exchange already finished";
+                Action::Done(Err(io::Error::other(err_msg)))
+            }
+        }
+    }
+
+    /// Handle the datagram `bytes` received from `src` at the local clock `time` and monotonic
+    /// time `now`. Datagrams received while no response is expected are ignored.
+    ///
+    /// A datagram from another source, one that cannot be decoded, one that is not a server
+    /// response echoing the transmit timestamp of the request, or one that the protection cannot
+    /// verify is dropped, and the exchange keeps waiting for the response until the deadline. If
+    /// none arrives, the attempt fails with the reason the last such datagram was dropped.
+    pub fn receive(&mut self, src: SocketAddr, bytes: &[u8], time: TimestampFormat, now: Instant) {
+        let (request, origin) = match self.state {
+            State::Waiting {
+                request, origin, ..
+            } => (request, origin),
+            _ => return,
+        };
+        if src != self.addr {
+            return self.drop_datagram(src, ResponseError::UnexpectedSource(src).into());
+        }
+        let response = match Packet::decode(bytes) {
+            Ok(response) => response,
+            Err(err) => return self.drop_datagram(src, err.into()),
+        };
+        let result = self.config.measure(&request, origin, &response, time);
+        if let Err(err @ ResponseError::UnexpectedMode(_))
+        | Err(err @ ResponseError::OriginMismatch)
+        | Err(err @ ResponseError::ZeroTransmit) = result
+        {
+            return self.drop_datagram(src, err.into());
+        }
+        if let Err(err) = self.protection.verify(bytes, &response) {
+            return self.drop_datagram(src, err);
+        }
+        match result {
+            Ok(measurement) => self.state = State::Done(Ok(measurement)),
+            Err(err) => self.fail(err.into(), now),
+        }
+    }
+
+    /// Fail the current attempt with `err`, such as an error sending the request. The request is
+    /// retried after the backoff if any retries remain, unless `err` is a kiss-o'-death
+    /// rejection, which RFC 5905 forbids retrying, or the backoff is too long for the time of the
+    /// retry to be represented.
+    pub fn fail(&mut self, err: io::Error, now: Instant) {
+        if self.is_done() {
+            return;
+        }
+        let rejection = ResponseError::from_io_error(&err);
+        let kiss = matches!(rejection, Some(ResponseError::KissOfDeath(_)));
+        let retry_at = match now.checked_add(self.backoff) {
+            Some(at) if !kiss && self.attempt < self.config.retries => at,
+            _ => {
+                self.state = State::Done(Err(err));
+                return;
+            }
+        };
+        debug!("attempt {} failed: {}", self.attempt, err);
+        self.state = State::Ready(retry_at);
+        self.backoff = self.backoff.saturating_mul(2);
+        self.attempt += 1;
+    }
+
+    // Drop the datagram from `src` that is not a response to the request, for the reason `err`.
+    fn drop_datagram(&mut self, src: SocketAddr, err: io::Error) {
+        debug!("dropping datagram from {}: {}", src, err);
+        self.dropped = Some(err);
+    }
+}
+
+impl Protection for Unprotected {
+    fn protect(&mut self, request: &Packet) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0u8; Packet::PACKED_SIZE_BYTES];
+        request.encode(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn verify(&mut self, _: &[u8], _: &Packet) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<P: Protection + ?Sized> Protection for &mut P {
+    fn protect(&mut self, request: &Packet) -> io::Result<Vec<u8>> {
+        (**self).protect(request)
+    }
+
+    fn verify(&mut self, bytes: &[u8], response: &Packet) -> io::Result<()> {
+        (**self).verify(bytes, response)
+    }
+}
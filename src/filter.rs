@@ -0,0 +1,209 @@
+//! The clock filter algorithm of RFC 5905 section 10.
+//!
+//! Each peer keeps a **ClockFilter**: a shift register of the last `NSTAGE` samples of the offset,
+//! delay and dispersion measured with the peer, along with the local time at which each was
+//! taken. The dispersion of each sample grows at the frequency tolerance `TOLERANCE` (PHI) as it
+//! ages. On every update the sample with the lowest delay is chosen to represent the peer, as the
+//! one least disturbed by queueing in the network, and the spread of the other offsets around it
+//! gives the peer jitter.
+//!
+//! A sample is only passed on to clock selection once, and never if it is older than the last one
+//! passed on. An offset that jumps further than `SGATE` times the last jitter from the last one is
+//! treated as a popcorn spike and suppressed, unless the last offset is more than two poll
+//! intervals old.
+//!
+//! ```
+//! use ntp::filter::{ClockFilter, Sample};
+//! use ntp::protocol::TimestampFormat;
+//!
+//! let mut filter = ClockFilter::new();
+//! let samples = [(0.010, 0.050), (0.002, 0.020), (0.020, 0.080)];
+//! for (i, &(offset, delay)) in samples.iter().enumerate() {
+//!     let epoch = TimestampFormat { seconds: 64 * i as u32, fraction: 0 };
+//!     filter.update(Sample { offset, delay, dispersion: 0.0, epoch }, 6);
+//! }
+//! // The sample with the lowest delay represents the peer.
+//! assert_eq!(filter.statistics().unwrap().offset, 0.002);
+//! ```
+
+use crate::measurement::{difference, Measurement};
+use crate::protocol::{TimestampFormat, MAXDISP, NSTAGE, SGATE, TOLERANCE};
+
+/// A single sample of the clock of a peer. All durations are in seconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    /// The offset of the peer clock relative to the local clock.
+    pub offset: f64,
+    /// The round-trip delay to the peer.
+    pub delay: f64,
+    /// The dispersion of the sample when it was taken.
+    pub dispersion: f64,
+    /// The local time at which the sample was taken.
+    pub epoch: TimestampFormat,
+}
+
+/// The statistics of a peer computed by the clock filter. All durations are in seconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeerStatistics {
+    /// The offset of the chosen sample.
+    pub offset: f64,
+    /// The delay of the chosen sample.
+    pub delay: f64,
+    /// The peer dispersion, the sum of the dispersions of the samples sorted by delay, weighted
+    /// by a half, a quarter, and so on.
+    pub dispersion: f64,
+    /// The root mean square difference between the offsets of the other samples and the chosen
+    /// sample, but no less than the precision of the local clock.
+    pub jitter: f64,
+    /// The local time at which the chosen sample was taken.
+    pub epoch: TimestampFormat,
+}
+
+/// The clock filter of a single peer.
+#[derive(Clone, Debug)]
+pub struct ClockFilter {
+    // The most recent sample first.
+    stages: [Sample; NSTAGE],
+    // The local time of the last update, up to which the dispersion of the stages has been aged.
+    updated: Option<TimestampFormat>,
+    precision: i8,
+    statistics: Option<PeerStatistics>,
+    // The number of valid samples the jitter of the statistics was computed from.
+    jitter_samples: usize,
+}
+
+// Inherent implementations.
+
+impl Sample {
+    // The empty stage the filter is initialized with, which is never chosen.
+    const EMPTY: Sample = Sample {
+        offset: 0.0,
+        delay: MAXDISP,
+        dispersion: MAXDISP,
+        epoch: TimestampFormat {
+            seconds: 0,
+            fraction: 0,
+        },
+    };
+}
+
+impl ClockFilter {
+    /// Create an empty filter, assuming a local clock precision of one microsecond.
+    pub fn new() -> Self {
+        ClockFilter {
+            stages: [Sample::EMPTY; NSTAGE],
+            updated: None,
+            precision: -20,
+            statistics: None,
+            jitter_samples: 0,
+        }
+    }
+
+    /// Set the precision exponent of the local clock, the lower bound of the peer jitter.
+    pub fn precision(mut self, precision: i8) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// The statistics of the last sample passed on by `update`, if any.
+    pub fn statistics(&self) -> Option<PeerStatistics> {
+        self.statistics
+    }
+
+    /// The samples in the filter, the most recent first, with their dispersion aged to the time of
+    /// the last update. Stages not yet filled have a dispersion of `MAXDISP`.
+    pub fn samples(&self) -> &[Sample; NSTAGE] {
+        &self.stages
+    }
+
+    /// Shift `sample` into the filter, given the system poll exponent `poll`.
+    ///
+    /// Returns the new peer statistics if the sample with the lowest delay is newer than the last
+    /// one passed on and is not a popcorn spike, in which case they should be passed on to clock
+    /// selection. Otherwise the statistics are left unchanged and `None` is returned.
+    pub fn update(&mut self, sample: Sample, poll: i8) -> Option<PeerStatistics> {
+        let elapsed = self.updated.map_or(0.0, |updated| difference(sample.epoch, updated));
+        for stage in self.stages.iter_mut() {
+            stage.dispersion = (stage.dispersion + TOLERANCE * elapsed).min(MAXDISP);
+        }
+        self.stages.rotate_right(1);
+        self.stages[0] = sample;
+        self.updated = Some(sample.epoch);
+
+        // Sort by delay, with the samples whose dispersion has reached MAXDISP last.
+        let mut sorted = self.stages;
+        sorted.sort_by(|a, b| {
+            let key = |s: &Sample| (s.dispersion >= MAXDISP, s.delay);
+            let (a, b) = (key(a), key(b));
+            a.0.cmp(&b.0).then(a.1.total_cmp(&b.1))
+        });
+        let valid = sorted.iter().filter(|s| s.dispersion < MAXDISP).count();
+        if valid == 0 {
+            return None;
+        }
+        let best = sorted[0];
+        let mut dispersion = 0.0;
+        let mut weight = 0.5;
+        for stage in &sorted {
+            dispersion += stage.dispersion * weight;
+            weight /= 2.0;
+        }
+        let mut jitter = 0.0;
+        for stage in &sorted[1..valid] {
+            jitter += (stage.offset - best.offset).powi(2);
+        }
+        if valid > 1 {
+            jitter = (jitter / (valid - 1) as f64).sqrt();
+        }
+        let statistics = PeerStatistics {
+            offset: best.offset,
+            delay: best.delay,
+            dispersion,
+            jitter: jitter.max(f64::from(self.precision).exp2()),
+            epoch: best.epoch,
+        };
+
+        if let Some(last) = self.statistics {
+            // Use each sample only once, and never a sample older than the last one used.
+            let age = difference(best.epoch, last.epoch);
+            if age <= 0.0 {
+                return None;
+            }
+            // Suppress popcorn spikes unless the last sample used is getting old. The jitter is
+            // taken from before the update, as a spike with the lowest delay inflates the new one,
+            // and only once it was computed from more than a single sample.
+            let spike = self.jitter_samples > 1
+                && (best.offset - last.offset).abs() > SGATE * last.jitter;
+            if spike && age < 2.0 * f64::from(poll).exp2() {
+                debug!("suppressed popcorn spike of {} s", best.offset - last.offset);
+                return None;
+            }
+        }
+        self.statistics = Some(statistics);
+        self.jitter_samples = valid;
+        Some(statistics)
+    }
+}
+
+impl Default for ClockFilter {
+    fn default() -> Self {
+        ClockFilter::new()
+    }
+}
+
+impl<'a> From<&'a Measurement> for Sample {
+    fn from(measurement: &'a Measurement) -> Self {
+        Sample {
+            offset: measurement.offset,
+            delay: measurement.delay,
+            dispersion: measurement.dispersion,
+            epoch: measurement.t4,
+        }
+    }
+}
+
+impl From<Measurement> for Sample {
+    fn from(measurement: Measurement) -> Self {
+        Sample::from(&measurement)
+    }
+}
@@ -3,7 +3,7 @@
 Shows how to use the ntp library to fetch the current time according
 to the requested ntp server.
 
-```rust
+```no_run
 extern crate chrono;
 extern crate ntp;
 
@@ -20,95 +20,121 @@ fn main() {
 */
 
 #![recursion_limit = "1024"]
+#![cfg_attr(not(feature = "std"), no_std)]
 
-#[macro_use]
-extern crate custom_derive;
-#[macro_use]
-extern crate conv;
+#[cfg(feature = "std")]
+extern crate core;
+#[cfg(feature = "std")]
 #[macro_use]
 extern crate log;
+#[cfg(feature = "std")]
+extern crate aes;
+#[cfg(feature = "nts")]
+extern crate aes_siv;
 extern crate byteorder;
+#[cfg(feature = "std")]
+extern crate cmac;
+#[cfg(feature = "std")]
+extern crate libc;
+extern crate md5;
+#[cfg(feature = "std")]
+extern crate rand;
+#[cfg(feature = "nts")]
+extern crate rustls;
+#[cfg(feature = "std")]
+extern crate sha1;
 
-use protocol::{ReadBytes, ConstPackedSizeBytes, WriteBytes};
+#[cfg(feature = "std")]
 use std::io;
-use std::net::{ToSocketAddrs, UdpSocket};
-use std::time::Duration;
+#[cfg(feature = "std")]
+use std::net::ToSocketAddrs;
 
+#[cfg(feature = "tokio")]
+pub mod async_client;
+#[cfg(feature = "std")]
+pub mod auth;
+#[cfg(feature = "std")]
+pub mod burst;
+#[cfg(feature = "std")]
+pub mod client;
+pub mod clock;
+#[cfg(feature = "std")]
+pub mod cluster;
+#[cfg(feature = "std")]
+pub mod discipline;
+#[cfg(feature = "std")]
+pub mod exchange;
+#[cfg(feature = "std")]
+pub mod filter;
+pub mod measurement;
+#[cfg(feature = "nts")]
+pub mod nts;
+#[cfg(feature = "std")]
+pub mod peer;
 pub mod protocol;
+#[cfg(feature = "std")]
+pub mod query;
+#[cfg(feature = "std")]
+pub mod selection;
+#[cfg(feature = "std")]
+pub mod transport;
 pub mod unix_time;
 
-/// Send a blocking request to an ntp server with a hardcoded 5 second timeout.
+/// The maximum size of a received datagram, large enough for any extension fields and MAC.
+#[cfg(feature = "std")]
+const RECV_BUFFER_SIZE: usize = 1024;
+
+/// Send a blocking request to an ntp server with a 5 second timeout.
 ///
 ///   `addr` can be any valid socket address
 ///   returns an error if the server cannot be reached or the response is invalid.
 ///
-///   **TODO**: remove hardcoded timeout
+/// Use a `client::Client` to configure timeouts, retries and the request fields.
+#[cfg(feature = "std")]
 pub fn request<A: ToSocketAddrs>(addr: A) -> io::Result<protocol::Packet> {
-    // Create a packet for requesting from an NTP server as a client.
-    let mut packet = {
-        let leap_indicator = protocol::LeapIndicator::default();
-        let version = protocol::Version::V4;
-        let mode = protocol::Mode::Client;
-        let poll = 0;
-        let precision = 0;
-        let root_delay = protocol::ShortFormat::default();
-        let root_dispersion = protocol::ShortFormat::default();
-        let transmit_timestamp = unix_time::Instant::now().into();
-        let stratum = protocol::Stratum::UNSPECIFIED;
-        let src = protocol::PrimarySource::Null;
-        let reference_id = protocol::ReferenceIdentifier::PrimarySource(src);
-        let reference_timestamp = protocol::TimestampFormat::default();
-        let receive_timestamp = protocol::TimestampFormat::default();
-        let origin_timestamp = protocol::TimestampFormat::default();
-        protocol::Packet {
-            leap_indicator,
-            version,
-            mode,
-            stratum,
-            poll,
-            precision,
-            root_delay,
-            root_dispersion,
-            reference_id,
-            reference_timestamp,
-            origin_timestamp,
-            receive_timestamp,
-            transmit_timestamp,
-        }
-    };
-
-    // Write the packet to a slice of bytes.
-    let mut bytes = [0u8; protocol::Packet::PACKED_SIZE_BYTES];
-    (&mut bytes[..]).write_bytes(&packet)?;
-
-    // Create the socket from which we will send the packet.
-    let sock = UdpSocket::bind("0.0.0.0:0")?;
-    sock.set_read_timeout(Some(Duration::from_secs(5)))?;
-    sock.set_write_timeout(Some(Duration::from_secs(5)))?;
-
-    // Send the data.
-    let sz = sock.send_to(&bytes, addr)?;
-    debug!("{:?}", sock.local_addr());
-    debug!("sent: {}", sz);
-
-    // Receive the response.
-    let res = sock.recv(&mut bytes[..])?;
-    debug!("recv: {:?}", res);
-    debug!("{:?}", &bytes[..]);
+    client::Client::new().request(addr).map(|measurement| measurement.packet)
+}
 
-    // Read the received packet from the response.
-    packet = (&bytes[..]).read_bytes()?;
-    Ok(packet)
+/// Send a blocking request to an ntp server with a 5 second timeout, returning the timestamps of
+/// the exchange together with the computed offset and delay.
+///
+///   `addr` can be any valid socket address
+///   returns an error if the server cannot be reached or the response is invalid.
+#[cfg(feature = "std")]
+pub fn request_measurement<A: ToSocketAddrs>(addr: A) -> io::Result<measurement::Measurement> {
+    client::Client::new().request(addr)
 }
 
-#[test]
-fn test_request_ntp_org() {
-    let res = request("0.pool.ntp.org:123");
-    let _ = res.expect("Failed to get a ntp packet from ntp.org");
+/// Query each of the ntp servers `addrs` with 4 samples a second apart, rejecting falsetickers
+/// and combining the offsets of the rest.
+///
+///   returns an error if an address cannot be resolved. See `query::Query` for the details and
+///   for configuring the query.
+#[cfg(feature = "std")]
+pub fn query_servers<I>(addrs: I) -> io::Result<query::QueryReport>
+where
+    I: IntoIterator,
+    I::Item: ToSocketAddrs,
+{
+    query::Query::default().run(addrs)
 }
 
-#[test]
-fn test_request_google() {
-    let res = request("time.google.com:123");
-    let _ = res.expect("Failed to get a ntp packet from time.google.com");
+/// Send a blocking request signed with the key `key_id` from `keys` to an ntp server with a 5
+/// second timeout, returning the response.
+///
+///   `addr` can be any valid socket address
+///   returns an error if the server cannot be reached or the response is invalid. Responses that
+///   are not signed with the same key are dropped.
+///
+/// Use a `client::Client` with an `auth::Signer` to configure timeouts, retries and the request
+/// fields.
+#[cfg(feature = "std")]
+pub fn request_authenticated<A: ToSocketAddrs>(
+    addr: A,
+    keys: &auth::KeyStore,
+    key_id: u32,
+) -> io::Result<protocol::ExtendedPacket> {
+    let mut signer = auth::Signer::new(keys, key_id);
+    client::Client::new().request_protected(addr, &mut signer)?;
+    Ok(signer.into_response().expect("the response of a measurement is verified"))
 }
@@ -0,0 +1,140 @@
+//! The on-wire measurement of a single request and response exchange.
+//!
+//! An NTP exchange yields four timestamps:
+//!
+//! ```ignore
+//!        Server      T2      T3
+//!                   /          \
+//!                  /            \
+//!        Client  T1              T4
+//! ```
+//!
+//! - **T1** - the client transmit timestamp, echoed by the server as the origin timestamp.
+//! - **T2** - the server receive timestamp.
+//! - **T3** - the server transmit timestamp.
+//! - **T4** - the client destination timestamp, taken when the response arrives.
+//!
+//! From these the clock offset and round-trip delay are computed following RFC 5905:
+//!
+//! ```ignore
+//! offset = ((T2 - T1) + (T3 - T4)) / 2
+//! delay = (T4 - T1) - (T3 - T2)
+//! ```
+
+use crate::protocol::{Packet, ShortFormat, TimestampFormat, MINDISP, TOLERANCE};
+
+// The scale of the NTP timestamp fraction.
+pub(crate) const TWO_POW_32: f64 = 4_294_967_296.0;
+
+/// The timestamps of an NTP exchange along with the statistics derived from them.
+///
+/// All durations are in seconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Measurement {
+    /// The response received from the server.
+    pub packet: Packet,
+    /// Time at the client when the request departed for the server.
+    pub t1: TimestampFormat,
+    /// Time at the server when the request arrived from the client.
+    pub t2: TimestampFormat,
+    /// Time at the server when the response left for the client.
+    pub t3: TimestampFormat,
+    /// Time at the client when the response arrived from the server.
+    pub t4: TimestampFormat,
+    /// The offset of the server clock relative to the local clock.
+    pub offset: f64,
+    /// The round-trip delay between the client and the server.
+    pub delay: f64,
+    /// The maximum error of the measurement due to the server precision and the frequency
+    /// tolerance of the local clock over the round trip.
+    pub dispersion: f64,
+    /// The maximum error of the server clock relative to the primary reference source.
+    pub root_distance: f64,
+}
+
+impl Measurement {
+    /// Compute the measurement from the server response `packet` and the time `destination` at
+    /// which it was received.
+    ///
+    /// T1 is taken from the origin timestamp of the response, so the response should be checked
+    /// to echo the request transmit timestamp first, as `client::validate` does.
+    pub fn new(packet: &Packet, destination: TimestampFormat) -> Self {
+        Measurement::with_origin(packet, packet.origin_timestamp, destination)
+    }
+
+    /// Compute the measurement from the server response `packet`, the time `origin` at which the
+    /// request was sent and the time `destination` at which the response was received.
+    ///
+    /// This is used when the request carried a random transmit cookie in place of the local time.
+    pub fn with_origin(
+        packet: &Packet,
+        origin: TimestampFormat,
+        destination: TimestampFormat,
+    ) -> Self {
+        let t1 = origin;
+        let t2 = packet.receive_timestamp;
+        let t3 = packet.transmit_timestamp;
+        let t4 = destination;
+        let offset = (difference(t2, t1) + difference(t3, t4)) / 2.0;
+        let delay = difference(t4, t1) - difference(t3, t2);
+        let dispersion = log2d(packet.precision) + TOLERANCE * difference(t4, t1);
+        let root_delay = short_to_secs(packet.root_delay);
+        let root_dispersion = short_to_secs(packet.root_dispersion);
+        let root_distance = (root_delay + delay).max(MINDISP) / 2.0 + root_dispersion + dispersion;
+        Measurement {
+            packet: *packet,
+            t1,
+            t2,
+            t3,
+            t4,
+            offset,
+            delay,
+            dispersion,
+            root_distance,
+        }
+    }
+}
+
+// Utility functions.
+
+/// The difference `a - b` between two timestamps in seconds.
+///
+/// The difference is computed modulo 2^64 in fixed point before converting to floating point, so
+/// it is correct across an era boundary as long as the timestamps are within 68 years of each
+/// other.
+pub fn difference(a: TimestampFormat, b: TimestampFormat) -> f64 {
+    let diff = timestamp_to_u64(a).wrapping_sub(timestamp_to_u64(b)) as i64;
+    diff as f64 / TWO_POW_32
+}
+
+// The timestamp `t` as a 64-bit fixed-point number of seconds.
+pub(crate) fn timestamp_to_u64(t: TimestampFormat) -> u64 {
+    (t.seconds as u64) << 32 | t.fraction as u64
+}
+
+// The timestamp of the 64-bit fixed-point number of seconds `t`.
+pub(crate) fn timestamp_from_u64(t: u64) -> TimestampFormat {
+    TimestampFormat {
+        seconds: (t >> 32) as u32,
+        fraction: t as u32,
+    }
+}
+
+pub(crate) fn short_to_secs(s: ShortFormat) -> f64 {
+    s.seconds as f64 + s.fraction as f64 / 65_536.0
+}
+
+// Convert a log2 exponent, such as a poll interval or precision, to seconds.
+fn log2d(exponent: i8) -> f64 {
+    let mut secs = 1.0;
+    if exponent >= 0 {
+        for _ in 0..exponent {
+            secs *= 2.0;
+        }
+    } else {
+        for _ in exponent..0 {
+            secs /= 2.0;
+        }
+    }
+    secs
+}
@@ -0,0 +1,327 @@
+//! The NTS Key Establishment protocol (NTS-KE).
+//!
+//! NTS-KE runs over TLS 1.3 and consists of a single request and response, each a sequence of
+//! records terminated by an End of Message record.
+//!
+//! ### Record Layout
+//!
+//! ```ignore
+//!  0                   1                   2                   3
+//!  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |C|         Record Type         |          Body Length          |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |                                                               |
+//! ~                           Record Body                         ~
+//! |                                                               |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! ```
+
+use byteorder::{ReadBytesExt, WriteBytesExt, BE};
+use crate::client::Client;
+use crate::nts::{Session, AEAD_AES_SIV_CMAC_256, ALPN_NTSKE, PROTOCOL_NTPV4};
+use crate::protocol::{ReadBytes, ReadFromBytes, WriteBytes, WriteToBytes};
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, StreamOwned};
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Record type of the End of Message record.
+pub const END_OF_MESSAGE: u16 = 0;
+/// Record type of the NTS Next Protocol Negotiation record.
+pub const NEXT_PROTOCOL: u16 = 1;
+/// Record type of the Error record.
+pub const ERROR: u16 = 2;
+/// Record type of the Warning record.
+pub const WARNING: u16 = 3;
+/// Record type of the AEAD Algorithm Negotiation record.
+pub const AEAD_ALGORITHM: u16 = 4;
+/// Record type of the New Cookie for NTPv4 record.
+pub const NEW_COOKIE: u16 = 5;
+/// Record type of the NTPv4 Server Negotiation record.
+pub const SERVER: u16 = 6;
+/// Record type of the NTPv4 Port Negotiation record.
+pub const PORT: u16 = 7;
+
+/// Error code sent in response to an unrecognized critical record.
+pub const ERROR_UNRECOGNIZED_CRITICAL_RECORD: u16 = 0;
+/// Error code sent in response to a malformed request.
+pub const ERROR_BAD_REQUEST: u16 = 1;
+/// Error code sent when the server is unable to process a request.
+pub const ERROR_INTERNAL_SERVER_ERROR: u16 = 2;
+
+/// The set bit indicating that a record is critical.
+const CRITICAL_BIT: u16 = 0x8000;
+
+/// A single NTS-KE record.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Record {
+    /// Terminates a request or response.
+    EndOfMessage,
+    /// The protocol IDs offered by the client, or the single protocol selected by the server.
+    NextProtocol(Vec<u16>),
+    /// The request could not be processed. Carries one of the `ERROR_*` codes.
+    Error(u16),
+    /// A non-fatal condition, carrying a warning code.
+    Warning(u16),
+    /// The AEAD algorithms offered by the client, or the single algorithm selected by the server.
+    AeadAlgorithm(Vec<u16>),
+    /// An opaque cookie for use in NTP requests.
+    NewCookie(Vec<u8>),
+    /// The host name or IP address of the NTP server to use, if different from the NTS-KE server.
+    Server(String),
+    /// The UDP port of the NTP server to use, if not 123.
+    Port(u16),
+    /// A record of a type not known to this implementation.
+    Unknown {
+        critical: bool,
+        record_type: u16,
+        body: Vec<u8>,
+    },
+}
+
+// Inherent implementations.
+
+impl Record {
+    /// The record type without the critical bit.
+    pub fn record_type(&self) -> u16 {
+        match *self {
+            Record::EndOfMessage => END_OF_MESSAGE,
+            Record::NextProtocol(_) => NEXT_PROTOCOL,
+            Record::Error(_) => ERROR,
+            Record::Warning(_) => WARNING,
+            Record::AeadAlgorithm(_) => AEAD_ALGORITHM,
+            Record::NewCookie(_) => NEW_COOKIE,
+            Record::Server(_) => SERVER,
+            Record::Port(_) => PORT,
+            Record::Unknown { record_type, .. } => record_type,
+        }
+    }
+
+    /// Whether or not the record is sent with the critical bit set.
+    pub fn is_critical(&self) -> bool {
+        match *self {
+            Record::EndOfMessage |
+            Record::NextProtocol(_) |
+            Record::Error(_) |
+            Record::AeadAlgorithm(_) => true,
+            Record::Unknown { critical, .. } => critical,
+            _ => false,
+        }
+    }
+
+    fn body(&self) -> Vec<u8> {
+        fn u16s(values: &[u16]) -> Vec<u8> {
+            let mut body = Vec::with_capacity(values.len() * 2);
+            for &value in values {
+                body.write_u16::<BE>(value).expect("writing to a Vec cannot fail");
+            }
+            body
+        }
+        match *self {
+            Record::EndOfMessage => vec![],
+            Record::NextProtocol(ref ids) | Record::AeadAlgorithm(ref ids) => u16s(ids),
+            Record::Error(code) | Record::Warning(code) | Record::Port(code) => u16s(&[code]),
+            Record::NewCookie(ref cookie) => cookie.clone(),
+            Record::Server(ref server) => server.as_bytes().to_vec(),
+            Record::Unknown { ref body, .. } => body.clone(),
+        }
+    }
+}
+
+// Writer implementations.
+
+impl WriteToBytes for Record {
+    fn write_to_bytes<W: WriteBytesExt>(&self, mut writer: W) -> io::Result<()> {
+        let body = self.body();
+        if body.len() > u16::MAX as usize {
+            let err_msg = "NTS-KE record body too long";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err_msg));
+        }
+        let critical = if self.is_critical() { CRITICAL_BIT } else { 0 };
+        writer.write_u16::<BE>(critical | self.record_type())?;
+        writer.write_u16::<BE>(body.len() as u16)?;
+        writer.write_all(&body)?;
+        Ok(())
+    }
+}
+
+// Reader implementations.
+
+impl ReadFromBytes for Record {
+    fn read_from_bytes<R: ReadBytesExt>(mut reader: R) -> io::Result<Self> {
+        let type_and_critical = reader.read_u16::<BE>()?;
+        let critical = type_and_critical & CRITICAL_BIT != 0;
+        let record_type = type_and_critical & !CRITICAL_BIT;
+        let mut body = vec![0u8; reader.read_u16::<BE>()? as usize];
+        reader.read_exact(&mut body)?;
+
+        let u16s = |body: &[u8]| -> io::Result<Vec<u16>> {
+            if !body.len().is_multiple_of(2) {
+                let err_msg = "NTS-KE record body is not a list of 16-bit values";
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+            }
+            Ok(body.chunks(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect())
+        };
+        let u16_value = |body: &[u8]| -> io::Result<u16> {
+            match u16s(body)?[..] {
+                [value] => Ok(value),
+                _ => {
+                    let err_msg = "NTS-KE record body is not a single 16-bit value";
+                    Err(io::Error::new(io::ErrorKind::InvalidData, err_msg))
+                }
+            }
+        };
+
+        let record = match record_type {
+            END_OF_MESSAGE => Record::EndOfMessage,
+            NEXT_PROTOCOL => Record::NextProtocol(u16s(&body)?),
+            ERROR => Record::Error(u16_value(&body)?),
+            WARNING => Record::Warning(u16_value(&body)?),
+            AEAD_ALGORITHM => Record::AeadAlgorithm(u16s(&body)?),
+            NEW_COOKIE => Record::NewCookie(body),
+            SERVER if body.is_ascii() => {
+                Record::Server(body.into_iter().map(char::from).collect())
+            }
+            SERVER => {
+                let err_msg = "NTS-KE server record is not valid ASCII";
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+            }
+            PORT => Record::Port(u16_value(&body)?),
+            _ => Record::Unknown { critical, record_type, body },
+        };
+        Ok(record)
+    }
+}
+
+/// Perform an NTS-KE handshake with the server at `addr`, returning a **Session** for protected
+/// NTP requests.
+///
+///   `server_name` is the name validated against the server's certificate.
+///   `config` must offer TLS 1.3 and the `ntske/1` ALPN protocol, see `nts::client_config`.
+///   returns an error if the server cannot be reached, the TLS handshake fails or the server does
+///   not agree to NTPv4 with AEAD_AES_SIV_CMAC_256.
+pub fn key_exchange<A: ToSocketAddrs>(
+    addr: A,
+    server_name: &str,
+    config: Arc<ClientConfig>,
+) -> io::Result<Session> {
+    key_exchange_with(addr, server_name, config, &Client::new())
+}
+
+/// Perform an NTS-KE handshake as with `key_exchange`, using the timeouts of `client`.
+///
+///   The write timeout of `client` also bounds connecting to each address `addr` resolves to.
+pub fn key_exchange_with<A: ToSocketAddrs>(
+    addr: A,
+    server_name: &str,
+    config: Arc<ClientConfig>,
+    client: &Client,
+) -> io::Result<Session> {
+    let name = match ServerName::try_from(server_name.to_owned()) {
+        Ok(name) => name,
+        Err(_) => {
+            let err_msg = "invalid NTS-KE server name";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err_msg));
+        }
+    };
+    let conn = ClientConnection::new(config, name).map_err(tls_error)?;
+    let sock = connect(addr, client.write_timeout)?;
+    sock.set_read_timeout(client.read_timeout)?;
+    sock.set_write_timeout(client.write_timeout)?;
+    let peer = sock.peer_addr()?;
+    let mut tls = StreamOwned::new(conn, sock);
+
+    // Send the request.
+    let mut request = Vec::new();
+    request.write_bytes(Record::NextProtocol(vec![PROTOCOL_NTPV4]))?;
+    request.write_bytes(Record::AeadAlgorithm(vec![AEAD_AES_SIV_CMAC_256]))?;
+    request.write_bytes(Record::EndOfMessage)?;
+    tls.write_all(&request)?;
+    tls.flush()?;
+    debug!("sent NTS-KE request to {}", peer);
+
+    if tls.conn.alpn_protocol() != Some(ALPN_NTSKE) {
+        let err_msg = "NTS-KE server did not negotiate the ntske/1 protocol";
+        return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+    }
+
+    // Read the response.
+    let mut next_protocol = None;
+    let mut aead_algorithm = None;
+    let mut cookies = Vec::new();
+    let mut ntp_server = peer.ip().to_string();
+    let mut ntp_port = crate::protocol::PORT as u16;
+    loop {
+        let record = tls.read_bytes::<Record>()?;
+        debug!("recv NTS-KE record: {:?}", record);
+        match record {
+            Record::EndOfMessage => break,
+            Record::NextProtocol(ids) => next_protocol = Some(ids),
+            Record::AeadAlgorithm(ids) => aead_algorithm = Some(ids),
+            Record::NewCookie(cookie) => cookies.push(cookie),
+            Record::Server(server) => ntp_server = server,
+            Record::Port(port) => ntp_port = port,
+            Record::Warning(code) => warn!("NTS-KE warning {}", code),
+            Record::Error(code) => {
+                let err_msg = format!("NTS-KE server responded with error {}", code);
+                return Err(io::Error::other(err_msg));
+            }
+            Record::Unknown { critical: true, .. } => {
+                let err_msg = "unrecognized critical NTS-KE record";
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+            }
+            Record::Unknown { .. } => (),
+        }
+    }
+
+    if next_protocol != Some(vec![PROTOCOL_NTPV4]) {
+        let err_msg = "NTS-KE server did not select NTPv4";
+        return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+    }
+    if aead_algorithm != Some(vec![AEAD_AES_SIV_CMAC_256]) {
+        let err_msg = "NTS-KE server did not select AEAD_AES_SIV_CMAC_256";
+        return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+    }
+    if cookies.is_empty() {
+        let err_msg = "NTS-KE server sent no cookies";
+        return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+    }
+
+    let (c2s, s2c) = super::exported_keys(&tls.conn)?;
+    Ok(Session {
+        c2s,
+        s2c,
+        cookies,
+        ntp_server,
+        ntp_port,
+    })
+}
+
+// Utility functions.
+
+// Connect to the first address `addr` resolves to that accepts the connection within `timeout`.
+fn connect<A: ToSocketAddrs>(addr: A, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return TcpStream::connect(addr),
+    };
+    let mut last_err = None;
+    for addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(sock) => return Ok(sock),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        let err_msg = "address resolved to no socket addresses";
+        io::Error::new(io::ErrorKind::InvalidInput, err_msg)
+    }))
+}
+
+pub(crate) fn tls_error(err: ::rustls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
@@ -0,0 +1,432 @@
+//! Network Time Security (NTS) for NTP, as defined by RFC 8915.
+//!
+//! NTS runs in two phases. The client first performs an NTS Key Establishment (NTS-KE) handshake
+//! over TLS 1.3, from which both sides export a pair of AEAD keys and the client receives a
+//! supply of opaque cookies. Each subsequent NTP request then carries a Unique Identifier, one
+//! cookie and an NTS Authenticator extension field, and each response returns fresh cookies
+//! encrypted under the server-to-client key.
+//!
+//! The `server` module provides the matching NTS-KE and NTP servers.
+//!
+//! Only the mandatory AEAD_AES_SIV_CMAC_256 algorithm is supported.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! extern crate ntp;
+//! extern crate rustls;
+//!
+//! fn main() {
+//!     let mut roots = rustls::RootCertStore::empty();
+//!     // Add the trust anchors of the NTS-KE server to `roots`.
+//!     let config = ntp::nts::client_config(roots).unwrap();
+//!     let mut session = ntp::nts::ke::key_exchange(
+//!         ("time.cloudflare.com", ntp::nts::NTS_KE_PORT),
+//!         "time.cloudflare.com",
+//!         config,
+//!     ).unwrap();
+//!     let response = session.request().unwrap();
+//!     println!("{:?}", response.packet.transmit_timestamp);
+//! }
+//! ```
+
+use aes_siv::siv::Aes128Siv;
+use aes_siv::KeyInit;
+use crate::client::Client;
+use crate::exchange::Protection;
+use crate::protocol::{
+    ExtendedPacket, ExtensionField, ExtensionFieldType, KissOfDeath, Packet, ReadBytes,
+    ReferenceIdentifier, Stratum, WriteBytes,
+};
+use rand::RngCore;
+use rustls::{ClientConfig, ConnectionCommon, RootCertStore};
+use std::sync::Arc;
+use std::{fmt, io};
+
+pub mod ke;
+pub mod server;
+
+/// The TCP port of NTS-KE servers.
+pub const NTS_KE_PORT: u16 = 4460;
+
+/// The ALPN protocol identifier of NTS-KE.
+pub const ALPN_NTSKE: &[u8] = b"ntske/1";
+
+/// The TLS exporter label used to derive the AEAD keys.
+pub const EXPORTER_LABEL: &[u8] = b"EXPORTER-network-time-security";
+
+/// The NTS Next Protocol ID of NTPv4.
+pub const PROTOCOL_NTPV4: u16 = 0;
+
+/// The IANA AEAD algorithm identifier of AEAD_AES_SIV_CMAC_256.
+pub const AEAD_AES_SIV_CMAC_256: u16 = 15;
+
+/// The length of the AEAD_AES_SIV_CMAC_256 key in octets.
+pub const KEY_LEN: usize = 32;
+
+/// The length of the nonces generated by this implementation in octets.
+pub const NONCE_LEN: usize = 16;
+
+/// The length of the Unique Identifier generated by this implementation in octets.
+pub const UNIQUE_IDENTIFIER_LEN: usize = 32;
+
+/// The number of cookies a client aims to hold, as recommended by RFC 8915.
+pub const COOKIE_TARGET: usize = 8;
+
+/// A key for the AEAD_AES_SIV_CMAC_256 algorithm.
+#[derive(Clone, Eq, PartialEq)]
+pub struct AeadKey([u8; KEY_LEN]);
+
+/// The contents of an NTS Authenticator and Encrypted Extension Fields extension field.
+///
+/// ### Layout
+///
+/// ```ignore
+///  0                   1                   2                   3
+///  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |          Nonce Length         |      Ciphertext Length        |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                                                               |
+/// ~                 Nonce, including up to 3 octets padding       ~
+/// |                                                               |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                                                               |
+/// ~             Ciphertext, including up to 3 octets padding      ~
+/// |                                                               |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                                                               |
+/// ~                      Additional Padding                       ~
+/// |                                                               |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// ```
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Authenticator {
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+/// The keys and cookies negotiated by an NTS-KE handshake, used to protect subsequent NTP
+/// requests to the negotiated NTP server.
+///
+/// Each request consumes one cookie and each valid response replenishes it, so a **Session** can
+/// be used for as long as responses keep arriving.
+pub struct Session {
+    c2s: AeadKey,
+    s2c: AeadKey,
+    cookies: Vec<Vec<u8>>,
+    ntp_server: String,
+    ntp_port: u16,
+}
+
+/// The **Protection** of the requests of an exchange by an NTS **Session**, for use with
+/// `Client::request_protected`.
+///
+/// Each attempt consumes one cookie and carries a fresh Unique Identifier. Only responses that
+/// echo it and are authenticated with the session keys are accepted, except for an
+/// unauthenticated NTSN kiss-o'-death echoing it, which RFC 8915 uses to report that the cookie
+/// was rejected.
+#[derive(Debug)]
+pub struct SessionProtection<'a> {
+    session: &'a mut Session,
+    unique_id: Vec<u8>,
+    response: Option<ExtendedPacket>,
+}
+
+// Inherent implementations.
+
+impl AeadKey {
+    /// Create a key from its raw bytes.
+    pub fn new(bytes: [u8; KEY_LEN]) -> Self {
+        AeadKey(bytes)
+    }
+
+    /// Generate a random key.
+    pub fn generate() -> Self {
+        let mut bytes = [0u8; KEY_LEN];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        AeadKey(bytes)
+    }
+
+    /// The raw bytes of the key.
+    pub fn as_bytes(&self) -> &[u8; KEY_LEN] {
+        &self.0
+    }
+
+    /// Encrypt and authenticate `plaintext`, additionally authenticating `associated_data`.
+    pub fn seal(&self, nonce: &[u8], plaintext: &[u8], associated_data: &[u8]) -> Vec<u8> {
+        self.cipher()
+            .encrypt([associated_data, nonce], plaintext)
+            .expect("AES-SIV encryption cannot fail")
+    }
+
+    /// Authenticate and decrypt `ciphertext`, which must have been sealed with the same nonce and
+    /// associated data.
+    pub fn open(
+        &self,
+        nonce: &[u8],
+        ciphertext: &[u8],
+        associated_data: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        match self.cipher().decrypt([associated_data, nonce], ciphertext) {
+            Ok(plaintext) => Ok(plaintext),
+            Err(_) => {
+                let err_msg = "NTS authenticator verification failed";
+                Err(io::Error::new(io::ErrorKind::InvalidData, err_msg))
+            }
+        }
+    }
+
+    fn cipher(&self) -> Aes128Siv {
+        Aes128Siv::new_from_slice(&self.0).expect("key has the correct length")
+    }
+}
+
+impl Authenticator {
+    /// Seal `plaintext` with `key` under a fresh random nonce. `associated_data` must be the bytes
+    /// of the packet preceding the authenticator extension field.
+    pub fn seal(key: &AeadKey, plaintext: &[u8], associated_data: &[u8]) -> Self {
+        let mut nonce = vec![0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = key.seal(&nonce, plaintext, associated_data);
+        Authenticator { nonce, ciphertext }
+    }
+
+    /// Verify the authenticator with `key`, returning the decrypted plaintext.
+    pub fn open(&self, key: &AeadKey, associated_data: &[u8]) -> io::Result<Vec<u8>> {
+        key.open(&self.nonce, &self.ciphertext, associated_data)
+    }
+
+    /// Encode the authenticator as an extension field.
+    pub fn to_extension_field(&self) -> ExtensionField {
+        let mut value = Vec::new();
+        value.extend_from_slice(&(self.nonce.len() as u16).to_be_bytes());
+        value.extend_from_slice(&(self.ciphertext.len() as u16).to_be_bytes());
+        value.extend_from_slice(&self.nonce);
+        value.resize(padded_len(value.len()), 0);
+        value.extend_from_slice(&self.ciphertext);
+        value.resize(padded_len(value.len()), 0);
+        ExtensionField::new(ExtensionFieldType::NTS_AUTHENTICATOR, value)
+    }
+
+    /// Decode the authenticator from the value of an extension field.
+    pub fn from_extension_field(field: &ExtensionField) -> io::Result<Self> {
+        let value = &field.value[..];
+        if field.field_type != ExtensionFieldType::NTS_AUTHENTICATOR || value.len() < 4 {
+            let err_msg = "not an NTS authenticator extension field";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+        }
+        let nonce_len = u16::from_be_bytes([value[0], value[1]]) as usize;
+        let ciphertext_len = u16::from_be_bytes([value[2], value[3]]) as usize;
+        let nonce_start = 4;
+        let ciphertext_start = nonce_start + padded_len(nonce_len);
+        if ciphertext_start + padded_len(ciphertext_len) > value.len() {
+            let err_msg = "truncated NTS authenticator extension field";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+        }
+        Ok(Authenticator {
+            nonce: value[nonce_start..nonce_start + nonce_len].to_vec(),
+            ciphertext: value[ciphertext_start..ciphertext_start + ciphertext_len].to_vec(),
+        })
+    }
+}
+
+impl Session {
+    /// The host name or address and the UDP port of the NTP server negotiated for this session.
+    pub fn ntp_server(&self) -> (&str, u16) {
+        (&self.ntp_server, self.ntp_port)
+    }
+
+    /// The number of unused cookies.
+    pub fn cookies(&self) -> usize {
+        self.cookies.len()
+    }
+
+    /// Protect the requests of an exchange with this session.
+    pub fn protection(&mut self) -> SessionProtection<'_> {
+        SessionProtection {
+            session: self,
+            unique_id: Vec::new(),
+            response: None,
+        }
+    }
+
+    /// Send a blocking, NTS-protected request to the negotiated NTP server with a 5 second
+    /// timeout.
+    ///
+    ///   returns an error if no cookies remain, the server cannot be reached, or no response can
+    ///   be authenticated. A rejected cookie is reported as an NTSN kiss-o'-death.
+    pub fn request(&mut self) -> io::Result<ExtendedPacket> {
+        self.request_with(&Client::new())
+    }
+
+    /// Send a blocking, NTS-protected request to the negotiated NTP server, configured by
+    /// `client`. Each retry consumes another cookie.
+    pub fn request_with(&mut self, client: &Client) -> io::Result<ExtendedPacket> {
+        let addr = (self.ntp_server.clone(), self.ntp_port);
+        let mut protection = self.protection();
+        client.request_protected(addr, &mut protection)?;
+        Ok(protection.response.expect("the response of a measurement is authenticated"))
+    }
+
+    // Build a request from the header `request`, consuming one cookie. Returns the bytes and
+    // the Unique Identifier that the response must echo.
+    fn prepare_request(&mut self, request: &Packet) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        let cookie = match self.cookies.pop() {
+            Some(cookie) => cookie,
+            None => {
+                let err_msg = "no NTS cookies left, a new key exchange is required";
+                return Err(io::Error::other(err_msg));
+            }
+        };
+        let mut unique_id = vec![0u8; UNIQUE_IDENTIFIER_LEN];
+        rand::thread_rng().fill_bytes(&mut unique_id);
+
+        let mut packet = ExtendedPacket::from(*request);
+        packet.extension_fields.push(ExtensionField::new(
+            ExtensionFieldType::UNIQUE_IDENTIFIER,
+            unique_id.clone(),
+        ));
+        // Request enough additional cookies to get back to the target.
+        let placeholders = COOKIE_TARGET.saturating_sub(self.cookies.len() + 1);
+        for _ in 0..placeholders {
+            packet.extension_fields.push(ExtensionField::new(
+                ExtensionFieldType::NTS_COOKIE_PLACEHOLDER,
+                vec![0; cookie.len()],
+            ));
+        }
+        packet.extension_fields.push(ExtensionField::new(ExtensionFieldType::NTS_COOKIE, cookie));
+        let authenticator = Authenticator::seal(&self.c2s, &[], &associated_data(&packet)?);
+        packet.extension_fields.push(authenticator.to_extension_field());
+
+        let mut bytes = Vec::with_capacity(packet.packed_size_bytes());
+        bytes.write_bytes(&packet)?;
+        Ok((bytes, unique_id))
+    }
+
+    // Authenticate the response `packet` and collect the cookies it carries.
+    fn process_response(&mut self, packet: &ExtendedPacket) -> io::Result<()> {
+        let authenticator = match packet.extension_fields.last() {
+            Some(field) if field.field_type == ExtensionFieldType::NTS_AUTHENTICATOR => {
+                Authenticator::from_extension_field(field)?
+            }
+            _ => {
+                let err_msg = "NTS response is not authenticated";
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+            }
+        };
+        let plaintext = authenticator.open(&self.s2c, &associated_data(packet)?)?;
+        for field in read_extension_fields(&plaintext)? {
+            if field.field_type == ExtensionFieldType::NTS_COOKIE {
+                self.cookies.push(field.value);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Trait implementations.
+
+impl<'a> Protection for SessionProtection<'a> {
+    fn protect(&mut self, request: &Packet) -> io::Result<Vec<u8>> {
+        let (bytes, unique_id) = self.session.prepare_request(request)?;
+        self.unique_id = unique_id;
+        Ok(bytes)
+    }
+
+    fn verify(&mut self, bytes: &[u8], response: &Packet) -> io::Result<()> {
+        let packet: ExtendedPacket = (&bytes[..]).read_bytes()?;
+        let echoed = packet.extension_fields.iter().any(|field| {
+            field.field_type == ExtensionFieldType::UNIQUE_IDENTIFIER
+                && field.value == self.unique_id
+        });
+        if !echoed {
+            let err_msg = "NTS response does not echo the unique identifier";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+        }
+        let nak = ReferenceIdentifier::KissOfDeath(KissOfDeath::Ntsn);
+        if response.stratum == Stratum::UNSPECIFIED && response.reference_id == nak {
+            return Ok(());
+        }
+        self.session.process_response(&packet)?;
+        self.response = Some(packet);
+        Ok(())
+    }
+}
+
+// Debug implementations.
+
+impl fmt::Debug for AeadKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AeadKey(<redacted>)")
+    }
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("cookies", &self.cookies.len())
+            .field("ntp_server", &self.ntp_server)
+            .field("ntp_port", &self.ntp_port)
+            .finish()
+    }
+}
+
+/// Create a TLS client configuration suitable for NTS-KE, trusting the certificates in `roots`.
+pub fn client_config(roots: RootCertStore) -> io::Result<Arc<ClientConfig>> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut config = ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(ke::tls_error)?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = vec![ALPN_NTSKE.to_vec()];
+    Ok(Arc::new(config))
+}
+
+/// Export the client-to-server and server-to-client keys from an established NTS-KE connection.
+pub fn exported_keys<Data>(conn: &ConnectionCommon<Data>) -> io::Result<(AeadKey, AeadKey)> {
+    let export = |direction: u8| -> io::Result<AeadKey> {
+        let context = [
+            (PROTOCOL_NTPV4 >> 8) as u8,
+            PROTOCOL_NTPV4 as u8,
+            (AEAD_AES_SIV_CMAC_256 >> 8) as u8,
+            AEAD_AES_SIV_CMAC_256 as u8,
+            direction,
+        ];
+        let key = conn
+            .export_keying_material([0u8; KEY_LEN], EXPORTER_LABEL, Some(&context))
+            .map_err(ke::tls_error)?;
+        Ok(AeadKey(key))
+    };
+    Ok((export(0)?, export(1)?))
+}
+
+/// The associated data protected by the authenticator of `packet`: the header and every extension
+/// field preceding the authenticator, or all extension fields if there is none yet.
+pub fn associated_data(packet: &ExtendedPacket) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    bytes.write_bytes(packet.packet)?;
+    for field in &packet.extension_fields {
+        if field.field_type == ExtensionFieldType::NTS_AUTHENTICATOR {
+            break;
+        }
+        bytes.write_bytes(field)?;
+    }
+    Ok(bytes)
+}
+
+/// Read a sequence of extension fields, such as the plaintext of an authenticator.
+pub fn read_extension_fields(mut bytes: &[u8]) -> io::Result<Vec<ExtensionField>> {
+    let mut fields = Vec::new();
+    while !bytes.is_empty() {
+        fields.push(bytes.read_bytes()?);
+    }
+    Ok(fields)
+}
+
+// Utility functions.
+
+fn padded_len(len: usize) -> usize {
+    (len + 3) & !3
+}
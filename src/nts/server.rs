@@ -0,0 +1,404 @@
+//! Server side of Network Time Security: an NTS-KE server handing out cookies and an NTP server
+//! answering NTS-protected requests.
+//!
+//! Cookies are opaque to clients. This implementation seals the client's AEAD keys under a
+//! master key, so the NTP server needs no per-client state. Master keys rotate periodically and
+//! a few previous keys are retained, so cookies handed out shortly before a rotation remain
+//! valid.
+//!
+//! ### Cookie Layout
+//!
+//! ```ignore
+//! +-------------------+-------------------+----------------------------------------+
+//! | Key ID (32)       | Nonce (128)       | AES-SIV(C2S key || S2C key) (640)      |
+//! +-------------------+-------------------+----------------------------------------+
+//! ```
+
+use byteorder::{ByteOrder, BE};
+use crate::nts::ke::{self, Record};
+use crate::nts::{
+    read_extension_fields, AeadKey, Authenticator, AEAD_AES_SIV_CMAC_256, COOKIE_TARGET, KEY_LEN,
+    NONCE_LEN, PROTOCOL_NTPV4,
+};
+use crate::protocol::{
+    ConstPackedSizeBytes, ExtendedPacket, ExtensionField, ExtensionFieldType, KissOfDeath, Mode,
+    Packet, ReadBytes, ReferenceIdentifier, Stratum, TimestampFormat, WriteBytes,
+};
+use crate::clock::{Clock, SystemClock};
+use rand::RngCore;
+use rustls::{ServerConnection, StreamOwned};
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The number of master keys, including the current one, that are accepted when opening cookies.
+pub const MASTER_KEY_HISTORY: usize = 3;
+
+/// The length of the cookies issued by this implementation in octets.
+pub const COOKIE_LEN: usize = 4 + NONCE_LEN + 2 * KEY_LEN + 16;
+
+/// The default maximum number of NTS-KE connections a **KeServer** handles at once.
+pub const MAX_KE_CONNECTIONS: usize = 64;
+
+/// A set of rotating master keys used to seal and open cookies.
+///
+/// Cloning a **MasterKeys** shares the underlying keys, so the NTS-KE and NTP servers can be given
+/// clones of the same instance.
+#[derive(Clone)]
+pub struct MasterKeys {
+    inner: Arc<RwLock<KeyRing>>,
+}
+
+// The number of connections being handled by a **KeServer**, signalled when one finishes.
+type Connections = Arc<(Mutex<usize>, Condvar)>;
+
+// Releases the slot of a connection when its thread finishes.
+struct ConnectionSlot(Connections);
+
+struct KeyRing {
+    // Most recent key first.
+    keys: VecDeque<(u32, AeadKey)>,
+    rotation_interval: Duration,
+    last_rotation: Instant,
+}
+
+/// An NTS-KE server, negotiating keys over TLS and issuing cookies.
+#[derive(Clone)]
+pub struct KeServer {
+    tls_config: Arc<rustls::ServerConfig>,
+    master_keys: MasterKeys,
+    ntp_server: Option<String>,
+    ntp_port: Option<u16>,
+    max_connections: usize,
+}
+
+/// An NTP server answering NTS-protected requests.
+#[derive(Clone)]
+pub struct NtpServer {
+    master_keys: MasterKeys,
+    /// The header fields describing this server's clock. The version, mode, poll and timestamps
+    /// are filled in per response.
+    pub system: Packet,
+    clock: Arc<dyn Clock + Send + Sync>,
+}
+
+// Inherent implementations.
+
+impl MasterKeys {
+    /// Create a new set of master keys, rotated every `rotation_interval`.
+    pub fn new(rotation_interval: Duration) -> Self {
+        let mut keys = VecDeque::with_capacity(MASTER_KEY_HISTORY);
+        keys.push_front((rand::random(), AeadKey::generate()));
+        let ring = KeyRing {
+            keys,
+            rotation_interval,
+            last_rotation: Instant::now(),
+        };
+        MasterKeys { inner: Arc::new(RwLock::new(ring)) }
+    }
+
+    /// Replace the current master key with a fresh one, retaining the previous keys for opening
+    /// existing cookies.
+    pub fn rotate(&self) {
+        let mut ring = self.inner.write().expect("master key lock poisoned");
+        ring.rotate();
+    }
+
+    /// Seal the client's keys into a new cookie under the current master key.
+    pub fn seal_cookie(&self, c2s: &AeadKey, s2c: &AeadKey) -> Vec<u8> {
+        let mut ring = self.inner.write().expect("master key lock poisoned");
+        if ring.last_rotation.elapsed() >= ring.rotation_interval {
+            ring.rotate();
+        }
+        let (key_id, ref master_key) = ring.keys[0];
+        let mut key_id_bytes = [0u8; 4];
+        BE::write_u32(&mut key_id_bytes, key_id);
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let mut plaintext = Vec::with_capacity(2 * KEY_LEN);
+        plaintext.extend_from_slice(c2s.as_bytes());
+        plaintext.extend_from_slice(s2c.as_bytes());
+
+        let mut cookie = Vec::with_capacity(COOKIE_LEN);
+        cookie.extend_from_slice(&key_id_bytes);
+        cookie.extend_from_slice(&nonce);
+        cookie.extend_from_slice(&master_key.seal(&nonce, &plaintext, &key_id_bytes));
+        cookie
+    }
+
+    /// Open a cookie, returning the client-to-server and server-to-client keys it carries, or
+    /// `None` if it was not issued under any of the retained master keys.
+    pub fn open_cookie(&self, cookie: &[u8]) -> Option<(AeadKey, AeadKey)> {
+        if cookie.len() != COOKIE_LEN {
+            return None;
+        }
+        let key_id = BE::read_u32(&cookie[..4]);
+        let nonce = &cookie[4..4 + NONCE_LEN];
+        let ring = self.inner.read().expect("master key lock poisoned");
+        let master_key = ring.keys.iter().find(|&&(id, _)| id == key_id).map(|(_, k)| k)?;
+        let plaintext = master_key.open(nonce, &cookie[4 + NONCE_LEN..], &cookie[..4]).ok()?;
+        let mut c2s = [0u8; KEY_LEN];
+        let mut s2c = [0u8; KEY_LEN];
+        c2s.copy_from_slice(&plaintext[..KEY_LEN]);
+        s2c.copy_from_slice(&plaintext[KEY_LEN..]);
+        Some((AeadKey::new(c2s), AeadKey::new(s2c)))
+    }
+}
+
+impl KeyRing {
+    fn rotate(&mut self) {
+        let key_id = self.keys[0].0.wrapping_add(1);
+        self.keys.push_front((key_id, AeadKey::generate()));
+        self.keys.truncate(MASTER_KEY_HISTORY);
+        self.last_rotation = Instant::now();
+    }
+}
+
+impl KeServer {
+    /// Create a new **KeServer**.
+    ///
+    /// `tls_config` must only allow TLS 1.3 and must offer the `ntske/1` ALPN protocol.
+    pub fn new(tls_config: Arc<rustls::ServerConfig>, master_keys: MasterKeys) -> Self {
+        KeServer {
+            tls_config,
+            master_keys,
+            ntp_server: None,
+            ntp_port: None,
+            max_connections: MAX_KE_CONNECTIONS,
+        }
+    }
+
+    /// Direct clients to an NTP server other than this host and the default port.
+    pub fn ntp_server(mut self, server: Option<String>, port: Option<u16>) -> Self {
+        self.ntp_server = server;
+        self.ntp_port = port;
+        self
+    }
+
+    /// Set the maximum number of connections handled at once. Further connections are not
+    /// accepted until one of them finishes.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections.max(1);
+        self
+    }
+
+    /// Accept NTS-KE connections on `listener` until an error occurs, handling each connection on
+    /// its own thread.
+    pub fn serve(&self, listener: &TcpListener) -> io::Result<()> {
+        let connections = Connections::default();
+        loop {
+            {
+                let (ref count, ref finished) = *connections;
+                let mut count = count.lock().expect("connection count lock poisoned");
+                while *count >= self.max_connections {
+                    count = finished.wait(count).expect("connection count lock poisoned");
+                }
+                *count += 1;
+            }
+            let slot = ConnectionSlot(connections.clone());
+            let (sock, peer) = listener.accept()?;
+            let server = self.clone();
+            thread::spawn(move || {
+                let _slot = slot;
+                if let Err(err) = server.handle(sock) {
+                    debug!("NTS-KE connection from {} failed: {}", peer, err);
+                }
+            });
+        }
+    }
+
+    /// Handle a single NTS-KE connection.
+    pub fn handle(&self, sock: TcpStream) -> io::Result<()> {
+        sock.set_read_timeout(Some(Duration::from_secs(5)))?;
+        sock.set_write_timeout(Some(Duration::from_secs(5)))?;
+        let conn = ServerConnection::new(self.tls_config.clone()).map_err(ke::tls_error)?;
+        let mut tls = StreamOwned::new(conn, sock);
+
+        let mut next_protocol = None;
+        let mut aead_algorithm = None;
+        let mut error = None;
+        loop {
+            match tls.read_bytes::<Record>()? {
+                Record::EndOfMessage => break,
+                Record::NextProtocol(ids) => next_protocol = Some(ids),
+                Record::AeadAlgorithm(ids) => aead_algorithm = Some(ids),
+                Record::Unknown { critical: true, .. } => {
+                    error = Some(ke::ERROR_UNRECOGNIZED_CRITICAL_RECORD);
+                }
+                _ => (),
+            }
+        }
+        // Both negotiation records are required in a request.
+        if next_protocol.is_none() || aead_algorithm.is_none() {
+            error = error.or(Some(ke::ERROR_BAD_REQUEST));
+        }
+
+        let mut response = Vec::new();
+        if let Some(code) = error {
+            response.write_bytes(Record::Error(code))?;
+        } else {
+            let ntp = next_protocol.is_some_and(|ids| ids.contains(&PROTOCOL_NTPV4));
+            let aead = aead_algorithm.is_some_and(|ids| ids.contains(&AEAD_AES_SIV_CMAC_256));
+            let selected = |supported: bool, id: u16| if supported { vec![id] } else { vec![] };
+            response.write_bytes(Record::NextProtocol(selected(ntp, PROTOCOL_NTPV4)))?;
+            response.write_bytes(Record::AeadAlgorithm(selected(aead, AEAD_AES_SIV_CMAC_256)))?;
+            if ntp && aead {
+                if let Some(ref server) = self.ntp_server {
+                    response.write_bytes(Record::Server(server.clone()))?;
+                }
+                if let Some(port) = self.ntp_port {
+                    response.write_bytes(Record::Port(port))?;
+                }
+                let (c2s, s2c) = super::exported_keys(&tls.conn)?;
+                for _ in 0..COOKIE_TARGET {
+                    let cookie = self.master_keys.seal_cookie(&c2s, &s2c);
+                    response.write_bytes(Record::NewCookie(cookie))?;
+                }
+            }
+        }
+        response.write_bytes(Record::EndOfMessage)?;
+        tls.write_all(&response)?;
+        tls.conn.send_close_notify();
+        tls.flush()?;
+        Ok(())
+    }
+}
+
+impl NtpServer {
+    /// Create a new **NtpServer** opening cookies with `master_keys`.
+    pub fn new(master_keys: MasterKeys, system: Packet) -> Self {
+        NtpServer {
+            master_keys,
+            system,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Take receive and transmit timestamps from `clock` rather than the system clock.
+    pub fn clock<C: Clock + Send + Sync + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Answer requests on `sock` indefinitely. Requests that cannot be answered are dropped, and
+    /// errors receiving or sending a datagram are logged without stopping the server.
+    pub fn serve(&self, sock: &UdpSocket) -> io::Result<()> {
+        let mut buf = [0u8; crate::RECV_BUFFER_SIZE];
+        loop {
+            let (len, src) = match sock.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(err) => {
+                    warn!("failed to receive NTS request: {}", err);
+                    continue;
+                }
+            };
+            let receive_timestamp = self.clock.now();
+            match self.respond(&buf[..len], receive_timestamp) {
+                Ok(response) => {
+                    if let Err(err) = sock.send_to(&response, src) {
+                        warn!("failed to send NTS response to {}: {}", src, err);
+                    }
+                }
+                Err(err) => debug!("dropped NTS request from {}: {}", src, err),
+            }
+        }
+    }
+
+    /// Build the response to the NTS-protected `request`, received at `receive_timestamp`.
+    ///
+    /// Returns an NTSN kiss-o'-death response if the cookie or authenticator cannot be verified,
+    /// and an error if the request is malformed and should be dropped.
+    pub fn respond(
+        &self,
+        request: &[u8],
+        receive_timestamp: TimestampFormat,
+    ) -> io::Result<Vec<u8>> {
+        if request.len() < Packet::PACKED_SIZE_BYTES {
+            let err_msg = "truncated NTP request";
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, err_msg));
+        }
+        let header = (&request[..Packet::PACKED_SIZE_BYTES]).read_bytes::<Packet>()?;
+        if header.mode != Mode::Client {
+            let err_msg = "not a client request";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+        }
+        let fields = read_extension_fields(&request[Packet::PACKED_SIZE_BYTES..])?;
+
+        let find_one = |field_type: ExtensionFieldType| -> io::Result<&ExtensionField> {
+            let mut matching = fields.iter().filter(|f| f.field_type == field_type);
+            match (matching.next(), matching.next()) {
+                (Some(field), None) => Ok(field),
+                _ => {
+                    let err_msg = "NTS request must carry exactly one of each NTS field";
+                    Err(io::Error::new(io::ErrorKind::InvalidData, err_msg))
+                }
+            }
+        };
+        let unique_id = find_one(ExtensionFieldType::UNIQUE_IDENTIFIER)?;
+        let cookie = find_one(ExtensionFieldType::NTS_COOKIE)?;
+        let authenticator_field = find_one(ExtensionFieldType::NTS_AUTHENTICATOR)?;
+        if fields.last() != Some(authenticator_field) {
+            let err_msg = "NTS authenticator is not the last extension field";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+        }
+        // Only placeholders at least as large as a cookie are honoured, so that the response is
+        // never larger than the request.
+        let placeholders = fields
+            .iter()
+            .filter(|f| f.field_type == ExtensionFieldType::NTS_COOKIE_PLACEHOLDER)
+            .filter(|f| f.value.len() >= COOKIE_LEN)
+            .count();
+
+        let mut packet = self.system;
+        packet.version = header.version;
+        packet.mode = Mode::Server;
+        packet.poll = header.poll;
+        packet.origin_timestamp = header.transmit_timestamp;
+        packet.receive_timestamp = receive_timestamp;
+        let mut response = ExtendedPacket::from(packet);
+        response.extension_fields.push(unique_id.clone());
+
+        // Open the cookie and verify the request with the keys it carries.
+        let associated_data = &request[..request.len() - authenticator_field.packed_size_bytes()];
+        let keys = self.master_keys.open_cookie(&cookie.value).and_then(|(c2s, s2c)| {
+            let authenticator = Authenticator::from_extension_field(authenticator_field).ok()?;
+            authenticator.open(&c2s, associated_data).ok().map(|_| (c2s, s2c))
+        });
+        match keys {
+            Some((c2s, s2c)) => {
+                let mut plaintext = Vec::new();
+                for _ in 0..placeholders.min(COOKIE_TARGET - 1) + 1 {
+                    let cookie = self.master_keys.seal_cookie(&c2s, &s2c);
+                    let field = ExtensionField::new(ExtensionFieldType::NTS_COOKIE, cookie);
+                    plaintext.write_bytes(field)?;
+                }
+                response.packet.transmit_timestamp = self.clock.now();
+                let associated_data = super::associated_data(&response)?;
+                let authenticator = Authenticator::seal(&s2c, &plaintext, &associated_data);
+                response.extension_fields.push(authenticator.to_extension_field());
+            }
+            None => {
+                response.packet.stratum = Stratum::UNSPECIFIED;
+                response.packet.reference_id = ReferenceIdentifier::KissOfDeath(KissOfDeath::Ntsn);
+                response.packet.transmit_timestamp = self.clock.now();
+            }
+        }
+
+        let mut bytes = Vec::with_capacity(response.packed_size_bytes());
+        bytes.write_bytes(&response)?;
+        Ok(bytes)
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let (ref count, ref finished) = *self.0;
+        if let Ok(mut count) = count.lock() {
+            *count -= 1;
+        }
+        finished.notify_one();
+    }
+}
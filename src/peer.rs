@@ -0,0 +1,243 @@
+//! Long-lived associations with peers, following the poll process of RFC 5905 section 13.
+//!
+//! A **PeerAssociation** holds the state kept for a single server between requests: the reach
+//! register recording which of the last 8 polls were answered, the unreach counter, the host poll
+//! exponent and the time of the next poll, the variables of the last packet received, and the
+//! clock filter of the samples measured. Like the **Exchange** it performs no I/O. The driver
+//! sends a request whenever `poll` returns true, and feeds the outcome back with `receive` or
+//! `kiss`.
+//!
+//! While the server answers, the host poll exponent follows the system poll exponent supplied by
+//! the driver, typically that of the clock discipline. Once it has left 24 polls in a row
+//! unanswered, the poll interval doubles with every further poll. Kiss-o'-death responses are
+//! obeyed: DENY and RSTR demobilize the association for good, and RATE doubles the poll interval
+//! and raises the minimum poll exponent of the association, so that the interval stays at least
+//! that long.
+//!
+//! ```
+//! use ntp::peer::PeerAssociation;
+//! use ntp::protocol::TimestampFormat;
+//! use std::time::Instant;
+//!
+//! let now = Instant::now();
+//! let time = TimestampFormat { seconds: 1000, fraction: 0 };
+//! let mut peer = PeerAssociation::new("192.0.2.1:123".parse().unwrap(), now);
+//! assert!(peer.poll(now, time, 6));
+//! // ... send a request and wait for the response ...
+//! assert_eq!(peer.reach(), 0);
+//! assert!(peer.next_poll() > now);
+//! ```
+
+use crate::filter::{ClockFilter, PeerStatistics, Sample};
+use crate::measurement::Measurement;
+use crate::protocol::{
+    KissOfDeath, Packet, TimestampFormat, BCOUNT, BTIME, MAXDISP, MAXPOLL, MINPOLL, UNREACH,
+};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// The state of an association with a single peer.
+#[derive(Clone, Debug)]
+pub struct PeerAssociation {
+    addr: SocketAddr,
+    reach: u8,
+    unreach: u32,
+    hpoll: u8,
+    ppoll: u8,
+    // The lowest host poll exponent, raised by RATE kisses.
+    minpoll: u8,
+    iburst: bool,
+    // The number of polls remaining in the current burst.
+    burst: u32,
+    // The time of the last poll outside a burst.
+    outdate: Instant,
+    next_poll: Instant,
+    packet: Option<Packet>,
+    filter: ClockFilter,
+    demobilized: Option<KissOfDeath>,
+}
+
+// Inherent implementations.
+
+impl PeerAssociation {
+    /// Create an association with the server at `addr`, due to be polled at `now`.
+    pub fn new(addr: SocketAddr, now: Instant) -> Self {
+        PeerAssociation {
+            addr,
+            reach: 0,
+            unreach: 0,
+            hpoll: MINPOLL,
+            ppoll: MAXPOLL,
+            minpoll: MINPOLL,
+            iburst: false,
+            burst: 0,
+            outdate: now,
+            next_poll: now,
+            packet: None,
+            filter: ClockFilter::new(),
+            demobilized: None,
+        }
+    }
+
+    /// Send a burst of `BCOUNT` requests `BTIME` seconds apart, rather than a single request,
+    /// whenever the server becomes unreachable, so that it is quickly reached again.
+    pub fn iburst(mut self, enabled: bool) -> Self {
+        self.iburst = enabled;
+        self
+    }
+
+    /// The address of the server.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The reach register. Bit 0 is set if the most recent poll was answered, bit 1 if the one
+    /// before it was, and so on.
+    pub fn reach(&self) -> u8 {
+        self.reach
+    }
+
+    /// Whether or not any of the last 8 polls were answered.
+    pub fn is_reachable(&self) -> bool {
+        self.reach != 0
+    }
+
+    /// The number of polls since the server was last reachable.
+    pub fn unreach(&self) -> u32 {
+        self.unreach
+    }
+
+    /// The host poll exponent, between `minpoll` and `MAXPOLL`.
+    pub fn hpoll(&self) -> u8 {
+        self.hpoll
+    }
+
+    /// The lowest host poll exponent, `MINPOLL` unless raised by RATE kisses.
+    pub fn minpoll(&self) -> u8 {
+        self.minpoll
+    }
+
+    /// The poll exponent last sent by the server, between `MINPOLL` and `MAXPOLL`.
+    pub fn ppoll(&self) -> u8 {
+        self.ppoll
+    }
+
+    /// The time at which the server is next due to be polled.
+    pub fn next_poll(&self) -> Instant {
+        self.next_poll
+    }
+
+    /// The last packet received from the server, holding its leap indicator, stratum, root delay,
+    /// root dispersion, reference identifier and reference timestamp.
+    pub fn packet(&self) -> Option<&Packet> {
+        self.packet.as_ref()
+    }
+
+    /// The clock filter of the samples measured with the server.
+    pub fn filter(&self) -> &ClockFilter {
+        &self.filter
+    }
+
+    /// The kiss code the association was demobilized by, if any. A demobilized association is
+    /// never polled again.
+    pub fn demobilized(&self) -> Option<KissOfDeath> {
+        self.demobilized
+    }
+
+    /// Run the poll process at the monotonic time `now` and local clock `time`, given the system
+    /// poll exponent `system_poll`. Returns whether or not a request should be sent.
+    ///
+    /// This should be called once `next_poll` has passed.
+    pub fn poll(&mut self, now: Instant, time: TimestampFormat, system_poll: u8) -> bool {
+        if self.demobilized.is_some() {
+            return false;
+        }
+        let mut hpoll = self.hpoll;
+        if self.burst == 0 {
+            self.outdate = now;
+            self.reach <<= 1;
+            // Age out the samples of a server that stopped answering.
+            if self.reach & 0x7 == 0 {
+                let sample = Sample {
+                    offset: 0.0,
+                    delay: MAXDISP,
+                    dispersion: MAXDISP,
+                    epoch: time,
+                };
+                self.filter.update(sample, self.hpoll as i8);
+            }
+            if self.reach == 0 {
+                // The request now being sent counts as the first of the burst.
+                if self.iburst && self.unreach == 0 {
+                    self.burst = BCOUNT - 1;
+                }
+                if self.unreach < UNREACH {
+                    self.unreach += 1;
+                } else {
+                    hpoll += 1;
+                }
+            } else {
+                self.unreach = 0;
+                hpoll = system_poll;
+            }
+        } else {
+            self.burst -= 1;
+        }
+        self.update_poll(hpoll, now);
+        true
+    }
+
+    /// Handle the valid response `measurement` received at `now`.
+    ///
+    /// Returns the new peer statistics if the clock filter passes the sample on to clock
+    /// selection.
+    pub fn receive(&mut self, measurement: &Measurement, now: Instant) -> Option<PeerStatistics> {
+        if self.demobilized.is_some() {
+            return None;
+        }
+        self.reach |= 1;
+        // Servers that do not echo the poll exponent of the request send zero.
+        if measurement.packet.poll > 0 {
+            self.ppoll = (measurement.packet.poll as u8).clamp(MINPOLL, MAXPOLL);
+        }
+        self.packet = Some(measurement.packet);
+        let statistics = self.filter.update(Sample::from(measurement), self.hpoll as i8);
+        if self.burst == 0 {
+            self.update_poll(self.hpoll, now);
+        }
+        statistics
+    }
+
+    /// Handle a kiss-o'-death response with `code` received at `now`.
+    pub fn kiss(&mut self, code: KissOfDeath, now: Instant) {
+        match code {
+            KissOfDeath::Deny | KissOfDeath::Rstr => {
+                debug!("demobilizing association with {}: {:?}", self.addr, code);
+                self.demobilized = Some(code);
+            }
+            KissOfDeath::Rate => {
+                debug!("reducing the poll rate of {}", self.addr);
+                self.burst = 0;
+                self.minpoll = (self.hpoll + 1).min(MAXPOLL);
+                self.update_poll(self.minpoll, now);
+            }
+            _ => (),
+        }
+    }
+
+    // Set the host poll exponent to `hpoll` and schedule the next poll.
+    fn update_poll(&mut self, hpoll: u8, now: Instant) {
+        self.hpoll = hpoll.clamp(self.minpoll, MAXPOLL);
+        self.next_poll = if self.burst > 0 {
+            now + Duration::from_secs(BTIME)
+        } else {
+            // Spread the polls of different associations over up to a sixteenth of the interval.
+            let interval = Duration::from_secs(1 << self.ppoll.min(self.hpoll));
+            let jitter = interval.mul_f64(rand::random::<f64>() / 16.0);
+            self.outdate + interval + jitter
+        };
+        if self.next_poll <= now {
+            self.next_poll = now + Duration::from_secs(1);
+        }
+    }
+}
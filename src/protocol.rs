@@ -4,11 +4,23 @@
 //! `WriteBytesExt` and `ReadBytesExt` traits with the ability to read and write types from the NTP
 //! protocol respectively.
 //!
+//! Without the `std` feature this module builds under `#![no_std]` and without an allocator. The
+//! packet header types remain available and can be decoded from and encoded into byte slices via
+//! `Packet::decode`, `Packet::encode` and `PacketView`, while the reader and writer traits and the
+//! extension field and MAC types require `std`.
+//!
 //! Documentation is largely derived (and often copied directly) from IETF RFC 5905.
 
-use byteorder::{ReadBytesExt, WriteBytesExt, BE};
-use conv::TryFrom;
-use std::{fmt, io};
+use byteorder::{ByteOrder, BE};
+#[cfg(feature = "std")]
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use core::convert::TryFrom;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use core::ops::Range;
+use core::{fmt, str};
+use md5::{Digest, Md5};
+#[cfg(feature = "std")]
+use std::{error, io};
 
 /// NTP port number.
 pub const PORT: u8 = 123;
@@ -34,9 +46,77 @@ pub const MAXDIST: u8 = 1;
 /// Maximum stratum number.
 pub const MAXSTRAT: u8 = 16;
 
+/// Number of clock filter stages.
+pub const NSTAGE: usize = 8;
+
+/// Spike gate, the clock filter offset change above which a sample is a popcorn spike, in
+/// multiples of the peer jitter.
+pub const SGATE: f64 = 3.0;
+
+/// Minimum number of survivors of the cluster algorithm.
+pub const NMIN: usize = 3;
+
+/// Step threshold (s). Offsets larger than this are corrected by stepping the clock.
+pub const STEPT: f64 = 0.128;
+
+/// Stepout threshold (s), the time an offset must persist above `STEPT` before a step.
+pub const WATCH: f64 = 900.0;
+
+/// Panic threshold (s). Offsets larger than this are not corrected.
+pub const PANICT: f64 = 1000.0;
+
+/// Phase-locked loop loop gain.
+pub const PLL: f64 = 65.0;
+
+/// Frequency-locked loop loop gain.
+pub const FLL: u8 = MAXPOLL + 1;
+
+/// Parameter averaging constant.
+pub const AVG: f64 = 4.0;
+
+/// Compromise Allan intercept (s).
+pub const ALLAN: f64 = 1500.0;
+
+/// Poll-adjust threshold.
+pub const LIMIT: i32 = 30;
+
+/// Maximum frequency correction (s/s).
+pub const MAXFREQ: f64 = 500e-6;
+
+/// Poll-adjust gate, in multiples of the clock jitter.
+pub const PGATE: f64 = 4.0;
+
+/// Unreach counter threshold, the number of unanswered polls after which the poll interval is
+/// lengthened.
+pub const UNREACH: u32 = 24;
+
+/// Number of packets in a burst.
+pub const BCOUNT: u32 = 8;
+
+/// Interval between the packets of a burst (s).
+pub const BTIME: u64 = 2;
+
+/// Length of a crypto-NAK in octets, which consists of a zero Key Identifier with no digest.
+pub const CRYPTO_NAK_LEN: usize = 4;
+
+/// Minimum length of a message authentication code in octets (Key Identifier and 128-bit digest).
+pub const MIN_MAC_LEN: usize = 20;
+
+/// Maximum length of a message authentication code in octets (Key Identifier and SHA-1 digest).
+pub const MAX_MAC_LEN: usize = 24;
+
+/// Minimum length of an extension field in octets, including the field header (RFC 7822).
+pub const MIN_EXTENSION_FIELD_LEN: usize = 16;
+
+/// Minimum length of the last extension field in octets when no MAC follows it (RFC 7822).
+///
+/// The larger minimum ensures that the last extension field cannot be mistaken for a MAC.
+pub const MIN_LAST_EXTENSION_FIELD_LEN: usize = 28;
+
 /// A trait for writing any of the Network Time Protocol types to network-endian bytes.
 ///
 /// A blanket implementation is provided for all types that implement `byteorder::WriteBytesExt`.
+#[cfg(feature = "std")]
 pub trait WriteBytes {
     fn write_bytes<P: WriteToBytes>(&mut self, protocol: P) -> io::Result<()>;
 }
@@ -44,20 +124,23 @@ pub trait WriteBytes {
 /// A trait for reading any of the Network Time Protocol types from network-endian bytes.
 ///
 /// A blanket implementation is provided for all types that implement `byteorder::ReadBytesExt`.
+#[cfg(feature = "std")]
 pub trait ReadBytes {
     fn read_bytes<P: ReadFromBytes>(&mut self) -> io::Result<P>;
 }
 
 /// Network Time Protocol types that may be written to network endian bytes.
+#[cfg(feature = "std")]
 pub trait WriteToBytes {
     /// Write the command to bytes.
-    fn write_to_bytes<W: WriteBytesExt>(&self, W) -> io::Result<()>;
+    fn write_to_bytes<W: WriteBytesExt>(&self, writer: W) -> io::Result<()>;
 }
 
 /// Network Time Protocol types that may be read from network endian bytes.
+#[cfg(feature = "std")]
 pub trait ReadFromBytes: Sized {
     /// Read the command from bytes.
-    fn read_from_bytes<R: ReadBytesExt>(R) -> io::Result<Self>;
+    fn read_from_bytes<R: ReadBytesExt>(reader: R) -> io::Result<Self>;
 }
 
 /// Types that have a constant size when written to or read from bytes.
@@ -137,26 +220,24 @@ pub struct DateFormat {
     pub fraction: u64,
 }
 
-custom_derive! {
-    /// A 2-bit integer warning of an impending leap second to be inserted or deleted in the last
-    /// minute of the current month with values defined below:
-    ///
-    /// Note that this field is packed in the actual header.
-    ///
-    /// As the only constructors are via associated constants, it should be impossible to create an
-    /// invalid `LeapIndicator`.
-    #[repr(u8)]
-    #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, TryFrom(u8))]
-    pub enum LeapIndicator {
-        /// No leap required.
-        NoWarning = 0,
-        /// Last minute of the day has 61 seconds.
-        AddOne = 1,
-        /// Last minute of the day has 59 seconds.
-        SubOne = 2,
-        /// Clock unsynchronized.
-        Unknown = 3,
-    }
+/// A 2-bit integer warning of an impending leap second to be inserted or deleted in the last
+/// minute of the current month with values defined below:
+///
+/// Note that this field is packed in the actual header.
+///
+/// As the only constructors are via associated constants, it should be impossible to create an
+/// invalid `LeapIndicator`.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum LeapIndicator {
+    /// No leap required.
+    NoWarning = 0,
+    /// Last minute of the day has 61 seconds.
+    AddOne = 1,
+    /// Last minute of the day has 59 seconds.
+    SubOne = 2,
+    /// Clock unsynchronized.
+    Unknown = 3,
 }
 
 /// A 3-bit integer representing the NTP version number, currently 4.
@@ -168,25 +249,23 @@ custom_derive! {
 #[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Version(u8);
 
-custom_derive! {
-    /// A 3-bit integer representing the mode.
-    ///
-    /// Note that while this struct is 8-bits, this field is packed to 3 in the actual header.
-    ///
-    /// As the only constructors are via associated constants, it should be impossible to create an
-    /// invalid `Mode`.
-    #[repr(u8)]
-    #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, TryFrom(u8))]
-    pub enum Mode {
-        Reserved = 0,
-        SymmetricActive = 1,
-        SymmetricPassive = 2,
-        Client = 3,
-        Server = 4,
-        Broadcast = 5,
-        NtpControlMessage = 6,
-        ReservedForPrivateUse = 7,
-    }
+/// A 3-bit integer representing the mode.
+///
+/// Note that while this struct is 8-bits, this field is packed to 3 in the actual header.
+///
+/// As the only constructors are via associated constants, it should be impossible to create an
+/// invalid `Mode`.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Mode {
+    Reserved = 0,
+    SymmetricActive = 1,
+    SymmetricPassive = 2,
+    Client = 3,
+    Server = 4,
+    Broadcast = 5,
+    NtpControlMessage = 6,
+    ReservedForPrivateUse = 7,
 }
 
 /// An 8-bit integer representing the stratum.
@@ -237,8 +316,21 @@ pub enum ReferenceIdentifier {
     /// not be detected.
     SecondaryOrClient([u8; 4]),
     KissOfDeath(KissOfDeath),
+    /// A reference identifier that is carried through unchanged, such as the identifier of an
+    /// unsynchronized server (stratum 16), of a reserved stratum, or an unregistered primary
+    /// source.
+    Unknown([u8; 4]),
 }
 
+/// A reference identifier viewed as a left-justified, zero-padded ASCII code such as `GPS` or
+/// `XFAC`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct AsciiCode([u8; 4]);
+
+/// A reference identifier viewed as the first four octets of the MD5 hash of an IPv6 address.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Ipv6Hash([u8; 4]);
+
 // Convert an ascii string to a big-endian u32.
 macro_rules! code_to_u32 {
     ($w:expr) => {
@@ -250,77 +342,154 @@ macro_rules! code_to_u32 {
     };
 }
 
-custom_derive! {
-    /// A four-octet, left-justified, zero-padded ASCII string assigned to the reference clock.
-    ///
-    /// The authoritative list of Reference Identifiers is maintained by IANA; however, any string
-    /// beginning with the ASCII character "X" is reserved for unregistered experimentation and
-    /// development.
-    #[repr(u32)]
-    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, TryFrom(u32))]
-    pub enum PrimarySource {
-        Goes = code_to_u32!(b"GOES"),
-        Gps = code_to_u32!(b"GPS\0"),
-        Cdma = code_to_u32!(b"CDMA"),
-        Gal = code_to_u32!(b"GAL\0"),
-        Pps = code_to_u32!(b"PPS\0"),
-        Irig = code_to_u32!(b"IRIG"),
-        Wwvb = code_to_u32!(b"WWVB"),
-        Dcf = code_to_u32!(b"DCF\0"),
-        Hgb = code_to_u32!(b"HGB\0"),
-        Msf = code_to_u32!(b"MSF\0"),
-        Jjy = code_to_u32!(b"JJY\0"),
-        Lorc = code_to_u32!(b"LORC"),
-        Tdf = code_to_u32!(b"TDF\0"),
-        Chu = code_to_u32!(b"CHU\0"),
-        Wwv = code_to_u32!(b"WWV\0"),
-        Wwvh = code_to_u32!(b"WWVH"),
-        Nist = code_to_u32!(b"NIST"),
-        Acts = code_to_u32!(b"ACTS"),
-        Usno = code_to_u32!(b"USNO"),
-        Ptb = code_to_u32!(b"PTB\0"),
-        Goog = code_to_u32!(b"GOOG"),
-        Locl = code_to_u32!(b"LOCL"),
-        Cesm = code_to_u32!(b"CESM"),
-        Rbdm = code_to_u32!(b"RBDM"),
-        Omeg = code_to_u32!(b"OMEG"),
-        Dcn = code_to_u32!(b"DCN\0"),
-        Tsp = code_to_u32!(b"TSP\0"),
-        Dts = code_to_u32!(b"DTS\0"),
-        Atom = code_to_u32!(b"ATOM"),
-        Vlf = code_to_u32!(b"VLF\0"),
-        Opps = code_to_u32!(b"OPPS"),
-        Free = code_to_u32!(b"FREE"),
-        Init = code_to_u32!(b"INIT"),
-        Null = 0,
-    }
-}
-
-custom_derive! {
-    /// If the Stratum field is 0, which implies unspecified or invalid, the Reference Identifier
-    /// field can be used to convey messages useful for status reporting and access control. These
-    /// are called **Kiss-o'-Death** (KoD) packets and the ASCII messages they convey are called
-    /// kiss codes.
-    ///
-    /// The KoD packets got their name because an early use was to tell clients to stop sending
-    /// packets that violate server access controls. The kiss codes can provide useful information
-    /// for an intelligent client, either NTPv4 or SNTPv4. Kiss codes are encoded in four-character
-    /// ASCII strings that are left justified and zero filled. The strings are designed for
-    /// character displays and log files.
-    /// 
-    /// Recipients of kiss codes MUST inspect them and, in the following cases, take the actions
-    /// described.
-    #[repr(u32)]
-    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, TryFrom(u32))]
-    pub enum KissOfDeath {
-        /// The client MUST demobilize any associations to that server and stop sending packets to it.
-        Deny = code_to_u32!(b"DENY"),
-        /// The client MUST demobilize any associations to that server and stop sending packets to it.
-        Rstr = code_to_u32!(b"RSTR"),
-        /// The client MUST immediately reduce its polling interval to that server and continue to
-        /// reduce it each time it receives a RATE kiss code.
-        Rate = code_to_u32!(b"RATE"),
-    }
+/// A four-octet, left-justified, zero-padded ASCII string assigned to the reference clock.
+///
+/// The authoritative list of Reference Identifiers is maintained by IANA; however, any string
+/// beginning with the ASCII character "X" is reserved for unregistered experimentation and
+/// development.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PrimarySource {
+    Goes = code_to_u32!(b"GOES"),
+    Gps = code_to_u32!(b"GPS\0"),
+    Cdma = code_to_u32!(b"CDMA"),
+    Gal = code_to_u32!(b"GAL\0"),
+    Pps = code_to_u32!(b"PPS\0"),
+    Irig = code_to_u32!(b"IRIG"),
+    Wwvb = code_to_u32!(b"WWVB"),
+    Dcf = code_to_u32!(b"DCF\0"),
+    Hgb = code_to_u32!(b"HGB\0"),
+    Msf = code_to_u32!(b"MSF\0"),
+    Jjy = code_to_u32!(b"JJY\0"),
+    Lorc = code_to_u32!(b"LORC"),
+    Tdf = code_to_u32!(b"TDF\0"),
+    Chu = code_to_u32!(b"CHU\0"),
+    Wwv = code_to_u32!(b"WWV\0"),
+    Wwvh = code_to_u32!(b"WWVH"),
+    Nist = code_to_u32!(b"NIST"),
+    Acts = code_to_u32!(b"ACTS"),
+    Usno = code_to_u32!(b"USNO"),
+    Ptb = code_to_u32!(b"PTB\0"),
+    Goog = code_to_u32!(b"GOOG"),
+    Locl = code_to_u32!(b"LOCL"),
+    Cesm = code_to_u32!(b"CESM"),
+    Rbdm = code_to_u32!(b"RBDM"),
+    Omeg = code_to_u32!(b"OMEG"),
+    Dcn = code_to_u32!(b"DCN\0"),
+    Tsp = code_to_u32!(b"TSP\0"),
+    Dts = code_to_u32!(b"DTS\0"),
+    Atom = code_to_u32!(b"ATOM"),
+    Vlf = code_to_u32!(b"VLF\0"),
+    Opps = code_to_u32!(b"OPPS"),
+    Free = code_to_u32!(b"FREE"),
+    Init = code_to_u32!(b"INIT"),
+    Null = 0,
+}
+
+// Every primary source, used to look up a source by its code.
+const PRIMARY_SOURCES: [PrimarySource; 34] = [
+    PrimarySource::Goes,
+    PrimarySource::Gps,
+    PrimarySource::Cdma,
+    PrimarySource::Gal,
+    PrimarySource::Pps,
+    PrimarySource::Irig,
+    PrimarySource::Wwvb,
+    PrimarySource::Dcf,
+    PrimarySource::Hgb,
+    PrimarySource::Msf,
+    PrimarySource::Jjy,
+    PrimarySource::Lorc,
+    PrimarySource::Tdf,
+    PrimarySource::Chu,
+    PrimarySource::Wwv,
+    PrimarySource::Wwvh,
+    PrimarySource::Nist,
+    PrimarySource::Acts,
+    PrimarySource::Usno,
+    PrimarySource::Ptb,
+    PrimarySource::Goog,
+    PrimarySource::Locl,
+    PrimarySource::Cesm,
+    PrimarySource::Rbdm,
+    PrimarySource::Omeg,
+    PrimarySource::Dcn,
+    PrimarySource::Tsp,
+    PrimarySource::Dts,
+    PrimarySource::Atom,
+    PrimarySource::Vlf,
+    PrimarySource::Opps,
+    PrimarySource::Free,
+    PrimarySource::Init,
+    PrimarySource::Null,
+];
+
+/// If the Stratum field is 0, which implies unspecified or invalid, the Reference Identifier
+/// field can be used to convey messages useful for status reporting and access control. These
+/// are called **Kiss-o'-Death** (KoD) packets and the ASCII messages they convey are called
+/// kiss codes.
+///
+/// The KoD packets got their name because an early use was to tell clients to stop sending
+/// packets that violate server access controls. The kiss codes can provide useful information
+/// for an intelligent client, either NTPv4 or SNTPv4. Kiss codes are encoded in four-character
+/// ASCII strings that are left justified and zero filled. The strings are designed for
+/// character displays and log files.
+///
+/// Recipients of kiss codes MUST inspect them and, in the following cases, take the actions
+/// described. The action for each code is given by `KissOfDeath::action`.
+///
+/// The authoritative list of kiss codes is maintained by IANA. Codes missing from the list are
+/// preserved as `KissOfDeath::Unknown`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum KissOfDeath {
+    /// The association belongs to a unicast server.
+    Acst,
+    /// Server authentication failed.
+    Auth,
+    /// Autokey sequence failed.
+    Auto,
+    /// The association belongs to a broadcast server.
+    Bcst,
+    /// Cryptographic authentication or identification failed.
+    Cryp,
+    /// Access denied by remote server. The client MUST demobilize any associations to that server
+    /// and stop sending packets to it.
+    Deny,
+    /// Lost peer in symmetric mode.
+    Drop,
+    /// Access denied due to local policy. The client MUST demobilize any associations to that
+    /// server and stop sending packets to it.
+    Rstr,
+    /// The association has not yet synchronized for the first time.
+    Init,
+    /// The association belongs to a dynamically discovered server.
+    Mcst,
+    /// No key found. Either the key was never installed or is not trusted.
+    Nkey,
+    /// The NTS cookie or authenticator of the request could not be verified (RFC 8915). The
+    /// client should discard its cookies and perform a new NTS-KE handshake.
+    Ntsn,
+    /// Rate exceeded. The client MUST immediately reduce its polling interval to that server and
+    /// continue to reduce it each time it receives a RATE kiss code.
+    Rate,
+    /// Alteration of association from a remote host running ntpdc.
+    Rmot,
+    /// A step change in system time has occurred, but the association has not yet
+    /// resynchronized.
+    Step,
+    /// A kiss code that is not in the registry, such as an experimental "X" code.
+    Unknown([u8; 4]),
+}
+
+/// The action a client must take on receiving a kiss code.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum KissAction {
+    /// Demobilize any associations to the server and stop sending packets to it.
+    Demobilize,
+    /// Reduce the polling interval to the server.
+    ReduceRate,
+    /// No action is required. The code is useful for status reporting and debugging.
+    Informational,
 }
 
 /// **Packet Header** - The most important state variables from an external point of view are the
@@ -437,9 +606,201 @@ pub struct Packet {
     pub transmit_timestamp: TimestampFormat,
 }
 
+/// A 16-bit code identifying the type of an extension field.
+///
+/// The authoritative list of Field Types is maintained by IANA in the "NTP Extension Field Types"
+/// registry.
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ExtensionFieldType(pub u16);
+
+/// **Extension Field** - An optional field following the NTP packet header, as defined by RFC
+/// 7822.
+///
+/// The Length field covers the entire extension field, including the four-octet field header and
+/// any padding. The Value field is zero-padded so that the field is a multiple of four octets long
+/// and at least `MIN_EXTENSION_FIELD_LEN` octets long. If no MAC follows, the last extension field
+/// in a packet must be at least `MIN_LAST_EXTENSION_FIELD_LEN` octets long.
+///
+/// ### Layout
+///
+/// ```ignore
+///  0                   1                   2                   3
+///  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |          Field Type           |            Length             |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// .                                                               .
+/// .                            Value                              .
+/// .                                                               .
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                       Padding (as needed)                     |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// ```
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ExtensionField {
+    /// The type of the field, identifying how its value is interpreted.
+    pub field_type: ExtensionFieldType,
+    /// The value of the field.
+    ///
+    /// Padding is added when the field is written. As the wire format does not distinguish padding
+    /// from data, a field that has been read includes any padding sent by the peer.
+    pub value: Vec<u8>,
+}
+
+/// **Message Authentication Code** - The optional trailer of an NTP packet consisting of the Key
+/// Identifier and Message Digest fields.
+///
+/// The digest is 16 octets for MD5 and AES-128-CMAC and 20 octets for SHA-1. A MAC with a Key
+/// Identifier of zero and an empty digest is a crypto-NAK, sent by a server that failed to
+/// authenticate a request.
+///
+/// ### Layout
+///
+/// ```ignore
+///  0                   1                   2                   3
+///  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                          Key Identifier                       |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                                                               |
+/// |                        dgst (128 or 160)                      |
+/// |                                                               |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// ```
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Mac {
+    pub key_id: u32,
+    pub digest: Vec<u8>,
+}
+
+/// An NTP packet header followed by zero or more extension fields and an optional MAC.
+///
+/// Reading an **ExtendedPacket** consumes the remainder of the reader, so it should be given
+/// exactly one received datagram. A trailer of 4, 20 or 24 octets is read as a MAC, as the
+/// minimum length of the last extension field rules out any ambiguity.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ExtendedPacket {
+    pub packet: Packet,
+    pub extension_fields: Vec<ExtensionField>,
+    pub mac: Option<Mac>,
+}
+
+/// A zero-copy view of an NTP packet header stored in a byte buffer.
+///
+/// The length of the buffer is checked once on construction, after which every header field can
+/// be read straight from the buffer without allocating or copying. When the buffer is mutable,
+/// fields can also be written in place, for example to rewrite the timestamps of a captured
+/// packet. Any octets following the header, such as extension fields or a MAC, are available via
+/// `PacketView::payload`.
+///
+/// ```
+/// use ntp::protocol::{Mode, PacketView, TimestampFormat};
+///
+/// let mut buffer = [0u8; 48];
+/// buffer[0] = 0x23;
+/// let mut view = PacketView::new(&mut buffer[..]).unwrap();
+/// assert_eq!(view.mode(), Mode::Client);
+/// view.set_transmit_timestamp(TimestampFormat { seconds: 1, fraction: 0 });
+/// assert_eq!(buffer[43], 1);
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct PacketView<B> {
+    buffer: B,
+}
+
+// Byte offsets of the header fields within a packet.
+mod field {
+    use core::ops::Range;
+
+    pub const LI_VN_MODE: usize = 0;
+    pub const STRATUM: usize = 1;
+    pub const POLL: usize = 2;
+    pub const PRECISION: usize = 3;
+    pub const ROOT_DELAY: Range<usize> = 4..8;
+    pub const ROOT_DISPERSION: Range<usize> = 8..12;
+    pub const REFERENCE_ID: Range<usize> = 12..16;
+    pub const REFERENCE_TIMESTAMP: Range<usize> = 16..24;
+    pub const ORIGIN_TIMESTAMP: Range<usize> = 24..32;
+    pub const RECEIVE_TIMESTAMP: Range<usize> = 32..40;
+    pub const TRANSMIT_TIMESTAMP: Range<usize> = 40..48;
+
+    // The field containing the octet at `offset` within the header, and its range.
+    pub fn at(offset: usize) -> (super::Field, Range<usize>) {
+        use super::Field;
+        let fields = [
+            (Field::LeapIndicator, LI_VN_MODE..LI_VN_MODE + 1),
+            (Field::Stratum, STRATUM..STRATUM + 1),
+            (Field::Poll, POLL..POLL + 1),
+            (Field::Precision, PRECISION..PRECISION + 1),
+            (Field::RootDelay, ROOT_DELAY),
+            (Field::RootDispersion, ROOT_DISPERSION),
+            (Field::ReferenceId, REFERENCE_ID),
+            (Field::ReferenceTimestamp, REFERENCE_TIMESTAMP),
+            (Field::OriginTimestamp, ORIGIN_TIMESTAMP),
+            (Field::ReceiveTimestamp, RECEIVE_TIMESTAMP),
+        ];
+        fields
+            .iter()
+            .find(|&(_, range)| range.contains(&offset))
+            .cloned()
+            .unwrap_or((Field::TransmitTimestamp, TRANSMIT_TIMESTAMP))
+    }
+}
+
 /// The consecutive types within the first packed byte in the NTP packet.
 pub type PacketByte1 = (LeapIndicator, Version, Mode);
 
+/// A field of an NTP packet, as reported by a **ProtocolError**.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Field {
+    LeapIndicator,
+    Mode,
+    Stratum,
+    Poll,
+    Precision,
+    RootDelay,
+    RootDispersion,
+    ReferenceId,
+    ReferenceTimestamp,
+    OriginTimestamp,
+    ReceiveTimestamp,
+    TransmitTimestamp,
+    ExtensionFieldType,
+    ExtensionFieldLength,
+    ExtensionFieldValue,
+    Mac,
+}
+
+/// An error decoding or encoding protocol types, identifying the field that failed and where it
+/// starts.
+///
+/// Byte offsets are counted from the start of the packet, or from the start of the extension
+/// field when one is decoded or encoded on its own. Encoding fails with `Invalid` when an
+/// extension field is too long for its length field, or a MAC has a length the decoder would not
+/// recognize. When the `std` feature is enabled, a **ProtocolError**
+/// converts into an `io::Error` of kind `UnexpectedEof` if truncated and `InvalidData` otherwise,
+/// and may be recovered from it with `ProtocolError::from_io_error`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ProtocolError {
+    /// The input ended within `field`: `needed` octets were required from `offset` but only
+    /// `available` remained.
+    Truncated {
+        field: Field,
+        offset: usize,
+        needed: usize,
+        available: usize,
+    },
+    /// `field`, starting at `offset`, holds the invalid raw `value`.
+    Invalid {
+        field: Field,
+        offset: usize,
+        value: u32,
+    },
+}
+
 // Inherent implementations.
 
 impl PrimarySource {
@@ -449,6 +810,215 @@ impl PrimarySource {
     }
 }
 
+impl ReferenceIdentifier {
+    /// Interpret the four octets of a reference identifier carried in a packet of the given
+    /// stratum. This never fails; identifiers that cannot be interpreted are kept as
+    /// `ReferenceIdentifier::Unknown`.
+    pub fn from_bytes(stratum: Stratum, bytes: [u8; 4]) -> Self {
+        if stratum == Stratum::UNSPECIFIED {
+            ReferenceIdentifier::KissOfDeath(KissOfDeath::from_bytes(bytes))
+        } else if stratum == Stratum::PRIMARY {
+            match PrimarySource::try_from(code_to_u32!(&bytes)) {
+                Ok(src) => ReferenceIdentifier::PrimarySource(src),
+                Err(_) => ReferenceIdentifier::Unknown(bytes),
+            }
+        } else if stratum.is_secondary() {
+            ReferenceIdentifier::SecondaryOrClient(bytes)
+        } else {
+            ReferenceIdentifier::Unknown(bytes)
+        }
+    }
+
+    /// The reference identifier a server advertises when synchronized to the server at `addr`.
+    pub fn from_addr(addr: IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(addr) => ReferenceIdentifier::SecondaryOrClient(addr.octets()),
+            IpAddr::V6(addr) => {
+                ReferenceIdentifier::SecondaryOrClient(Ipv6Hash::new(&addr).bytes())
+            }
+        }
+    }
+
+    /// The four octets of the reference identifier, exactly as transmitted.
+    pub fn bytes(&self) -> [u8; 4] {
+        match *self {
+            ReferenceIdentifier::PrimarySource(src) => src.bytes(),
+            ReferenceIdentifier::KissOfDeath(kod) => kod.bytes(),
+            ReferenceIdentifier::SecondaryOrClient(arr) | ReferenceIdentifier::Unknown(arr) => arr,
+        }
+    }
+
+    /// View the reference identifier as an ASCII code, as used for reference clocks (stratum 1)
+    /// and kiss codes (stratum 0). Returns `None` if the octets are not a printable,
+    /// zero-padded ASCII string.
+    pub fn as_ascii(&self) -> Option<AsciiCode> {
+        AsciiCode::new(self.bytes())
+    }
+
+    /// View the reference identifier as the IPv4 address of the upstream server, as used by
+    /// secondary servers synchronized over IPv4.
+    pub fn as_ipv4(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.bytes())
+    }
+
+    /// View the reference identifier as an IPv6 address hash, as used by secondary servers
+    /// synchronized over IPv6.
+    pub fn as_ipv6_hash(&self) -> Ipv6Hash {
+        Ipv6Hash::from(self.bytes())
+    }
+
+    /// Whether or not the identifier is an unregistered, experimental code beginning with "X".
+    pub fn is_experimental(&self) -> bool {
+        self.as_ascii().is_some_and(|code| code.as_str().starts_with('X'))
+    }
+}
+
+impl AsciiCode {
+    /// Create an **AsciiCode** if `bytes` is a non-empty, printable ASCII string followed only by
+    /// zero padding.
+    pub fn new(bytes: [u8; 4]) -> Option<Self> {
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        let printable = bytes[..len].iter().all(|&b| (0x20..0x7f).contains(&b));
+        let padded = bytes[len..].iter().all(|&b| b == 0);
+        if len > 0 && printable && padded {
+            Some(AsciiCode(bytes))
+        } else {
+            None
+        }
+    }
+
+    /// The code without its zero padding.
+    pub fn as_str(&self) -> &str {
+        let len = self.0.iter().position(|&b| b == 0).unwrap_or(self.0.len());
+        str::from_utf8(&self.0[..len]).unwrap_or("")
+    }
+
+    /// The four octets of the code, including zero padding.
+    pub fn bytes(&self) -> [u8; 4] {
+        self.0
+    }
+}
+
+impl Ipv6Hash {
+    /// The hash advertised as reference identifier by a server synchronized to `addr`.
+    pub fn new(addr: &Ipv6Addr) -> Self {
+        let digest = Md5::digest(addr.octets());
+        Ipv6Hash([digest[0], digest[1], digest[2], digest[3]])
+    }
+
+    /// Whether or not the hash was derived from `addr`. Useful for detecting timing loops.
+    pub fn matches(&self, addr: &Ipv6Addr) -> bool {
+        *self == Ipv6Hash::new(addr)
+    }
+
+    /// The four octets of the hash.
+    pub fn bytes(&self) -> [u8; 4] {
+        self.0
+    }
+}
+
+impl KissOfDeath {
+    /// Interpret the four octets of a reference identifier as a kiss code.
+    pub fn from_bytes(bytes: [u8; 4]) -> Self {
+        match &bytes {
+            b"ACST" => KissOfDeath::Acst,
+            b"AUTH" => KissOfDeath::Auth,
+            b"AUTO" => KissOfDeath::Auto,
+            b"BCST" => KissOfDeath::Bcst,
+            b"CRYP" => KissOfDeath::Cryp,
+            b"DENY" => KissOfDeath::Deny,
+            b"DROP" => KissOfDeath::Drop,
+            b"RSTR" => KissOfDeath::Rstr,
+            b"INIT" => KissOfDeath::Init,
+            b"MCST" => KissOfDeath::Mcst,
+            b"NKEY" => KissOfDeath::Nkey,
+            b"NTSN" => KissOfDeath::Ntsn,
+            b"RATE" => KissOfDeath::Rate,
+            b"RMOT" => KissOfDeath::Rmot,
+            b"STEP" => KissOfDeath::Step,
+            _ => KissOfDeath::Unknown(bytes),
+        }
+    }
+
+    /// The bytestring representation of the kiss code.
+    pub fn bytes(&self) -> [u8; 4] {
+        match *self {
+            KissOfDeath::Acst => *b"ACST",
+            KissOfDeath::Auth => *b"AUTH",
+            KissOfDeath::Auto => *b"AUTO",
+            KissOfDeath::Bcst => *b"BCST",
+            KissOfDeath::Cryp => *b"CRYP",
+            KissOfDeath::Deny => *b"DENY",
+            KissOfDeath::Drop => *b"DROP",
+            KissOfDeath::Rstr => *b"RSTR",
+            KissOfDeath::Init => *b"INIT",
+            KissOfDeath::Mcst => *b"MCST",
+            KissOfDeath::Nkey => *b"NKEY",
+            KissOfDeath::Ntsn => *b"NTSN",
+            KissOfDeath::Rate => *b"RATE",
+            KissOfDeath::Rmot => *b"RMOT",
+            KissOfDeath::Step => *b"STEP",
+            KissOfDeath::Unknown(bytes) => bytes,
+        }
+    }
+
+    /// The action a client must take on receiving this kiss code.
+    pub fn action(&self) -> KissAction {
+        match *self {
+            KissOfDeath::Deny | KissOfDeath::Rstr => KissAction::Demobilize,
+            KissOfDeath::Rate => KissAction::ReduceRate,
+            _ => KissAction::Informational,
+        }
+    }
+}
+
+impl Packet {
+    /// Decode the packet header at the start of `bytes`.
+    pub fn decode(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        PacketView::new(bytes).map(|view| view.to_packet())
+    }
+
+    /// Encode the packet header into the start of `buffer`, returning the number of octets
+    /// written.
+    pub fn encode(&self, buffer: &mut [u8]) -> Result<usize, ProtocolError> {
+        PacketView::from_packet(buffer, self)?;
+        Ok(Self::PACKED_SIZE_BYTES)
+    }
+}
+
+impl ProtocolError {
+    /// The field that could not be decoded.
+    pub fn field(&self) -> Field {
+        match *self {
+            ProtocolError::Truncated { field, .. } | ProtocolError::Invalid { field, .. } => field,
+        }
+    }
+
+    /// The byte offset at which the field starts.
+    pub fn offset(&self) -> usize {
+        match *self {
+            ProtocolError::Truncated { offset, .. } | ProtocolError::Invalid { offset, .. } => {
+                offset
+            }
+        }
+    }
+
+    /// Whether or not the input was truncated, as opposed to holding an invalid value.
+    pub fn is_truncated(&self) -> bool {
+        match *self {
+            ProtocolError::Truncated { .. } => true,
+            ProtocolError::Invalid { .. } => false,
+        }
+    }
+
+    /// Recover the **ProtocolError** carried by an `io::Error` returned when reading protocol
+    /// types, if any.
+    #[cfg(feature = "std")]
+    pub fn from_io_error(err: &io::Error) -> Option<Self> {
+        err.get_ref().and_then(|err| err.downcast_ref::<ProtocolError>()).cloned()
+    }
+}
+
 impl Version {
     pub const V1: Self = Version(1);
     pub const V2: Self = Version(2);
@@ -484,6 +1054,558 @@ impl Stratum {
     pub fn is_reserved(&self) -> bool {
         *self > Self::MAX
     }
+
+    /// Whether or not the stratum marks a kiss-o'-death packet.
+    pub fn is_kiss_of_death(&self) -> bool {
+        *self == Self::UNSPECIFIED
+    }
+
+    /// Map a stratum received in a packet to the peer stratum used for clock selection.
+    ///
+    /// Stratum 0 (unspecified) maps to `MAXSTRAT` and all other values are unchanged.
+    pub fn from_packet(packet_stratum: Stratum) -> Stratum {
+        if packet_stratum == Self::UNSPECIFIED {
+            Stratum(MAXSTRAT)
+        } else {
+            packet_stratum
+        }
+    }
+
+    /// Map this peer stratum to the stratum transmitted in packets.
+    ///
+    /// Values of `MAXSTRAT` or greater map to 0 (unspecified) and all other values are unchanged.
+    pub fn to_packet(&self) -> Stratum {
+        if self.0 >= MAXSTRAT {
+            Self::UNSPECIFIED
+        } else {
+            *self
+        }
+    }
+}
+
+impl ExtensionFieldType {
+    /// Unique Identifier, protecting against replay attacks (RFC 8915).
+    pub const UNIQUE_IDENTIFIER: Self = ExtensionFieldType(0x0104);
+    /// NTS Cookie, carrying server state for Network Time Security (RFC 8915).
+    pub const NTS_COOKIE: Self = ExtensionFieldType(0x0204);
+    /// NTS Cookie Placeholder, requesting an additional cookie from the server (RFC 8915).
+    pub const NTS_COOKIE_PLACEHOLDER: Self = ExtensionFieldType(0x0304);
+    /// NTS Authenticator and Encrypted Extension Fields (RFC 8915).
+    pub const NTS_AUTHENTICATOR: Self = ExtensionFieldType(0x0404);
+}
+
+#[cfg(feature = "std")]
+impl ExtensionField {
+    /// The length of the field header preceding the value.
+    pub const HEADER_SIZE_BYTES: usize = 4;
+
+    /// Create a new **ExtensionField** of the given type.
+    pub fn new(field_type: ExtensionFieldType, value: Vec<u8>) -> Self {
+        ExtensionField { field_type, value }
+    }
+
+    /// The number of bytes the field occupies when written as any field but the last one in the
+    /// packet, including the header and padding.
+    pub fn packed_size_bytes(&self) -> usize {
+        self.packed_size_bytes_with_min(MIN_EXTENSION_FIELD_LEN)
+    }
+
+    /// Decode the extension field at the start of `bytes`, returning it along with the number of
+    /// octets it occupies.
+    pub fn decode(bytes: &[u8]) -> Result<(Self, usize), ProtocolError> {
+        Self::decode_at(bytes, 0)
+    }
+
+    // Decode the extension field at the start of `bytes`, which begin at `offset` in the packet.
+    fn decode_at(bytes: &[u8], offset: usize) -> Result<(Self, usize), ProtocolError> {
+        if bytes.len() < Self::HEADER_SIZE_BYTES {
+            let (field, start) = if bytes.len() < 2 {
+                (Field::ExtensionFieldType, 0)
+            } else {
+                (Field::ExtensionFieldLength, 2)
+            };
+            return Err(ProtocolError::Truncated {
+                field,
+                offset: offset + start,
+                needed: 2,
+                available: bytes.len() - start,
+            });
+        }
+        let field_type = ExtensionFieldType(BE::read_u16(&bytes[..2]));
+        let len = BE::read_u16(&bytes[2..4]) as usize;
+        if len < MIN_EXTENSION_FIELD_LEN || !len.is_multiple_of(4) {
+            return Err(ProtocolError::Invalid {
+                field: Field::ExtensionFieldLength,
+                offset: offset + 2,
+                value: len as u32,
+            });
+        }
+        if bytes.len() < len {
+            return Err(ProtocolError::Truncated {
+                field: Field::ExtensionFieldValue,
+                offset: offset + Self::HEADER_SIZE_BYTES,
+                needed: len - Self::HEADER_SIZE_BYTES,
+                available: bytes.len() - Self::HEADER_SIZE_BYTES,
+            });
+        }
+        let value = bytes[Self::HEADER_SIZE_BYTES..len].to_vec();
+        Ok((ExtensionField { field_type, value }, len))
+    }
+
+    fn packed_size_bytes_with_min(&self, min_len: usize) -> usize {
+        let len = Self::HEADER_SIZE_BYTES + self.value.len();
+        let aligned = (len + 3) & !3;
+        std::cmp::max(aligned, min_len)
+    }
+
+    // Write the extension field, which begins at `offset` in the packet.
+    fn write_at<W>(&self, mut writer: W, offset: usize, min_len: usize) -> io::Result<()>
+    where
+        W: WriteBytesExt,
+    {
+        let len = self.packed_size_bytes_with_min(min_len);
+        if len > u16::MAX as usize {
+            return Err(ProtocolError::Invalid {
+                field: Field::ExtensionFieldLength,
+                offset: offset + 2,
+                value: len as u32,
+            }
+            .into());
+        }
+        writer.write_u16::<BE>(self.field_type.0)?;
+        writer.write_u16::<BE>(len as u16)?;
+        writer.write_all(&self.value)?;
+        let padding = [0u8; MIN_LAST_EXTENSION_FIELD_LEN];
+        writer.write_all(&padding[..len - Self::HEADER_SIZE_BYTES - self.value.len()])?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl Mac {
+    /// Whether or not this is a crypto-NAK.
+    pub fn is_crypto_nak(&self) -> bool {
+        self.key_id == 0 && self.digest.is_empty()
+    }
+
+    /// The number of bytes the MAC occupies when written.
+    pub fn packed_size_bytes(&self) -> usize {
+        4 + self.digest.len()
+    }
+}
+
+#[cfg(feature = "std")]
+impl ExtendedPacket {
+    /// Decode a packet with its extension fields and MAC from `bytes`, which must hold exactly
+    /// one received datagram.
+    pub fn decode(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        let packet = Packet::decode(bytes)?;
+        let mut offset = Packet::PACKED_SIZE_BYTES;
+        let mut extension_fields = Vec::new();
+        let mut mac = None;
+        while offset < bytes.len() {
+            let rest = &bytes[offset..];
+            match rest.len() {
+                CRYPTO_NAK_LEN | MIN_MAC_LEN | MAX_MAC_LEN => {
+                    let key_id = BE::read_u32(&rest[..4]);
+                    mac = Some(Mac { key_id, digest: rest[4..].to_vec() });
+                    break;
+                }
+                _ => (),
+            }
+            let (field, len) = ExtensionField::decode_at(rest, offset)?;
+            if len == rest.len() && len < MIN_LAST_EXTENSION_FIELD_LEN {
+                return Err(ProtocolError::Invalid {
+                    field: Field::ExtensionFieldLength,
+                    offset: offset + 2,
+                    value: len as u32,
+                });
+            }
+            extension_fields.push(field);
+            offset += len;
+        }
+        Ok(ExtendedPacket { packet, extension_fields, mac })
+    }
+
+    /// The number of bytes the packet occupies when written, including all extension fields and
+    /// the MAC.
+    pub fn packed_size_bytes(&self) -> usize {
+        let fields: usize = self.extension_fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| field.packed_size_bytes_with_min(self.min_extension_field_len(i)))
+            .sum();
+        let mac = self.mac.as_ref().map_or(0, Mac::packed_size_bytes);
+        Packet::PACKED_SIZE_BYTES + fields + mac
+    }
+
+    /// The bytes covered by the MAC, i.e. the header and all extension fields.
+    pub fn authenticated_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(self.packed_size_bytes());
+        self.write_header_and_fields(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn min_extension_field_len(&self, index: usize) -> usize {
+        if index + 1 == self.extension_fields.len() && self.mac.is_none() {
+            MIN_LAST_EXTENSION_FIELD_LEN
+        } else {
+            MIN_EXTENSION_FIELD_LEN
+        }
+    }
+
+    fn write_header_and_fields<W: WriteBytesExt>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_bytes(self.packet)?;
+        let mut offset = Packet::PACKED_SIZE_BYTES;
+        for (i, field) in self.extension_fields.iter().enumerate() {
+            let min_len = self.min_extension_field_len(i);
+            field.write_at(&mut writer, offset, min_len)?;
+            offset += field.packed_size_bytes_with_min(min_len);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Packet> for ExtendedPacket {
+    fn from(packet: Packet) -> Self {
+        ExtendedPacket {
+            packet,
+            extension_fields: Vec::new(),
+            mac: None,
+        }
+    }
+}
+
+impl<B: AsRef<[u8]>> PacketView<B> {
+    /// Create a view of the packet header at the start of `buffer`.
+    ///
+    /// Returns an error if the buffer is shorter than a packet header.
+    pub fn new(buffer: B) -> Result<Self, ProtocolError> {
+        let len = buffer.as_ref().len();
+        if len < Packet::PACKED_SIZE_BYTES {
+            let (field, range) = field::at(len);
+            return Err(ProtocolError::Truncated {
+                field,
+                offset: range.start,
+                needed: range.len(),
+                available: len - range.start,
+            });
+        }
+        Ok(PacketView { buffer })
+    }
+
+    /// Consume the view, returning the underlying buffer.
+    pub fn into_inner(self) -> B {
+        self.buffer
+    }
+
+    /// The octets following the packet header, such as extension fields and a MAC.
+    pub fn payload(&self) -> &[u8] {
+        &self.buffer.as_ref()[Packet::PACKED_SIZE_BYTES..]
+    }
+
+    /// The leap indicator.
+    pub fn leap_indicator(&self) -> LeapIndicator {
+        match self.buffer.as_ref()[field::LI_VN_MODE] >> 6 {
+            0 => LeapIndicator::NoWarning,
+            1 => LeapIndicator::AddOne,
+            2 => LeapIndicator::SubOne,
+            _ => LeapIndicator::Unknown,
+        }
+    }
+
+    /// The version number exactly as sent, which is not validated and may be any value from 0 to
+    /// 7. Use `Version::is_known` to check it.
+    pub fn version(&self) -> Version {
+        Version((self.buffer.as_ref()[field::LI_VN_MODE] >> 3) & 0b111)
+    }
+
+    /// The association mode.
+    pub fn mode(&self) -> Mode {
+        match self.buffer.as_ref()[field::LI_VN_MODE] & 0b111 {
+            0 => Mode::Reserved,
+            1 => Mode::SymmetricActive,
+            2 => Mode::SymmetricPassive,
+            3 => Mode::Client,
+            4 => Mode::Server,
+            5 => Mode::Broadcast,
+            6 => Mode::NtpControlMessage,
+            _ => Mode::ReservedForPrivateUse,
+        }
+    }
+
+    /// The stratum of the sender's clock.
+    pub fn stratum(&self) -> Stratum {
+        Stratum(self.buffer.as_ref()[field::STRATUM])
+    }
+
+    /// The maximum interval between messages as a log2 number of seconds.
+    pub fn poll(&self) -> i8 {
+        self.buffer.as_ref()[field::POLL] as i8
+    }
+
+    /// The precision of the sender's clock as a log2 number of seconds.
+    pub fn precision(&self) -> i8 {
+        self.buffer.as_ref()[field::PRECISION] as i8
+    }
+
+    /// The total round-trip delay to the reference clock.
+    pub fn root_delay(&self) -> ShortFormat {
+        self.short_format(field::ROOT_DELAY)
+    }
+
+    /// The total dispersion to the reference clock.
+    pub fn root_dispersion(&self) -> ShortFormat {
+        self.short_format(field::ROOT_DISPERSION)
+    }
+
+    /// The reference identifier, interpreted according to the stratum.
+    pub fn reference_id(&self) -> ReferenceIdentifier {
+        let bytes = &self.buffer.as_ref()[field::REFERENCE_ID];
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        ReferenceIdentifier::from_bytes(self.stratum(), bytes)
+    }
+
+    /// The time at which the sender's clock was last set or corrected.
+    pub fn reference_timestamp(&self) -> TimestampFormat {
+        self.timestamp(field::REFERENCE_TIMESTAMP)
+    }
+
+    /// The time at which the request this packet answers was sent.
+    pub fn origin_timestamp(&self) -> TimestampFormat {
+        self.timestamp(field::ORIGIN_TIMESTAMP)
+    }
+
+    /// The time at which the request this packet answers arrived at the sender.
+    pub fn receive_timestamp(&self) -> TimestampFormat {
+        self.timestamp(field::RECEIVE_TIMESTAMP)
+    }
+
+    /// The time at which this packet departed the sender.
+    pub fn transmit_timestamp(&self) -> TimestampFormat {
+        self.timestamp(field::TRANSMIT_TIMESTAMP)
+    }
+
+    /// Copy the header fields into an owned **Packet**.
+    pub fn to_packet(&self) -> Packet {
+        Packet {
+            leap_indicator: self.leap_indicator(),
+            version: self.version(),
+            mode: self.mode(),
+            stratum: self.stratum(),
+            poll: self.poll(),
+            precision: self.precision(),
+            root_delay: self.root_delay(),
+            root_dispersion: self.root_dispersion(),
+            reference_id: self.reference_id(),
+            reference_timestamp: self.reference_timestamp(),
+            origin_timestamp: self.origin_timestamp(),
+            receive_timestamp: self.receive_timestamp(),
+            transmit_timestamp: self.transmit_timestamp(),
+        }
+    }
+
+    fn short_format(&self, range: Range<usize>) -> ShortFormat {
+        let bytes = &self.buffer.as_ref()[range];
+        ShortFormat { seconds: BE::read_u16(&bytes[..2]), fraction: BE::read_u16(&bytes[2..]) }
+    }
+
+    fn timestamp(&self, range: Range<usize>) -> TimestampFormat {
+        let bytes = &self.buffer.as_ref()[range];
+        TimestampFormat { seconds: BE::read_u32(&bytes[..4]), fraction: BE::read_u32(&bytes[4..]) }
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> PacketView<B> {
+    /// Write all header fields of `packet` into `buffer` and return a view of it.
+    ///
+    /// Returns an error if the buffer is shorter than a packet header.
+    pub fn from_packet(buffer: B, packet: &Packet) -> Result<Self, ProtocolError> {
+        let mut view = PacketView::new(buffer)?;
+        view.set_packet(packet);
+        Ok(view)
+    }
+
+    /// Overwrite all header fields with those of `packet`. The payload is left unchanged.
+    pub fn set_packet(&mut self, packet: &Packet) {
+        self.set_li_vn_mode(packet.leap_indicator, packet.version, packet.mode);
+        self.set_stratum(packet.stratum);
+        self.set_poll(packet.poll);
+        self.set_precision(packet.precision);
+        self.set_root_delay(packet.root_delay);
+        self.set_root_dispersion(packet.root_dispersion);
+        self.set_reference_id(packet.reference_id);
+        self.set_reference_timestamp(packet.reference_timestamp);
+        self.set_origin_timestamp(packet.origin_timestamp);
+        self.set_receive_timestamp(packet.receive_timestamp);
+        self.set_transmit_timestamp(packet.transmit_timestamp);
+    }
+
+    /// The octets following the packet header, such as extension fields and a MAC.
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer.as_mut()[Packet::PACKED_SIZE_BYTES..]
+    }
+
+    /// Set the leap indicator, version and mode, which share the first octet.
+    ///
+    /// Only the low three bits of the version are kept.
+    pub fn set_li_vn_mode(&mut self, leap_indicator: LeapIndicator, version: Version, mode: Mode) {
+        let byte = (leap_indicator as u8) << 6 | (version.0 & 0b111) << 3 | mode as u8;
+        self.buffer.as_mut()[field::LI_VN_MODE] = byte;
+    }
+
+    /// Set the stratum.
+    pub fn set_stratum(&mut self, stratum: Stratum) {
+        self.buffer.as_mut()[field::STRATUM] = stratum.0;
+    }
+
+    /// Set the poll exponent.
+    pub fn set_poll(&mut self, poll: i8) {
+        self.buffer.as_mut()[field::POLL] = poll as u8;
+    }
+
+    /// Set the precision exponent.
+    pub fn set_precision(&mut self, precision: i8) {
+        self.buffer.as_mut()[field::PRECISION] = precision as u8;
+    }
+
+    /// Set the root delay.
+    pub fn set_root_delay(&mut self, root_delay: ShortFormat) {
+        self.set_short_format(field::ROOT_DELAY, root_delay);
+    }
+
+    /// Set the root dispersion.
+    pub fn set_root_dispersion(&mut self, root_dispersion: ShortFormat) {
+        self.set_short_format(field::ROOT_DISPERSION, root_dispersion);
+    }
+
+    /// Set the reference identifier. It is written as is, whatever the stratum.
+    pub fn set_reference_id(&mut self, reference_id: ReferenceIdentifier) {
+        self.buffer.as_mut()[field::REFERENCE_ID].copy_from_slice(&reference_id.bytes());
+    }
+
+    /// Set the reference timestamp.
+    pub fn set_reference_timestamp(&mut self, timestamp: TimestampFormat) {
+        self.set_timestamp(field::REFERENCE_TIMESTAMP, timestamp);
+    }
+
+    /// Set the origin timestamp.
+    pub fn set_origin_timestamp(&mut self, timestamp: TimestampFormat) {
+        self.set_timestamp(field::ORIGIN_TIMESTAMP, timestamp);
+    }
+
+    /// Set the receive timestamp.
+    pub fn set_receive_timestamp(&mut self, timestamp: TimestampFormat) {
+        self.set_timestamp(field::RECEIVE_TIMESTAMP, timestamp);
+    }
+
+    /// Set the transmit timestamp.
+    pub fn set_transmit_timestamp(&mut self, timestamp: TimestampFormat) {
+        self.set_timestamp(field::TRANSMIT_TIMESTAMP, timestamp);
+    }
+
+    fn set_short_format(&mut self, range: Range<usize>, short: ShortFormat) {
+        let bytes = &mut self.buffer.as_mut()[range];
+        BE::write_u16(&mut bytes[..2], short.seconds);
+        BE::write_u16(&mut bytes[2..], short.fraction);
+    }
+
+    fn set_timestamp(&mut self, range: Range<usize>, timestamp: TimestampFormat) {
+        let bytes = &mut self.buffer.as_mut()[range];
+        BE::write_u32(&mut bytes[..4], timestamp.seconds);
+        BE::write_u32(&mut bytes[4..], timestamp.fraction);
+    }
+}
+
+impl<'a, B: AsRef<[u8]>> From<&'a PacketView<B>> for Packet {
+    fn from(view: &'a PacketView<B>) -> Self {
+        view.to_packet()
+    }
+}
+
+impl From<Packet> for PacketView<[u8; Packet::PACKED_SIZE_BYTES]> {
+    fn from(packet: Packet) -> Self {
+        let mut view = PacketView { buffer: [0; Packet::PACKED_SIZE_BYTES] };
+        view.set_packet(&packet);
+        view
+    }
+}
+
+// Conversion implementations.
+
+impl From<[u8; 4]> for Ipv6Hash {
+    fn from(bytes: [u8; 4]) -> Self {
+        Ipv6Hash(bytes)
+    }
+}
+
+impl TryFrom<u8> for LeapIndicator {
+    type Error = ProtocolError;
+
+    fn try_from(u: u8) -> Result<Self, ProtocolError> {
+        match u {
+            0 => Ok(LeapIndicator::NoWarning),
+            1 => Ok(LeapIndicator::AddOne),
+            2 => Ok(LeapIndicator::SubOne),
+            3 => Ok(LeapIndicator::Unknown),
+            _ => Err(ProtocolError::Invalid {
+                field: Field::LeapIndicator,
+                offset: field::LI_VN_MODE,
+                value: u as u32,
+            }),
+        }
+    }
+}
+
+impl TryFrom<u8> for Mode {
+    type Error = ProtocolError;
+
+    fn try_from(u: u8) -> Result<Self, ProtocolError> {
+        match u {
+            0 => Ok(Mode::Reserved),
+            1 => Ok(Mode::SymmetricActive),
+            2 => Ok(Mode::SymmetricPassive),
+            3 => Ok(Mode::Client),
+            4 => Ok(Mode::Server),
+            5 => Ok(Mode::Broadcast),
+            6 => Ok(Mode::NtpControlMessage),
+            7 => Ok(Mode::ReservedForPrivateUse),
+            _ => Err(ProtocolError::Invalid {
+                field: Field::Mode,
+                offset: field::LI_VN_MODE,
+                value: u as u32,
+            }),
+        }
+    }
+}
+
+impl TryFrom<u32> for PrimarySource {
+    type Error = ProtocolError;
+
+    fn try_from(u: u32) -> Result<Self, ProtocolError> {
+        PRIMARY_SOURCES
+            .iter()
+            .find(|&&src| src as u32 == u)
+            .cloned()
+            .ok_or(ProtocolError::Invalid {
+                field: Field::ReferenceId,
+                offset: field::REFERENCE_ID.start,
+                value: u,
+            })
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<ProtocolError> for io::Error {
+    fn from(err: ProtocolError) -> Self {
+        let kind = if err.is_truncated() {
+            io::ErrorKind::UnexpectedEof
+        } else {
+            io::ErrorKind::InvalidData
+        };
+        io::Error::new(kind, err)
+    }
 }
 
 // Size implementations.
@@ -524,6 +1646,7 @@ impl ConstPackedSizeBytes for Packet {
 
 // Writer implementations.
 
+#[cfg(feature = "std")]
 impl<W> WriteBytes for W
 where
     W: WriteBytesExt,
@@ -533,7 +1656,8 @@ where
     }
 }
 
-impl<'a, P> WriteToBytes for &'a P
+#[cfg(feature = "std")]
+impl<P> WriteToBytes for &P
 where
     P: WriteToBytes,
 {
@@ -542,6 +1666,7 @@ where
     }
 }
 
+#[cfg(feature = "std")]
 impl WriteToBytes for ShortFormat {
     fn write_to_bytes<W: WriteBytesExt>(&self, mut writer: W) -> io::Result<()> {
         writer.write_u16::<BE>(self.seconds)?;
@@ -550,6 +1675,7 @@ impl WriteToBytes for ShortFormat {
     }
 }
 
+#[cfg(feature = "std")]
 impl WriteToBytes for TimestampFormat {
     fn write_to_bytes<W: WriteBytesExt>(&self, mut writer: W) -> io::Result<()> {
         writer.write_u32::<BE>(self.seconds)?;
@@ -558,6 +1684,7 @@ impl WriteToBytes for TimestampFormat {
     }
 }
 
+#[cfg(feature = "std")]
 impl WriteToBytes for DateFormat {
     fn write_to_bytes<W: WriteBytesExt>(&self, mut writer: W) -> io::Result<()> {
         writer.write_i32::<BE>(self.era_number)?;
@@ -567,6 +1694,7 @@ impl WriteToBytes for DateFormat {
     }
 }
 
+#[cfg(feature = "std")]
 impl WriteToBytes for Stratum {
     fn write_to_bytes<W: WriteBytesExt>(&self, mut writer: W) -> io::Result<()> {
         writer.write_u8(self.0)?;
@@ -574,23 +1702,15 @@ impl WriteToBytes for Stratum {
     }
 }
 
+#[cfg(feature = "std")]
 impl WriteToBytes for ReferenceIdentifier {
     fn write_to_bytes<W: WriteBytesExt>(&self, mut writer: W) -> io::Result<()> {
-        match *self {
-            ReferenceIdentifier::KissOfDeath(kod) => {
-                writer.write_u32::<BE>(kod as u32)?;
-            }
-            ReferenceIdentifier::PrimarySource(src) => {
-                writer.write_u32::<BE>(src as u32)?;
-            }
-            ReferenceIdentifier::SecondaryOrClient(arr) => {
-                writer.write_u32::<BE>(code_to_u32!(&arr))?;
-            }
-        }
+        writer.write_all(&self.bytes())?;
         Ok(())
     }
 }
 
+#[cfg(feature = "std")]
 impl WriteToBytes for (LeapIndicator, Version, Mode) {
     fn write_to_bytes<W: WriteBytesExt>(&self, mut writer: W) -> io::Result<()> {
         let (li, vn, mode) = *self;
@@ -603,6 +1723,7 @@ impl WriteToBytes for (LeapIndicator, Version, Mode) {
     }
 }
 
+#[cfg(feature = "std")]
 impl WriteToBytes for Packet {
     fn write_to_bytes<W: WriteBytesExt>(&self, mut writer: W) -> io::Result<()> {
         let li_vn_mode = (self.leap_indicator, self.version, self.mode);
@@ -621,8 +1742,48 @@ impl WriteToBytes for Packet {
     }
 }
 
+#[cfg(feature = "std")]
+impl WriteToBytes for ExtensionField {
+    fn write_to_bytes<W: WriteBytesExt>(&self, writer: W) -> io::Result<()> {
+        self.write_at(writer, 0, MIN_EXTENSION_FIELD_LEN)
+    }
+}
+
+#[cfg(feature = "std")]
+impl WriteToBytes for Mac {
+    fn write_to_bytes<W: WriteBytesExt>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_u32::<BE>(self.key_id)?;
+        writer.write_all(&self.digest)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl WriteToBytes for ExtendedPacket {
+    fn write_to_bytes<W: WriteBytesExt>(&self, mut writer: W) -> io::Result<()> {
+        self.write_header_and_fields(&mut writer)?;
+        if let Some(ref mac) = self.mac {
+            let len = mac.packed_size_bytes();
+            match len {
+                CRYPTO_NAK_LEN | MIN_MAC_LEN | MAX_MAC_LEN => (),
+                _ => {
+                    return Err(ProtocolError::Invalid {
+                        field: Field::Mac,
+                        offset: self.packed_size_bytes() - len,
+                        value: len as u32,
+                    }
+                    .into())
+                }
+            }
+            writer.write_bytes(mac)?;
+        }
+        Ok(())
+    }
+}
+
 // Reader implementations.
 
+#[cfg(feature = "std")]
 impl<R> ReadBytes for R
 where
     R: ReadBytesExt,
@@ -632,6 +1793,7 @@ where
     }
 }
 
+#[cfg(feature = "std")]
 impl ReadFromBytes for ShortFormat {
     fn read_from_bytes<R: ReadBytesExt>(mut reader: R) -> io::Result<Self> {
         let seconds = reader.read_u16::<BE>()?;
@@ -641,6 +1803,7 @@ impl ReadFromBytes for ShortFormat {
     }
 }
 
+#[cfg(feature = "std")]
 impl ReadFromBytes for TimestampFormat {
     fn read_from_bytes<R: ReadBytesExt>(mut reader: R) -> io::Result<Self> {
         let seconds = reader.read_u32::<BE>()?;
@@ -650,6 +1813,7 @@ impl ReadFromBytes for TimestampFormat {
     }
 }
 
+#[cfg(feature = "std")]
 impl ReadFromBytes for DateFormat {
     fn read_from_bytes<R: ReadBytesExt>(mut reader: R) -> io::Result<Self> {
         let era_number = reader.read_i32::<BE>()?;
@@ -660,6 +1824,7 @@ impl ReadFromBytes for DateFormat {
     }
 }
 
+#[cfg(feature = "std")]
 impl ReadFromBytes for Stratum {
     fn read_from_bytes<R: ReadBytesExt>(mut reader: R) -> io::Result<Self> {
         let stratum = Stratum(reader.read_u8()?);
@@ -667,84 +1832,68 @@ impl ReadFromBytes for Stratum {
     }
 }
 
+#[cfg(feature = "std")]
 impl ReadFromBytes for (LeapIndicator, Version, Mode) {
     fn read_from_bytes<R: ReadBytesExt>(mut reader: R) -> io::Result<Self> {
         let li_vn_mode = reader.read_u8()?;
         let li_u8 = li_vn_mode >> 6;
         let vn_u8 = (li_vn_mode >> 3) & 0b111;
         let mode_u8 = li_vn_mode & 0b111;
-        let li = match LeapIndicator::try_from(li_u8).ok() {
-            Some(li) => li,
-            None => {
-                let err_msg = "unknown leap indicator";
-                return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
-            },
-        };
+        let li = LeapIndicator::try_from(li_u8)?;
         let vn = Version(vn_u8);
-        let mode = match Mode::try_from(mode_u8).ok() {
-            Some(mode) => mode,
-            None => {
-                let err_msg = "unknown association mode";
-                return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
-            },
-        };
+        let mode = Mode::try_from(mode_u8)?;
         Ok((li, vn, mode))
     }
 }
 
+#[cfg(feature = "std")]
 impl ReadFromBytes for Packet {
     fn read_from_bytes<R: ReadBytesExt>(mut reader: R) -> io::Result<Self> {
-        let (leap_indicator, version, mode) = reader.read_bytes()?;
-        let stratum = reader.read_bytes::<Stratum>()?;
-        let poll = reader.read_i8()?;
-        let precision = reader.read_i8()?;
-        let root_delay = reader.read_bytes()?;
-        let root_dispersion = reader.read_bytes()?;
-        let reference_id = {
-            let u = reader.read_u32::<BE>()?;
-            if stratum == Stratum::PRIMARY {
-                match PrimarySource::try_from(u) {
-                    Ok(src) => ReferenceIdentifier::PrimarySource(src),
-                    Err(_) => match KissOfDeath::try_from(u) {
-                        Ok(kod) => ReferenceIdentifier::KissOfDeath(kod),
-                        Err(_) => {
-                            let err_msg = "unknown reference id";
-                            return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
-                        }
-                    },
-                }
-            } else if stratum.is_secondary() {
-                let arr = be_u32_to_bytes(u);
-                ReferenceIdentifier::SecondaryOrClient(arr)
-            } else {
-                let err_msg = "unsupported stratum";
-                return Err(io::Error::new(io::ErrorKind::InvalidData, err_msg));
+        let mut bytes = [0u8; Packet::PACKED_SIZE_BYTES];
+        let len = read_up_to(&mut reader, &mut bytes)?;
+        Ok(Packet::decode(&bytes[..len])?)
+    }
+}
+
+#[cfg(feature = "std")]
+impl ReadFromBytes for ExtensionField {
+    fn read_from_bytes<R: ReadBytesExt>(mut reader: R) -> io::Result<Self> {
+        let mut bytes = vec![0u8; ExtensionField::HEADER_SIZE_BYTES];
+        let mut len = read_up_to(&mut reader, &mut bytes)?;
+        if len == ExtensionField::HEADER_SIZE_BYTES {
+            let field_len = BE::read_u16(&bytes[2..4]) as usize;
+            if field_len > len {
+                bytes.resize(field_len, 0);
+                len += read_up_to(&mut reader, &mut bytes[len..])?;
             }
-        };
-        let reference_timestamp = reader.read_bytes()?;
-        let origin_timestamp = reader.read_bytes()?;
-        let receive_timestamp = reader.read_bytes()?;
-        let transmit_timestamp = reader.read_bytes()?;
-        Ok(Packet {
-            leap_indicator,
-            version,
-            mode,
-            stratum,
-            poll,
-            precision,
-            root_delay,
-            root_dispersion,
-            reference_id,
-            reference_timestamp,
-            origin_timestamp,
-            receive_timestamp,
-            transmit_timestamp,
-        })
+        }
+        let (field, _) = ExtensionField::decode(&bytes[..len])?;
+        Ok(field)
+    }
+}
+
+#[cfg(feature = "std")]
+impl ReadFromBytes for Mac {
+    fn read_from_bytes<R: ReadBytesExt>(mut reader: R) -> io::Result<Self> {
+        let key_id = reader.read_u32::<BE>()?;
+        let mut digest = Vec::new();
+        reader.read_to_end(&mut digest)?;
+        Ok(Mac { key_id, digest })
+    }
+}
+
+#[cfg(feature = "std")]
+impl ReadFromBytes for ExtendedPacket {
+    fn read_from_bytes<R: ReadBytesExt>(mut reader: R) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Ok(ExtendedPacket::decode(&bytes)?)
     }
 }
 
 // Manual default implementations.
 
+#[allow(clippy::derivable_impls)]
 impl Default for LeapIndicator {
     fn default() -> Self {
         LeapIndicator::NoWarning
@@ -753,16 +1902,114 @@ impl Default for LeapIndicator {
 
 // Display implementations.
 
+impl fmt::Display for ReferenceIdentifier {
+    /// Reference clocks and kiss codes are shown as ASCII and upstream servers as an IPv4
+    /// address. Unknown identifiers are shown as ASCII where possible.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReferenceIdentifier::PrimarySource(src) => write!(f, "{}", src),
+            ReferenceIdentifier::KissOfDeath(kod) => write!(f, "{}", kod),
+            ReferenceIdentifier::SecondaryOrClient(_) => write!(f, "{}", self.as_ipv4()),
+            ReferenceIdentifier::Unknown(_) => match self.as_ascii() {
+                Some(code) => write!(f, "{}", code),
+                None => write!(f, "{}", self.as_ipv4()),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Field::LeapIndicator => "leap indicator",
+            Field::Mode => "mode",
+            Field::Stratum => "stratum",
+            Field::Poll => "poll",
+            Field::Precision => "precision",
+            Field::RootDelay => "root delay",
+            Field::RootDispersion => "root dispersion",
+            Field::ReferenceId => "reference id",
+            Field::ReferenceTimestamp => "reference timestamp",
+            Field::OriginTimestamp => "origin timestamp",
+            Field::ReceiveTimestamp => "receive timestamp",
+            Field::TransmitTimestamp => "transmit timestamp",
+            Field::ExtensionFieldType => "extension field type",
+            Field::ExtensionFieldLength => "extension field length",
+            Field::ExtensionFieldValue => "extension field value",
+            Field::Mac => "MAC",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProtocolError::Truncated { field, offset, needed, available } => write!(
+                f,
+                "truncated {} at offset {}: needed {} octets but {} available",
+                field, offset, needed, available
+            ),
+            ProtocolError::Invalid { field, offset, value } => {
+                write!(f, "invalid {} {:#x} at offset {}", field, value, offset)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for ProtocolError {}
+
+impl fmt::Display for AsciiCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl fmt::Display for Ipv6Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}{:02x}{:02x}{:02x}", self.0[0], self.0[1], self.0[2], self.0[3])
+    }
+}
+
+impl fmt::Display for KissOfDeath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_code(f, self.bytes())
+    }
+}
+
 impl fmt::Display for PrimarySource {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let bytes = self.bytes();
-        let s = String::from_utf8_lossy(&bytes);
-        write!(f, "{}", s)
+        fmt_code(f, self.bytes())
     }
 }
 
 // Utility functions.
 
+// Read from `reader` until `buf` is full or the reader is exhausted, returning the number of
+// octets read.
+#[cfg(feature = "std")]
+fn read_up_to<R: io::Read>(mut reader: R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(len)
+}
+
+// Write a four-octet code as ASCII, escaping octets that are not printable.
+fn fmt_code(f: &mut fmt::Formatter, bytes: [u8; 4]) -> fmt::Result {
+    match AsciiCode::new(bytes) {
+        Some(code) => write!(f, "{}", code),
+        None => write!(f, "{}", bytes.escape_ascii()),
+    }
+}
+
 fn be_u32_to_bytes(u: u32) -> [u8; 4] {
     [
         (u >> 24 & 0xff) as u8,
@@ -0,0 +1,271 @@
+//! Querying several servers for a combined estimate of the local clock offset, in the manner of
+//! `ntpdate -q` or `sntp`.
+//!
+//! A **Query** sends a number of samples to every server, in rounds spaced apart by a fixed
+//! interval, and keeps the sample with the lowest round-trip delay from each, along with the
+//! jitter of the other samples around it. The servers are then mitigated as a daemon would
+//! mitigate its peers: clock selection rejects the falsetickers, the cluster algorithm prunes the
+//! outliers among the truechimers, and the offsets of the survivors are combined weighted by the
+//! inverse of their root distance.
+//!
+//! ```no_run
+//! use ntp::client::Client;
+//! use ntp::query::Query;
+//! use std::time::Duration;
+//!
+//! let servers = ["0.pool.ntp.org:123", "1.pool.ntp.org:123", "2.pool.ntp.org:123"];
+//! let report = Query::new(Client::new().timeout(Duration::from_secs(1)))
+//!     .samples(4)
+//!     .run(&servers)
+//!     .unwrap();
+//! for server in &report.servers {
+//!     println!("{}: {:?} truechimer: {}", server.addr, server.best, server.truechimer);
+//! }
+//! if let Some(estimate) = report.estimate {
+//!     println!("offset: {} +/- {} s", estimate.offset, estimate.error);
+//! }
+//! ```
+
+use crate::burst;
+use crate::client::{self, Client};
+use crate::cluster;
+use crate::measurement::Measurement;
+use crate::selection::{self, Candidate, Verdict};
+use crate::transport::{Transport, UdpTransport};
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+/// The default number of samples sent to each server.
+pub const DEFAULT_SAMPLES: u32 = 4;
+
+/// The default interval between rounds of samples.
+pub const DEFAULT_SPACING: Duration = Duration::from_secs(1);
+
+/// A query of several servers, configured by chaining its builder methods.
+#[derive(Clone, Debug)]
+pub struct Query {
+    client: Client,
+    samples: u32,
+    spacing: Duration,
+}
+
+/// The outcome of a **Query**.
+#[derive(Debug)]
+pub struct QueryReport {
+    /// The combined estimate, or `None` if no majority of the servers that responded agree.
+    pub estimate: Option<Estimate>,
+    /// A report for each server, in the order the servers were given.
+    pub servers: Vec<ServerReport>,
+}
+
+/// The combined estimate of the offset of the local clock, in seconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Estimate {
+    /// The offset of the survivors, weighted by the inverse of their root distance.
+    pub offset: f64,
+    /// A bound on the error of `offset`, assuming the correctness intervals of the survivors
+    /// contain the true time.
+    pub error: f64,
+    /// The system jitter.
+    pub jitter: f64,
+    /// The address of the best survivor.
+    pub system_peer: SocketAddr,
+    /// The number of truechimers.
+    pub truechimers: usize,
+    /// The number of truechimers surviving the cluster algorithm.
+    pub survivors: usize,
+}
+
+/// The samples taken from a single server.
+#[derive(Debug)]
+pub struct ServerReport {
+    /// The address of the server.
+    pub addr: SocketAddr,
+    /// The number of samples sent.
+    pub sent: u32,
+    /// Every valid response received, in the order the samples were sent.
+    pub measurements: Vec<Measurement>,
+    /// The valid response with the lowest round-trip delay, if any.
+    pub best: Option<Measurement>,
+    /// The root mean square difference between the offsets of the other responses and the best.
+    pub jitter: f64,
+    /// The error of the most recent failed sample, if any.
+    pub error: Option<io::Error>,
+    /// Whether or not the server was found to be a truechimer.
+    pub truechimer: bool,
+    /// Whether or not the server survived clustering and was combined into the estimate.
+    pub survivor: bool,
+}
+
+// Inherent implementations.
+
+impl Query {
+    /// Create a query sending `DEFAULT_SAMPLES` requests configured by `client` to each server,
+    /// `DEFAULT_SPACING` apart.
+    pub fn new(client: Client) -> Self {
+        Query {
+            client,
+            samples: DEFAULT_SAMPLES,
+            spacing: DEFAULT_SPACING,
+        }
+    }
+
+    /// Set the number of samples sent to each server.
+    pub fn samples(mut self, samples: u32) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    /// Set the interval between rounds of samples.
+    pub fn spacing(mut self, spacing: Duration) -> Self {
+        self.spacing = spacing;
+        self
+    }
+
+    /// Query every server in `addrs`.
+    ///
+    ///   returns an error if an address cannot be resolved. Failures of individual samples are
+    ///   recorded in the report of their server.
+    pub fn run<I>(&self, addrs: I) -> io::Result<QueryReport>
+    where
+        I: IntoIterator,
+        I::Item: ToSocketAddrs,
+    {
+        self.run_with(&mut UdpTransport::new(&self.client), addrs)
+    }
+
+    /// Query every server in `addrs` over `transport`, as with `Client::request_with`.
+    pub fn run_with<T, I>(&self, transport: &mut T, addrs: I) -> io::Result<QueryReport>
+    where
+        T: Transport,
+        I: IntoIterator,
+        I::Item: ToSocketAddrs,
+    {
+        let addrs = resolve(addrs)?;
+        let mut reports: Vec<ServerReport> = addrs.into_iter().map(ServerReport::new).collect();
+        for round in 0..self.samples {
+            if round > 0 {
+                thread::sleep(self.spacing);
+            }
+            for report in &mut reports {
+                let result = self.client.request_with(transport, report.addr);
+                report.record(result);
+            }
+        }
+        Ok(QueryReport::new(reports))
+    }
+}
+
+impl Default for Query {
+    fn default() -> Self {
+        Query::new(Client::new())
+    }
+}
+
+impl QueryReport {
+    // Mitigate the best samples of `servers` into an estimate.
+    fn new(mut servers: Vec<ServerReport>) -> Self {
+        let mut candidates = Vec::new();
+        let mut indices = Vec::new();
+        for (i, server) in servers.iter_mut().enumerate() {
+            if let Some(ref best) = server.best {
+                server.jitter = burst::jitter(best, &server.measurements);
+                let mut candidate = Candidate::from(best);
+                candidate.jitter = server.jitter;
+                candidate.root_distance += server.jitter;
+                candidates.push(candidate);
+                indices.push(i);
+            }
+        }
+        let selection = match selection::select(&candidates) {
+            Some(selection) => selection,
+            None => {
+                return QueryReport {
+                    estimate: None,
+                    servers,
+                }
+            }
+        };
+        let survivors = cluster::cluster(&candidates, &selection);
+        let system = match cluster::combine(&candidates, &survivors) {
+            Some(system) => system,
+            None => {
+                return QueryReport {
+                    estimate: None,
+                    servers,
+                }
+            }
+        };
+        for (c, verdict) in selection.verdicts.iter().enumerate() {
+            servers[indices[c]].truechimer = *verdict == Verdict::Truechimer;
+        }
+        for &c in &survivors {
+            servers[indices[c]].survivor = true;
+        }
+
+        // The true offset lies within the correctness interval of every survivor, so the error of
+        // the combined offset is at most its distance from any survivor's offset plus that
+        // survivor's root distance, and the smallest such bound is taken.
+        let error = survivors
+            .iter()
+            .map(|&c| (candidates[c].offset - system.offset).abs() + candidates[c].root_distance)
+            .fold(f64::INFINITY, f64::min);
+        let estimate = Estimate {
+            offset: system.offset,
+            error,
+            jitter: system.jitter,
+            system_peer: servers[indices[system.system_peer]].addr,
+            truechimers: selection.truechimers().count(),
+            survivors: survivors.len(),
+        };
+        QueryReport {
+            estimate: Some(estimate),
+            servers,
+        }
+    }
+}
+
+impl ServerReport {
+    fn new(addr: SocketAddr) -> Self {
+        ServerReport {
+            addr,
+            sent: 0,
+            measurements: Vec::new(),
+            best: None,
+            jitter: 0.0,
+            error: None,
+            truechimer: false,
+            survivor: false,
+        }
+    }
+
+    // Record the result of a sample, keeping it if it has the lowest delay so far.
+    fn record(&mut self, result: io::Result<Measurement>) {
+        self.sent += 1;
+        match result {
+            Ok(measurement) => {
+                if self.best.is_none_or(|best| measurement.delay < best.delay) {
+                    self.best = Some(measurement);
+                }
+                self.measurements.push(measurement);
+            }
+            Err(err) => {
+                debug!("sample from {} failed: {}", self.addr, err);
+                self.error = Some(err);
+            }
+        }
+    }
+}
+
+// Utility functions.
+
+// Resolve each of `addrs` to its first socket address.
+fn resolve<I>(addrs: I) -> io::Result<Vec<SocketAddr>>
+where
+    I: IntoIterator,
+    I::Item: ToSocketAddrs,
+{
+    addrs.into_iter().map(client::resolve).collect()
+}
@@ -0,0 +1,203 @@
+//! The clock selection algorithm of RFC 5905 section 11.2.1.
+//!
+//! Each **Candidate** peer defines a correctness interval, its offset plus or minus its root
+//! distance, that contains the true time if the peer is correct. The selection algorithm, a
+//! variant of Marzullo's algorithm, finds the smallest interval containing points from the
+//! correctness intervals of the largest possible majority of the candidates. Candidates whose
+//! offset lies within it are the truechimers, and the rest are falsetickers.
+//!
+//! Candidates whose stratum is unspecified or unsynchronized, or whose root distance is at least
+//! `MAXDIST`, cannot be trusted and are excluded before selection.
+//!
+//! ```
+//! use ntp::protocol::Stratum;
+//! use ntp::selection::{select, Candidate, Verdict};
+//!
+//! let candidate = |offset, root_distance| Candidate {
+//!     offset,
+//!     jitter: 0.0,
+//!     root_distance,
+//!     stratum: Stratum::PRIMARY,
+//! };
+//! let candidates = [candidate(0.10, 0.02), candidate(0.11, 0.01), candidate(0.30, 0.05)];
+//! let selection = select(&candidates).unwrap();
+//! assert_eq!(selection.verdicts[2], Verdict::Falseticker);
+//! assert_eq!(selection.truechimers().collect::<Vec<_>>(), [0, 1]);
+//! ```
+
+use crate::filter::PeerStatistics;
+use crate::measurement::{difference, short_to_secs, Measurement};
+use crate::protocol::{Packet, Stratum, TimestampFormat, MAXDIST, MAXSTRAT, MINDISP, TOLERANCE};
+
+/// A peer taking part in clock selection. All durations are in seconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Candidate {
+    /// The offset of the peer clock relative to the local clock.
+    pub offset: f64,
+    /// The peer jitter.
+    pub jitter: f64,
+    /// The maximum error of the peer clock relative to the primary reference source, the half
+    /// width of the correctness interval.
+    pub root_distance: f64,
+    /// The stratum of the peer.
+    pub stratum: Stratum,
+}
+
+/// The outcome of clock selection for a single candidate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// The offset of the candidate lies within the intersection interval.
+    Truechimer,
+    /// The offset of the candidate lies outside the intersection interval.
+    Falseticker,
+    /// The candidate was excluded from selection by its stratum or root distance.
+    Unfit,
+}
+
+/// The result of clock selection.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Selection {
+    /// The lower bound of the intersection interval.
+    pub low: f64,
+    /// The upper bound of the intersection interval.
+    pub high: f64,
+    /// The verdict for each candidate, in the order the candidates were given.
+    pub verdicts: Vec<Verdict>,
+}
+
+// Inherent implementations.
+
+impl Candidate {
+    /// Create a candidate from the last `packet` received from a peer and the `statistics` of its
+    /// clock filter, at the local time `now`.
+    ///
+    /// The root distance is accumulated following RFC 5905: half the sum of the root delay and
+    /// the peer delay, plus the root dispersion, the peer dispersion grown at `TOLERANCE` since
+    /// the sample was taken, and the peer jitter.
+    pub fn new(packet: &Packet, statistics: &PeerStatistics, now: TimestampFormat) -> Self {
+        let root_delay = short_to_secs(packet.root_delay);
+        let root_dispersion = short_to_secs(packet.root_dispersion);
+        let age = difference(now, statistics.epoch).max(0.0);
+        let root_distance = (root_delay + statistics.delay).max(MINDISP) / 2.0
+            + root_dispersion
+            + statistics.dispersion
+            + TOLERANCE * age
+            + statistics.jitter;
+        Candidate {
+            offset: statistics.offset,
+            jitter: statistics.jitter,
+            root_distance,
+            stratum: packet.stratum,
+        }
+    }
+
+    /// Whether or not the candidate may take part in selection, having a specified stratum below
+    /// `MAXSTRAT` and a root distance below `MAXDIST`.
+    pub fn is_fit(&self) -> bool {
+        self.stratum != Stratum::UNSPECIFIED
+            && self.stratum.0 < MAXSTRAT
+            && self.root_distance < MAXDIST as f64
+    }
+}
+
+impl Selection {
+    /// The indices of the truechimers among the candidates.
+    pub fn truechimers(&self) -> impl Iterator<Item = usize> + '_ {
+        self.verdicts
+            .iter()
+            .enumerate()
+            .filter(|&(_, verdict)| *verdict == Verdict::Truechimer)
+            .map(|(i, _)| i)
+    }
+}
+
+impl<'a> From<&'a Measurement> for Candidate {
+    /// A candidate from a single measurement, with no jitter.
+    fn from(measurement: &'a Measurement) -> Self {
+        Candidate {
+            offset: measurement.offset,
+            jitter: 0.0,
+            root_distance: measurement.root_distance,
+            stratum: measurement.packet.stratum,
+        }
+    }
+}
+
+// Utility functions.
+
+/// Select the truechimers among `candidates`.
+///
+/// Returns `None` if no majority of the fit candidates have intersecting correctness intervals,
+/// in which case none of them can be trusted.
+pub fn select(candidates: &[Candidate]) -> Option<Selection> {
+    // The endpoints and midpoint of every correctness interval, with a type of -1 for a lower
+    // endpoint, 0 for a midpoint and 1 for an upper endpoint. At equal values lower endpoints
+    // sort first, so that touching intervals intersect.
+    let mut points: Vec<(f64, i8)> = Vec::new();
+    for candidate in candidates.iter().filter(|c| c.is_fit()) {
+        points.push((candidate.offset - candidate.root_distance, -1));
+        points.push((candidate.offset, 0));
+        points.push((candidate.offset + candidate.root_distance, 1));
+    }
+    points.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    let n = points.len() / 3;
+
+    // Allow an increasing number of falsetickers until a majority of the intervals intersect,
+    // and no more midpoints than allowed fall outside the intersection.
+    let mut interval = None;
+    for allow in (0..n).take_while(|allow| 2 * allow < n) {
+        let mut found = 0;
+        let mut chime = 0;
+        let mut low = None;
+        for &(value, kind) in &points {
+            chime -= kind as isize;
+            if chime >= (n - allow) as isize {
+                low = Some(value);
+                break;
+            }
+            if kind == 0 {
+                found += 1;
+            }
+        }
+        chime = 0;
+        let mut high = None;
+        for &(value, kind) in points.iter().rev() {
+            chime += kind as isize;
+            if chime >= (n - allow) as isize {
+                high = Some(value);
+                break;
+            }
+            if kind == 0 {
+                found += 1;
+            }
+        }
+        if found > allow {
+            continue;
+        }
+        if let (Some(low), Some(high)) = (low, high) {
+            if high > low {
+                interval = Some((low, high));
+                break;
+            }
+        }
+    }
+    let (low, high) = interval?;
+
+    let verdicts = candidates
+        .iter()
+        .map(|candidate| {
+            if !candidate.is_fit() {
+                Verdict::Unfit
+            } else if candidate.offset < low || candidate.offset > high {
+                Verdict::Falseticker
+            } else {
+                Verdict::Truechimer
+            }
+        })
+        .collect();
+    Some(Selection {
+        low,
+        high,
+        verdicts,
+    })
+}
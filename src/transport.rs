@@ -0,0 +1,240 @@
+//! The datagram transports the blocking client sends requests over.
+//!
+//! By default a `Client` sends each attempt from a new `UdpSocket`. `Client::request_with` sends
+//! over any **Transport** instead, such as an existing `UdpSocket`, or a **ScriptedTransport**
+//! that replays scripted server responses so that client behavior can be tested without a network.
+//!
+//! ```
+//! use ntp::client::Client;
+//! use ntp::protocol::*;
+//! use ntp::transport::{ScriptedReply, ScriptedTransport};
+//! use std::time::Duration;
+//!
+//! let now = ntp::unix_time::Instant::now().into();
+//! let packet = Packet {
+//!     leap_indicator: LeapIndicator::NoWarning,
+//!     version: Version::V4,
+//!     mode: Mode::Server,
+//!     stratum: Stratum::PRIMARY,
+//!     poll: 0,
+//!     precision: -20,
+//!     root_delay: ShortFormat::default(),
+//!     root_dispersion: ShortFormat::default(),
+//!     reference_id: ReferenceIdentifier::PrimarySource(PrimarySource::Gps),
+//!     reference_timestamp: now,
+//!     origin_timestamp: TimestampFormat::default(),
+//!     receive_timestamp: now,
+//!     transmit_timestamp: now,
+//! };
+//!
+//! let mut transport = ScriptedTransport::new()
+//!     .reply(ScriptedReply::new(packet).delay(Duration::from_millis(10)));
+//! let measurement = Client::new().request_with(&mut transport, "192.0.2.1:123").unwrap();
+//! assert!(measurement.delay >= 0.01);
+//! ```
+
+use crate::client::Client;
+use crate::protocol::Packet;
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A means of exchanging datagrams with NTP servers.
+pub trait Transport {
+    /// Send the datagram `bytes` to `addr`.
+    fn send_to(&mut self, bytes: &[u8], addr: SocketAddr) -> io::Result<()>;
+
+    /// Receive a datagram into `buf`, returning its length and source address.
+    ///
+    /// Waits at most `timeout`, or indefinitely if it is `None`, and fails with an error of kind
+    /// `WouldBlock` or `TimedOut` if no datagram arrives in time. The timeout is never zero.
+    fn recv_from(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Option<Duration>,
+    ) -> io::Result<(usize, SocketAddr)>;
+}
+
+impl Transport for UdpSocket {
+    fn send_to(&mut self, bytes: &[u8], addr: SocketAddr) -> io::Result<()> {
+        UdpSocket::send_to(self, bytes, addr).map(|_| ())
+    }
+
+    fn recv_from(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Option<Duration>,
+    ) -> io::Result<(usize, SocketAddr)> {
+        self.set_read_timeout(timeout)?;
+        UdpSocket::recv_from(self, buf)
+    }
+}
+
+/// A scripted server response for a **ScriptedTransport**.
+#[derive(Clone, Debug)]
+pub struct ScriptedReply {
+    packet: Packet,
+    delay: Duration,
+    source: Option<SocketAddr>,
+    echo_origin: bool,
+}
+
+/// A **Transport** that answers each request sent with the next scripted replies.
+///
+/// Replies are delivered after their delay, measured in real time from when the request was
+/// sent. Requests sent once the script is exhausted, or answered with `silence`, get no reply.
+/// Receiving with no timeout while no reply is due fails with an error of kind `UnexpectedEof`.
+#[derive(Clone, Debug, Default)]
+pub struct ScriptedTransport {
+    script: VecDeque<Vec<ScriptedReply>>,
+    inbox: Vec<(Instant, Vec<u8>, SocketAddr)>,
+    sent: Vec<(Vec<u8>, SocketAddr)>,
+}
+
+impl ScriptedReply {
+    /// Reply with `packet`, immediately and from the address the request was sent to. The origin
+    /// timestamp is replaced by the transmit timestamp of the request.
+    pub fn new(packet: Packet) -> Self {
+        ScriptedReply {
+            packet,
+            delay: Duration::from_secs(0),
+            source: None,
+            echo_origin: true,
+        }
+    }
+
+    /// Deliver the reply `delay` after the request is sent.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Deliver the reply from `source`, as a spoofed reply would be.
+    pub fn source(mut self, source: SocketAddr) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Keep the origin timestamp of the packet rather than echoing the request.
+    pub fn keep_origin(mut self) -> Self {
+        self.echo_origin = false;
+        self
+    }
+}
+
+impl ScriptedTransport {
+    /// Create a transport with an empty script.
+    pub fn new() -> Self {
+        ScriptedTransport::default()
+    }
+
+    /// Answer the next unanswered request with `reply`.
+    pub fn reply(self, reply: ScriptedReply) -> Self {
+        self.replies(vec![reply])
+    }
+
+    /// Answer the next unanswered request with every one of `replies`, such as a spoofed reply
+    /// followed by the genuine one.
+    pub fn replies(mut self, replies: Vec<ScriptedReply>) -> Self {
+        self.script.push_back(replies);
+        self
+    }
+
+    /// Leave the next unanswered request without a reply.
+    pub fn silence(self) -> Self {
+        self.replies(Vec::new())
+    }
+
+    /// The datagrams sent so far, along with their destinations.
+    pub fn sent(&self) -> &[(Vec<u8>, SocketAddr)] {
+        &self.sent
+    }
+}
+
+impl Transport for ScriptedTransport {
+    fn send_to(&mut self, bytes: &[u8], addr: SocketAddr) -> io::Result<()> {
+        let sent_at = Instant::now();
+        self.sent.push((bytes.to_vec(), addr));
+        for reply in self.script.pop_front().unwrap_or_default() {
+            let mut packet = reply.packet;
+            if reply.echo_origin {
+                packet.origin_timestamp = Packet::decode(bytes)?.transmit_timestamp;
+            }
+            let mut buf = [0u8; 48];
+            let len = packet.encode(&mut buf)?;
+            let source = reply.source.unwrap_or(addr);
+            self.inbox.push((sent_at + reply.delay, buf[..len].to_vec(), source));
+        }
+        Ok(())
+    }
+
+    fn recv_from(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Option<Duration>,
+    ) -> io::Result<(usize, SocketAddr)> {
+        let now = Instant::now();
+        let next = (0..self.inbox.len()).min_by_key(|&i| self.inbox[i].0);
+        if let Some(i) = next {
+            let due = self.inbox[i].0;
+            if timeout.is_none_or(|timeout| due <= now + timeout) {
+                thread::sleep(due.saturating_duration_since(now));
+                let (_, bytes, source) = self.inbox.remove(i);
+                let len = bytes.len().min(buf.len());
+                buf[..len].copy_from_slice(&bytes[..len]);
+                return Ok((len, source));
+            }
+        }
+        match timeout {
+            Some(timeout) => {
+                thread::sleep(timeout);
+                let err_msg = "no scripted reply due within the timeout";
+                Err(io::Error::new(io::ErrorKind::TimedOut, err_msg))
+            }
+            None => {
+                let err_msg = "no scripted reply will arrive";
+                Err(io::Error::new(io::ErrorKind::UnexpectedEof, err_msg))
+            }
+        }
+    }
+}
+
+// The transport used by default, sending each attempt from a new socket bound by the client.
+pub(crate) struct UdpTransport<'a> {
+    client: &'a Client,
+    socket: Option<UdpSocket>,
+}
+
+impl<'a> UdpTransport<'a> {
+    pub(crate) fn new(client: &'a Client) -> Self {
+        UdpTransport {
+            client,
+            socket: None,
+        }
+    }
+}
+
+impl<'a> Transport for UdpTransport<'a> {
+    fn send_to(&mut self, bytes: &[u8], addr: SocketAddr) -> io::Result<()> {
+        let mut socket = self.client.bind_for(&addr)?;
+        Transport::send_to(&mut socket, bytes, addr)?;
+        self.socket = Some(socket);
+        Ok(())
+    }
+
+    fn recv_from(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Option<Duration>,
+    ) -> io::Result<(usize, SocketAddr)> {
+        match self.socket {
+            Some(ref mut socket) => Transport::recv_from(socket, buf, timeout),
+            None => {
+                let err_msg = "no request has been sent";
+                Err(io::Error::new(io::ErrorKind::NotConnected, err_msg))
+            }
+        }
+    }
+}
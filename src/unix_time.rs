@@ -1,12 +1,10 @@
-use protocol;
-use std::{self, time};
+use crate::protocol;
+#[cfg(feature = "std")]
+use std::time;
 
 /// The number of seconds from 1st January 1900 UTC to the start of the Unix epoch.
 pub const EPOCH_DELTA: i64 = 2_208_988_800;
 
-// The NTP fractional scale.
-const NTP_SCALE: f64 = std::u32::MAX as f64;
-
 /// Describes an instant relative to the `UNIX_EPOCH` - 00:00:00 Coordinated Universal Time (UTC),
 /// Thursay, 1 January 1970 in seconds with the fractional part in nanoseconds.
 ///
@@ -68,6 +66,7 @@ impl Instant {
     ///     println!("{:?}", ntp::unix_time::Instant::now());
     /// }
     /// ```
+    #[cfg(feature = "std")]
     pub fn now() -> Self {
         match time::SystemTime::now().duration_since(time::UNIX_EPOCH) {
             Ok(duration) => {
@@ -96,41 +95,70 @@ impl Instant {
 }
 
 // Conversion implementations.
+//
+// The conversions are exact to the resolution of the coarser format: a fraction converts to the
+// nanoseconds it rounds up to, and nanoseconds convert to the fraction they round down to, so an
+// **Instant** survives a round trip through a **TimestampFormat** and a **ShortFormat** survives
+// one through an **Instant**.
 
 impl From<protocol::ShortFormat> for Instant {
     fn from(t: protocol::ShortFormat) -> Self {
-        let secs = t.seconds as i64 - EPOCH_DELTA;
-        let subsec_nanos = (t.fraction as f64 / NTP_SCALE * 1e9) as i32;
-        Instant::new(secs, subsec_nanos)
+        from_ntp(t.seconds as i64, t.fraction as u64, 16)
     }
 }
 
 impl From<protocol::TimestampFormat> for Instant {
     fn from(t: protocol::TimestampFormat) -> Self {
-        let secs = t.seconds as i64 - EPOCH_DELTA;
-        let subsec_nanos = (t.fraction as f64 / NTP_SCALE * 1e9) as i32;
-        Instant::new(secs, subsec_nanos)
+        from_ntp(t.seconds as i64, t.fraction as u64, 32)
     }
 }
 
 impl From<Instant> for protocol::ShortFormat {
     fn from(t: Instant) -> Self {
-        let sec = t.secs() + EPOCH_DELTA;
-        let frac = t.subsec_nanos() as f64 * NTP_SCALE / 1e9;
+        let (secs, fraction) = to_ntp(t, 16);
         protocol::ShortFormat {
-            seconds: sec as u16,
-            fraction: frac as u16,
+            seconds: secs as u16,
+            fraction: fraction as u16,
         }
     }
 }
 
 impl From<Instant> for protocol::TimestampFormat {
     fn from(t: Instant) -> Self {
-        let sec = t.secs() + EPOCH_DELTA;
-        let frac = t.subsec_nanos() as f64 * NTP_SCALE / 1e9;
+        let (secs, fraction) = to_ntp(t, 32);
         protocol::TimestampFormat {
-            seconds: sec as u32,
-            fraction: frac as u32,
+            seconds: secs as u32,
+            fraction: fraction as u32,
         }
     }
 }
+
+// Utility functions.
+
+// The **Instant** of the NTP time `secs` and the `fraction` of a second scaled by 2^`bits`.
+fn from_ntp(secs: i64, fraction: u64, bits: u32) -> Instant {
+    let scale = 1u64 << bits;
+    let nanos = (fraction * 1_000_000_000 + scale - 1) >> bits;
+    let (secs, nanos) = match nanos {
+        1_000_000_000 => (secs + 1, 0),
+        nanos => (secs, nanos as i32),
+    };
+    let secs = secs - EPOCH_DELTA;
+    // An **Instant** before the epoch counts both of its components down from it.
+    match secs {
+        secs if secs < 0 && nanos > 0 => Instant::new(secs + 1, nanos - 1_000_000_000),
+        secs => Instant::new(secs, nanos),
+    }
+}
+
+// The NTP time of the **Instant** `t` as its seconds, wrapping at the era boundary, and the
+// fraction of a second scaled by 2^`bits`.
+fn to_ntp(t: Instant, bits: u32) -> (i64, u64) {
+    // Borrow a second before the epoch so that the fraction counts up.
+    let (secs, nanos) = match t.subsec_nanos() {
+        nanos if nanos < 0 => (t.secs() - 1, nanos + 1_000_000_000),
+        nanos => (t.secs(), nanos),
+    };
+    let fraction = ((nanos as u64) << bits) / 1_000_000_000;
+    (secs.wrapping_add(EPOCH_DELTA), fraction)
+}
@@ -0,0 +1,173 @@
+#![cfg(feature = "tokio")]
+
+use ntp::async_client::AsyncClient;
+use ntp::client::{Client, ResponseError};
+use ntp::clock::ManualClock;
+use ntp::protocol::{LeapIndicator, Packet};
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+mod common;
+
+// Collect `count` requests on a local socket, then respond to all of them in reverse order,
+// passing each response through `tamper`. Returns the server address and a handle yielding the
+// requests.
+fn serve_reversed(
+    count: usize,
+    tamper: fn(&mut Packet),
+) -> (SocketAddr, thread::JoinHandle<Vec<Packet>>) {
+    serve_reversed_on("127.0.0.1:0", count, tamper)
+}
+
+// As `serve_reversed`, but on a socket bound to `addr`.
+fn serve_reversed_on(
+    addr: &str,
+    count: usize,
+    tamper: fn(&mut Packet),
+) -> (SocketAddr, thread::JoinHandle<Vec<Packet>>) {
+    let sock = UdpSocket::bind(addr).unwrap();
+    let server_addr = sock.local_addr().unwrap();
+    let handle = thread::spawn(move || {
+        let mut requests = Vec::new();
+        for _ in 0..count {
+            let mut buf = [0u8; 1024];
+            let (len, src) = sock.recv_from(&mut buf).unwrap();
+            requests.push((Packet::decode(&buf[..len]).unwrap(), src));
+        }
+        for &(request, src) in requests.iter().rev() {
+            let now = ntp::unix_time::Instant::now().into();
+            let mut response = common::reply_to(&request, now);
+            tamper(&mut response);
+            let mut bytes = [0u8; 48];
+            let len = response.encode(&mut bytes).unwrap();
+            sock.send_to(&bytes[..len], src).unwrap();
+        }
+        requests.into_iter().map(|(request, _)| request).collect()
+    });
+    (server_addr, handle)
+}
+
+fn config() -> Client {
+    Client::new().timeout(Duration::from_secs(1))
+}
+
+#[tokio::test]
+async fn concurrent_requests_share_a_socket() {
+    let (addr, server) = serve_reversed(3, |_| ());
+    let client = AsyncClient::new(config()).await.unwrap();
+    let (a, b, c) = tokio::join!(client.request(addr), client.request(addr), client.request(addr));
+    let requests = server.join().unwrap();
+
+    // Each response is matched to its request despite arriving in reverse order.
+    let mut origins: Vec<_> = [a, b, c]
+        .iter()
+        .map(|m| m.as_ref().unwrap().packet.origin_timestamp)
+        .collect();
+    let mut transmits: Vec<_> = requests.iter().map(|r| r.transmit_timestamp).collect();
+    origins.sort_by_key(|t| (t.seconds, t.fraction));
+    transmits.sort_by_key(|t| (t.seconds, t.fraction));
+    assert_eq!(origins, transmits);
+}
+
+#[tokio::test]
+async fn concurrent_requests_with_data_minimization() {
+    let (addr, server) = serve_reversed(2, |_| ());
+    let client = AsyncClient::new(config().data_minimization(true)).await.unwrap();
+    let (a, b) = tokio::join!(client.request(addr), client.request(addr));
+    server.join().unwrap();
+    assert!(a.unwrap().offset.abs() < 1.0);
+    assert!(b.unwrap().offset.abs() < 1.0);
+}
+
+#[tokio::test]
+async fn concurrent_requests_under_a_stopped_clock() {
+    // Both requests carry the same transmit timestamp, and are told apart by their server.
+    let (a_addr, a_server) = serve_reversed(1, |_| ());
+    let (b_addr, b_server) = serve_reversed(1, |_| ());
+    let clock = ManualClock::new(ntp::unix_time::Instant::now().into());
+    let client = AsyncClient::new(config().clock(clock)).await.unwrap();
+    let (a, b) = tokio::join!(client.request(a_addr), client.request(b_addr));
+    let a_request = a_server.join().unwrap()[0];
+    let b_request = b_server.join().unwrap()[0];
+    assert_eq!(a_request.transmit_timestamp, b_request.transmit_timestamp);
+    assert_eq!(a.unwrap().packet.origin_timestamp, a_request.transmit_timestamp);
+    assert_eq!(b.unwrap().packet.origin_timestamp, b_request.transmit_timestamp);
+}
+
+#[tokio::test]
+async fn rejects_insane_response() {
+    let (addr, server) = serve_reversed(1, |p| p.leap_indicator = LeapIndicator::Unknown);
+    let client = AsyncClient::new(config()).await.unwrap();
+    let err = client.request(addr).await.unwrap_err();
+    server.join().unwrap();
+    assert_eq!(ResponseError::from_io_error(&err), Some(ResponseError::Unsynchronized));
+}
+
+#[tokio::test]
+async fn times_out_and_retries() {
+    let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = sock.local_addr().unwrap();
+    let client = AsyncClient::new(
+        Client::new()
+            .timeout(Duration::from_millis(50))
+            .retries(2)
+            .backoff(Duration::from_millis(10)),
+    )
+    .await
+    .unwrap();
+    let err = client.request(addr).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+
+    // One request per attempt was sent.
+    sock.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+    let mut buf = [0u8; 1024];
+    for _ in 0..3 {
+        sock.recv_from(&mut buf).unwrap();
+    }
+    assert!(sock.recv_from(&mut buf).is_err());
+}
+
+#[tokio::test]
+async fn drops_spoofed_response() {
+    let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = sock.local_addr().unwrap();
+    let spoofer = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let handle = thread::spawn(move || {
+        let mut buf = [0u8; 1024];
+        let (len, src) = sock.recv_from(&mut buf).unwrap();
+        let request = Packet::decode(&buf[..len]).unwrap();
+        let response = common::reply_to(&request, ntp::unix_time::Instant::now().into());
+        let mut bytes = [0u8; 48];
+        response.encode(&mut bytes).unwrap();
+        // The spoofed response echoes the origin, but arrives from the wrong address.
+        spoofer.send_to(&bytes, src).unwrap();
+        thread::sleep(Duration::from_millis(10));
+        sock.send_to(&bytes, src).unwrap();
+    });
+    let client = AsyncClient::new(config()).await.unwrap();
+    let measurement = client.request(addr).await.unwrap();
+    handle.join().unwrap();
+    assert!(measurement.offset.abs() < 1.0);
+}
+
+#[tokio::test]
+async fn binds_a_socket_per_address_family() {
+    // Skip if the host has no IPv6 loopback.
+    if UdpSocket::bind("[::1]:0").is_err() {
+        return;
+    }
+    let (v4, v4_server) = serve_reversed(1, |_| ());
+    let (v6, v6_server) = serve_reversed_on("[::1]:0", 1, |_| ());
+    let client = AsyncClient::new(config()).await.unwrap();
+    let err = client.local_addr(&v6).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotConnected);
+
+    let (a, b) = tokio::join!(client.request(v4), client.request(v6));
+    a.unwrap();
+    b.unwrap();
+    v4_server.join().unwrap();
+    v6_server.join().unwrap();
+    assert!(client.local_addr(&v4).unwrap().is_ipv4());
+    assert!(client.local_addr(&v6).unwrap().is_ipv6());
+}
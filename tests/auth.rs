@@ -0,0 +1,191 @@
+#![cfg(feature = "std")]
+
+extern crate ntp;
+
+use ntp::auth::{DigestAlgorithm, Key, KeyStore, Signer};
+use ntp::client::Client;
+use ntp::protocol::{
+    ExtendedPacket, ExtensionField, ExtensionFieldType, Packet, ReadBytes, WriteBytes,
+};
+use ntp::transport::Transport;
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+mod common;
+
+const PACKET_BYTES: [u8; 48] = [
+    20, 1, 3, 240, 0, 0, 0, 0, 0, 0, 0, 24, 67, 68, 77, 65, 215, 188, 128, 105, 198, 169,
+    46, 99, 215, 187, 177, 194, 159, 47, 120, 0, 215, 188, 128, 113, 45, 236, 230, 45, 215,
+    188, 128, 113, 46, 35, 158, 108,
+];
+
+fn packet() -> ExtendedPacket {
+    let packet = (&PACKET_BYTES[..]).read_bytes::<Packet>().unwrap();
+    ExtendedPacket::from(packet)
+}
+
+// A server that verifies each request and answers it with one reply signed with each of
+// `reply_key_ids` in turn.
+struct SigningServer {
+    keys: KeyStore,
+    reply_key_ids: Vec<u32>,
+    addr: SocketAddr,
+    queue: VecDeque<Vec<u8>>,
+}
+
+impl SigningServer {
+    fn new(reply_key_ids: Vec<u32>) -> Self {
+        SigningServer {
+            keys: key_store(),
+            reply_key_ids,
+            addr: "192.0.2.1:123".parse().unwrap(),
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+impl Transport for SigningServer {
+    fn send_to(&mut self, bytes: &[u8], addr: SocketAddr) -> io::Result<()> {
+        let request: ExtendedPacket = (&bytes[..]).read_bytes()?;
+        self.keys.verify(&request)?;
+        self.addr = addr;
+        let now = ntp::unix_time::Instant::now().into();
+        for &key_id in &self.reply_key_ids {
+            let mut response = ExtendedPacket::from(common::reply_to(&request.packet, now));
+            self.keys.sign(key_id, &mut response)?;
+            let mut out = vec![];
+            out.write_bytes(&response)?;
+            self.queue.push_back(out);
+        }
+        Ok(())
+    }
+
+    fn recv_from(
+        &mut self,
+        buf: &mut [u8],
+        _: Option<Duration>,
+    ) -> io::Result<(usize, SocketAddr)> {
+        match self.queue.pop_front() {
+            Some(bytes) => {
+                buf[..bytes.len()].copy_from_slice(&bytes);
+                Ok((bytes.len(), self.addr))
+            }
+            None => Err(io::Error::new(io::ErrorKind::TimedOut, "no reply")),
+        }
+    }
+}
+
+fn key_store() -> KeyStore {
+    let mut keys = KeyStore::new();
+    keys.insert(1, Key::new(DigestAlgorithm::Md5, b"ntp secret".to_vec()).unwrap());
+    keys.insert(2, Key::new(DigestAlgorithm::Sha1, b"ntp secret".to_vec()).unwrap());
+    keys.insert(3, Key::new(DigestAlgorithm::AesCmac128, vec![0x42; 16]).unwrap());
+    keys
+}
+
+#[test]
+fn sign_legacy_digests() {
+    let keys = key_store();
+    let mut packet = packet();
+    keys.sign(1, &mut packet).unwrap();
+    let mac = packet.mac.clone().unwrap();
+    assert_eq!(mac.key_id, 1);
+    assert_eq!(
+        mac.digest,
+        vec![201, 75, 213, 17, 156, 15, 231, 131, 205, 85, 151, 112, 12, 54, 91, 185]
+    );
+    keys.sign(2, &mut packet).unwrap();
+    let mac = packet.mac.unwrap();
+    assert_eq!(mac.key_id, 2);
+    assert_eq!(
+        mac.digest,
+        vec![
+            207, 63, 160, 61, 239, 82, 86, 111, 77, 98, 205, 16, 135, 36, 185, 48, 78, 206, 33,
+            89,
+        ]
+    );
+}
+
+#[test]
+fn aes_cmac_test_vector() {
+    // RFC 4493, section 4, example 2.
+    let secret = vec![
+        0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f,
+        0x3c,
+    ];
+    let message = [
+        0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17,
+        0x2a,
+    ];
+    let key = Key::new(DigestAlgorithm::AesCmac128, secret).unwrap();
+    assert_eq!(
+        key.digest(&message),
+        vec![
+            0x07, 0x0a, 0x16, 0xb4, 0x6b, 0x4d, 0x41, 0x44, 0xf7, 0x9b, 0xdd, 0x9d, 0xd0, 0x4a,
+            0x28, 0x7c,
+        ]
+    );
+}
+
+#[test]
+fn sign_and_verify_roundtrip() {
+    let keys = key_store();
+    for &key_id in &[1, 2, 3] {
+        let mut packet = packet();
+        packet.extension_fields.push(ExtensionField::new(ExtensionFieldType(0x0104), vec![1; 32]));
+        keys.sign(key_id, &mut packet).unwrap();
+        let mut bytes = vec![];
+        bytes.write_bytes(&packet).unwrap();
+        let received = (&bytes[..]).read_bytes::<ExtendedPacket>().unwrap();
+        assert_eq!(received, packet);
+        assert_eq!(keys.verify(&received).unwrap(), key_id);
+    }
+}
+
+#[test]
+fn verify_rejects_tampered_packet() {
+    let keys = key_store();
+    let mut packet = packet();
+    keys.sign(3, &mut packet).unwrap();
+    packet.packet.poll += 1;
+    let err = keys.verify(&packet).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn verify_rejects_unknown_key_and_missing_mac() {
+    let keys = key_store();
+    let mut packet = packet();
+    assert!(keys.verify(&packet).is_err());
+    keys.sign(1, &mut packet).unwrap();
+    let mut other_keys = KeyStore::new();
+    other_keys.insert(4, Key::new(DigestAlgorithm::Md5, b"ntp secret".to_vec()).unwrap());
+    assert!(other_keys.verify(&packet).is_err());
+    assert!(keys.sign(4, &mut packet).is_err());
+    assert!(keys.sign(0, &mut packet).is_err());
+}
+
+#[test]
+fn invalid_keys() {
+    assert!(Key::new(DigestAlgorithm::Md5, vec![]).is_err());
+    assert!(Key::new(DigestAlgorithm::AesCmac128, vec![0; 15]).is_err());
+}
+
+#[test]
+fn client_drops_responses_signed_with_another_key() {
+    let keys = key_store();
+    let client = Client::new().timeout(Duration::from_millis(100));
+    let mut server = SigningServer::new(vec![2, 3]);
+    let mut signer = Signer::new(&keys, 3);
+    client.request_protected_with(&mut server, "192.0.2.1:123", &mut signer).unwrap();
+    assert_eq!(signer.into_response().unwrap().mac.unwrap().key_id, 3);
+
+    // A server that only answers with another key times out with the reason it was dropped.
+    let mut server = SigningServer::new(vec![2]);
+    let mut signer = Signer::new(&keys, 3);
+    let err = client.request_protected_with(&mut server, "192.0.2.1:123", &mut signer);
+    assert_eq!(err.unwrap_err().to_string(), "response signed with a different key");
+    assert!(signer.response().is_none());
+}
@@ -0,0 +1,101 @@
+#![cfg(feature = "std")]
+
+use ntp::burst::Burst;
+use ntp::client::{Client, ResponseError};
+use ntp::protocol::KissOfDeath;
+use ntp::transport::{ScriptedReply, ScriptedTransport};
+use std::io;
+use std::time::Duration;
+
+mod common;
+
+use common::{kiss, response};
+
+const SERVER: &str = "192.0.2.1:123";
+
+fn burst(count: u32) -> Burst {
+    let client = Client::new().timeout(Duration::from_millis(100));
+    Burst::new(client).count(count).spacing(Duration::from_millis(1))
+}
+
+#[test]
+fn keeps_lowest_delay_sample() {
+    let mut transport = ScriptedTransport::new()
+        .reply(ScriptedReply::new(response()).delay(Duration::from_millis(40)))
+        .silence()
+        .reply(ScriptedReply::new(response()))
+        .reply(ScriptedReply::new(response()).delay(Duration::from_millis(20)));
+    let report = burst(4).run_with(&mut transport, SERVER).unwrap();
+
+    assert_eq!(report.sent, 4);
+    assert_eq!(report.measurements.len(), 3);
+    assert_eq!(report.kiss, None);
+    assert_eq!(report.best, report.measurements[1]);
+    assert!(report.best.delay < 0.02);
+    // The scripted server clock is stopped, so the offsets of the samples differ.
+    assert!(report.jitter > 0.0);
+}
+
+#[test]
+fn stops_on_rate_kiss() {
+    let mut transport = ScriptedTransport::new()
+        .reply(ScriptedReply::new(response()))
+        .reply(ScriptedReply::new(kiss(KissOfDeath::Rate)))
+        .reply(ScriptedReply::new(response()));
+    let report = burst(8).run_with(&mut transport, SERVER).unwrap();
+
+    assert_eq!(report.sent, 2);
+    assert_eq!(transport.sent().len(), 2);
+    assert_eq!(report.measurements.len(), 1);
+    assert_eq!(report.kiss, Some(KissOfDeath::Rate));
+    assert_eq!(report.jitter, 0.0);
+}
+
+#[test]
+fn stops_on_deny_and_rstr_kiss() {
+    for &code in &[KissOfDeath::Deny, KissOfDeath::Rstr] {
+        let mut transport = ScriptedTransport::new()
+            .reply(ScriptedReply::new(response()))
+            .reply(ScriptedReply::new(kiss(code)))
+            .reply(ScriptedReply::new(response()));
+        let report = burst(8).run_with(&mut transport, SERVER).unwrap();
+        assert_eq!((report.sent, transport.sent().len()), (2, 2));
+        assert_eq!(report.kiss, Some(code));
+    }
+}
+
+#[test]
+fn kiss_is_not_retried() {
+    let client = Client::new()
+        .timeout(Duration::from_millis(100))
+        .retries(2)
+        .backoff(Duration::from_millis(1));
+    let mut transport = ScriptedTransport::new()
+        .reply(ScriptedReply::new(kiss(KissOfDeath::Rate)))
+        .reply(ScriptedReply::new(response()));
+    let err = Burst::new(client)
+        .count(4)
+        .spacing(Duration::from_millis(1))
+        .run_with(&mut transport, SERVER)
+        .unwrap_err();
+    assert_eq!(
+        ResponseError::from_io_error(&err),
+        Some(ResponseError::KissOfDeath(KissOfDeath::Rate))
+    );
+    assert_eq!(transport.sent().len(), 1);
+}
+
+#[test]
+fn fails_without_responses() {
+    let reply = ScriptedReply::new(kiss(KissOfDeath::Rate));
+    let mut transport = ScriptedTransport::new().reply(reply);
+    let err = burst(8).run_with(&mut transport, SERVER).unwrap_err();
+    assert_eq!(
+        ResponseError::from_io_error(&err),
+        Some(ResponseError::KissOfDeath(KissOfDeath::Rate))
+    );
+
+    let mut transport = ScriptedTransport::new().silence().silence();
+    let err = burst(2).run_with(&mut transport, SERVER).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+}
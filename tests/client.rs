@@ -0,0 +1,236 @@
+#![cfg(feature = "std")]
+
+extern crate ntp;
+
+use ntp::client::{Client, ResponseError};
+use ntp::protocol::{
+    KissOfDeath, LeapIndicator, Mode, Packet, ReferenceIdentifier, ShortFormat, Stratum,
+    TimestampFormat, Version,
+};
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+mod common;
+
+// Serve `count` requests on a local socket, ignoring the first `skip` of them and sleeping for
+// `delay` before each response. Returns the server address and a handle yielding the requests
+// along with their source addresses.
+fn serve(
+    addr: &str,
+    count: usize,
+    skip: usize,
+    delay: Duration,
+) -> (SocketAddr, thread::JoinHandle<Vec<(Packet, SocketAddr)>>) {
+    serve_with(addr, count, skip, delay, |_| ())
+}
+
+// As `serve`, but passing each response through `tamper` before it is sent.
+fn serve_with(
+    addr: &str,
+    count: usize,
+    skip: usize,
+    delay: Duration,
+    tamper: fn(&mut Packet),
+) -> (SocketAddr, thread::JoinHandle<Vec<(Packet, SocketAddr)>>) {
+    let sock = UdpSocket::bind(addr).unwrap();
+    let server_addr = sock.local_addr().unwrap();
+    let handle = thread::spawn(move || {
+        let mut requests = Vec::new();
+        for i in 0..count {
+            let mut buf = [0u8; 1024];
+            let (len, src) = sock.recv_from(&mut buf).unwrap();
+            let request = Packet::decode(&buf[..len]).unwrap();
+            requests.push((request, src));
+            if i < skip {
+                continue;
+            }
+            thread::sleep(delay);
+            let now = ntp::unix_time::Instant::now().into();
+            let mut response = common::reply_to(&request, now);
+            tamper(&mut response);
+            let mut bytes = [0u8; 48];
+            let len = response.encode(&mut bytes).unwrap();
+            sock.send_to(&bytes[..len], src).unwrap();
+        }
+        requests
+    });
+    (server_addr, handle)
+}
+
+#[test]
+fn request_fields_from_builder() {
+    let (addr, server) = serve("127.0.0.1:0", 1, 0, Duration::from_millis(0));
+    let client = Client::new().version(Version::V3).poll(6).precision(-18);
+    let measurement = client.request(addr).unwrap();
+    let (request, _) = server.join().unwrap()[0];
+    assert_eq!(request.version, Version::V3);
+    assert_eq!(request.mode, Mode::Client);
+    assert_eq!(request.poll, 6);
+    assert_eq!(request.precision, -18);
+    assert_eq!(measurement.packet.poll, 6);
+    assert_eq!(measurement.t1, request.transmit_timestamp);
+}
+
+#[test]
+fn retries_after_timeout() {
+    let (addr, server) = serve("127.0.0.1:0", 2, 1, Duration::from_millis(0));
+    let client = Client::new()
+        .timeout(Duration::from_millis(200))
+        .retries(1)
+        .backoff(Duration::from_millis(10));
+    client.request(addr).unwrap();
+    assert_eq!(server.join().unwrap().len(), 2);
+}
+
+#[test]
+fn fails_after_last_retry() {
+    let (addr, server) = serve("127.0.0.1:0", 2, 2, Duration::from_millis(0));
+    let client = Client::new()
+        .timeout(Duration::from_millis(100))
+        .retries(1)
+        .backoff(Duration::from_millis(10));
+    assert!(client.request(addr).is_err());
+    assert_eq!(server.join().unwrap().len(), 2);
+}
+
+#[test]
+fn rejects_excessive_delay() {
+    let (addr, server) = serve("127.0.0.1:0", 1, 0, Duration::from_millis(100));
+    let client = Client::new().max_delay(Duration::from_millis(20));
+    match rejection(client.request(addr)) {
+        ResponseError::ExcessiveDelay(delay) => assert!(delay >= 0.1),
+        err => panic!("unexpected error: {}", err),
+    }
+    server.join().unwrap();
+}
+
+// Unwrap the rejection reason from the result of a request.
+fn rejection<T>(result: std::io::Result<T>) -> ResponseError {
+    let err = result.err().expect("response was not rejected");
+    ResponseError::from_io_error(&err).expect("error is not a rejection")
+}
+
+// Request from a server that passes its response through `tamper`.
+fn tampered(tamper: fn(&mut Packet)) -> ResponseError {
+    let (addr, server) = serve_with("127.0.0.1:0", 1, 0, Duration::from_millis(0), tamper);
+    // Responses that are not verified to answer the request are only reported at the timeout.
+    let client = Client::new().timeout(Duration::from_millis(200));
+    let err = rejection(client.request(addr));
+    server.join().unwrap();
+    err
+}
+
+#[test]
+fn rejects_insane_responses() {
+    assert_eq!(
+        tampered(|p| p.mode = Mode::Broadcast),
+        ResponseError::UnexpectedMode(Mode::Broadcast)
+    );
+    assert_eq!(
+        tampered(|p| p.origin_timestamp.fraction ^= 1),
+        ResponseError::OriginMismatch
+    );
+    assert_eq!(
+        tampered(|p| p.transmit_timestamp = TimestampFormat::default()),
+        ResponseError::ZeroTransmit
+    );
+    assert_eq!(
+        tampered(|p| p.leap_indicator = LeapIndicator::Unknown),
+        ResponseError::Unsynchronized
+    );
+    assert_eq!(
+        tampered(|p| p.stratum = Stratum(16)),
+        ResponseError::InvalidStratum(Stratum(16))
+    );
+    match tampered(|p| p.root_dispersion = ShortFormat { seconds: 2, fraction: 0 }) {
+        ResponseError::ExcessiveRootDistance(distance) => assert!(distance >= 2.0),
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn rejects_kiss_of_death() {
+    let err = tampered(|p| {
+        p.leap_indicator = LeapIndicator::Unknown;
+        p.stratum = Stratum::UNSPECIFIED;
+        p.reference_id = ReferenceIdentifier::KissOfDeath(KissOfDeath::Rate);
+    });
+    assert_eq!(err, ResponseError::KissOfDeath(KissOfDeath::Rate));
+}
+
+#[test]
+fn rejects_unexpected_source() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let spoofer = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let spoofer_addr = spoofer.local_addr().unwrap();
+    let handle = thread::spawn(move || {
+        let mut buf = [0u8; 1024];
+        let (_, src) = server.recv_from(&mut buf).unwrap();
+        spoofer.send_to(&buf[..48], src).unwrap();
+    });
+    let err = rejection(Client::new().timeout(Duration::from_millis(200)).request(addr));
+    assert_eq!(err, ResponseError::UnexpectedSource(spoofer_addr));
+    handle.join().unwrap();
+}
+
+#[test]
+fn data_minimization() {
+    let (addr, server) = serve("127.0.0.1:0", 2, 1, Duration::from_millis(0));
+    let client = Client::new()
+        .poll(6)
+        .precision(-18)
+        .data_minimization(true)
+        .timeout(Duration::from_millis(200))
+        .retries(1)
+        .backoff(Duration::from_millis(10));
+    let measurement = client.request(addr).unwrap();
+    let requests = server.join().unwrap();
+    let (first, first_src) = requests[0];
+    let (second, second_src) = requests[1];
+
+    // Only the version, mode and transmit cookie are sent.
+    let mut bytes = [0u8; 48];
+    first.encode(&mut bytes).unwrap();
+    assert_eq!(bytes[0], 0b00_100_011);
+    assert!(bytes[1..40].iter().all(|&b| b == 0));
+
+    // Each attempt uses a fresh cookie and a source port drawn from the dynamic port range.
+    assert_ne!(first.transmit_timestamp, second.transmit_timestamp);
+    for src in &[first_src, second_src] {
+        assert!((49152..=65535).contains(&src.port()), "port {} is not dynamic", src.port());
+    }
+
+    // The response echoes the cookie, but T1 is the local send time.
+    assert_eq!(measurement.packet.origin_timestamp, second.transmit_timestamp);
+    assert!(measurement.offset.abs() < 1.0);
+    assert!(measurement.delay >= 0.0 && measurement.delay < 1.0);
+}
+
+#[test]
+fn request_over_ipv6() {
+    // Skip if the host has no IPv6 loopback.
+    if UdpSocket::bind("[::1]:0").is_err() {
+        return;
+    }
+    let (addr, server) = serve("[::1]:0", 1, 0, Duration::from_millis(0));
+    Client::new().timeout(Duration::from_secs(1)).request(addr).unwrap();
+    server.join().unwrap();
+}
+
+// End-to-end requests to public servers, run with `cargo test -- --ignored`.
+
+#[test]
+#[ignore = "requires network access"]
+fn test_request_ntp_org() {
+    let res = ntp::request("0.pool.ntp.org:123");
+    let _ = res.expect("Failed to get a ntp packet from ntp.org");
+}
+
+#[test]
+#[ignore = "requires network access"]
+fn test_request_google() {
+    let res = ntp::request("time.google.com:123");
+    let _ = res.expect("Failed to get a ntp packet from time.google.com");
+}
@@ -0,0 +1,75 @@
+#![cfg(feature = "std")]
+
+use ntp::client::Client;
+use ntp::clock::{Clock, ManualClock, SimulatedClock, SystemClock};
+use ntp::measurement::difference;
+use ntp::protocol::TimestampFormat;
+use ntp::transport::{ScriptedReply, ScriptedTransport};
+use std::sync::Arc;
+use std::time::Duration;
+
+mod common;
+
+use common::response_at;
+
+fn timestamp(seconds: u32) -> TimestampFormat {
+    TimestampFormat { seconds, fraction: 0 }
+}
+
+#[test]
+fn manual_clock() {
+    let clock = ManualClock::new(timestamp(100));
+    assert_eq!(clock.now(), timestamp(100));
+    clock.advance(Duration::from_millis(1500));
+    assert_eq!(clock.now(), TimestampFormat { seconds: 101, fraction: 1 << 31 });
+    clock.set(timestamp(50));
+    assert_eq!(clock.now(), timestamp(50));
+}
+
+#[test]
+fn simulated_clock() {
+    let reference = Arc::new(ManualClock::new(timestamp(1000)));
+    let behind = SimulatedClock::new(reference.clone()).offset(-2.0);
+    let drifting = SimulatedClock::new(reference.clone()).drift(-100e-6);
+    assert!((difference(behind.now(), reference.now()) + 2.0).abs() < 1e-6);
+    assert_eq!(drifting.now(), reference.now());
+
+    reference.advance(Duration::from_secs(10_000));
+    assert!((difference(behind.now(), reference.now()) + 2.0).abs() < 1e-6);
+    assert!((difference(drifting.now(), reference.now()) + 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn system_clock() {
+    let system = SystemClock.now();
+    let unix: TimestampFormat = ntp::unix_time::Instant::now().into();
+    assert!(difference(unix, system).abs() < 1.0);
+
+    #[cfg(unix)]
+    {
+        let realtime = ntp::clock::RealtimeClock.now();
+        assert!(difference(realtime, system).abs() < 1.0);
+    }
+}
+
+#[test]
+fn debug_does_not_read_the_clock() {
+    let clock: Arc<dyn Clock + Send + Sync> = Arc::new(ManualClock::default());
+    assert_eq!(format!("{:?}", clock), "Clock(..)");
+}
+
+#[test]
+fn client_reads_its_clock() {
+    // The server is a second ahead of a local clock that does not move during the exchange.
+    let clock = ManualClock::new(timestamp(1000));
+    let reply = ScriptedReply::new(response_at(timestamp(1001)));
+    let mut transport = ScriptedTransport::new().reply(reply);
+    let measurement = Client::new()
+        .clock(clock)
+        .request_with(&mut transport, "192.0.2.1:123")
+        .unwrap();
+    assert_eq!(measurement.t1, timestamp(1000));
+    assert_eq!(measurement.t4, timestamp(1000));
+    assert_eq!(measurement.offset, 1.0);
+    assert_eq!(measurement.delay, 0.0);
+}
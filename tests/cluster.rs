@@ -0,0 +1,93 @@
+#![cfg(feature = "std")]
+
+use ntp::cluster::{cluster, combine, mitigate};
+use ntp::protocol::{Stratum, MINDISP, NMIN};
+use ntp::selection::{select, Candidate};
+
+fn candidate(offset: f64, jitter: f64, root_distance: f64) -> Candidate {
+    Candidate {
+        offset,
+        jitter,
+        root_distance,
+        stratum: Stratum::SECONDARY_MIN,
+    }
+}
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-9
+}
+
+// Five truechimers, ranked by root distance in the order given, one of them an outlier.
+fn truechimers(jitter: f64) -> Vec<Candidate> {
+    [0.000, 0.001, 0.002, 0.004, 0.040]
+        .iter()
+        .enumerate()
+        .map(|(i, &offset)| candidate(offset, jitter, 0.1 + 0.01 * i as f64))
+        .collect()
+}
+
+#[test]
+fn prunes_outliers_to_nmin() {
+    let candidates = truechimers(0.0001);
+    let selection = select(&candidates).unwrap();
+    assert_eq!(selection.truechimers().count(), 5);
+    // The outlier goes first, then the survivor furthest from the remaining ones.
+    let survivors = cluster(&candidates, &selection);
+    assert_eq!(survivors.len(), NMIN);
+    assert_eq!(survivors, [0, 1, 2]);
+}
+
+#[test]
+fn keeps_survivors_within_peer_jitter() {
+    let candidates = truechimers(0.05);
+    let selection = select(&candidates).unwrap();
+    assert_eq!(cluster(&candidates, &selection), [0, 1, 2, 3, 4]);
+}
+
+#[test]
+fn ranks_by_stratum_then_root_distance() {
+    let mut candidates = vec![
+        candidate(0.0, 0.001, 0.01),
+        candidate(0.0, 0.001, 0.03),
+        candidate(0.0, 0.001, 0.02),
+    ];
+    candidates[1].stratum = Stratum::PRIMARY;
+    let selection = select(&candidates).unwrap();
+    assert_eq!(cluster(&candidates, &selection), [1, 0, 2]);
+}
+
+#[test]
+fn combines_by_inverse_root_distance() {
+    let candidates = [candidate(0.0, 0.001, 0.01), candidate(0.03, 0.002, 0.02)];
+    let estimate = combine(&candidates, &[0, 1]).unwrap();
+    assert!(close(estimate.offset, 0.01));
+    // The spread of the survivors around the system peer, combined with its jitter.
+    let selection_jitter = (0.03f64.powi(2) / 0.02 / 150.0).sqrt();
+    assert!(close(estimate.jitter, (0.001f64.powi(2) + selection_jitter.powi(2)).sqrt()));
+    assert_eq!(estimate.system_peer, 0);
+    assert_eq!(estimate.survivors, [0, 1]);
+
+    assert_eq!(combine(&candidates, &[]), None);
+}
+
+#[test]
+fn combine_clamps_root_distance() {
+    // A zero root distance weighs as MINDISP rather than infinitely.
+    let candidates = [candidate(0.0, 0.001, 0.0), candidate(0.01, 0.001, MINDISP)];
+    let estimate = combine(&candidates, &[0, 1]).unwrap();
+    assert!(close(estimate.offset, 0.005));
+    assert!(estimate.jitter.is_finite());
+}
+
+#[test]
+fn mitigates_candidates() {
+    let mut candidates = truechimers(0.0001);
+    candidates.push(candidate(0.9, 0.0001, 0.05));
+    let estimate = mitigate(&candidates).unwrap();
+    assert_eq!(estimate.system_peer, 0);
+    assert_eq!(estimate.survivors, [0, 1, 2]);
+    assert!(estimate.offset > 0.0 && estimate.offset < 0.002);
+
+    let disagreeing = [candidate(0.0, 0.0, 0.01), candidate(0.5, 0.0, 0.01)];
+    assert_eq!(mitigate(&disagreeing), None);
+}
@@ -0,0 +1,56 @@
+//! Packet fixtures shared by the integration tests.
+
+#![allow(dead_code)]
+
+#[cfg(feature = "std")]
+use ntp::protocol::KissOfDeath;
+use ntp::protocol::{
+    LeapIndicator, Mode, Packet, PrimarySource, ReferenceIdentifier, ShortFormat, Stratum,
+    TimestampFormat, Version,
+};
+
+/// A valid response from a primary server reading `time`, with a zero origin timestamp.
+pub fn response_at(time: TimestampFormat) -> Packet {
+    Packet {
+        leap_indicator: LeapIndicator::NoWarning,
+        version: Version::V4,
+        mode: Mode::Server,
+        stratum: Stratum::PRIMARY,
+        poll: 0,
+        precision: -20,
+        root_delay: ShortFormat::default(),
+        root_dispersion: ShortFormat::default(),
+        reference_id: ReferenceIdentifier::PrimarySource(PrimarySource::Gps),
+        reference_timestamp: time,
+        origin_timestamp: TimestampFormat::default(),
+        receive_timestamp: time,
+        transmit_timestamp: time,
+    }
+}
+
+/// A valid response from a primary server whose clock agrees with the system clock.
+#[cfg(feature = "std")]
+pub fn response() -> Packet {
+    response_at(ntp::unix_time::Instant::now().into())
+}
+
+/// The response to `request` from a primary server reading `time`.
+pub fn reply_to(request: &Packet, time: TimestampFormat) -> Packet {
+    Packet {
+        version: request.version,
+        poll: request.poll,
+        origin_timestamp: request.transmit_timestamp,
+        ..response_at(time)
+    }
+}
+
+/// A kiss-o'-death response carrying `code`.
+#[cfg(feature = "std")]
+pub fn kiss(code: KissOfDeath) -> Packet {
+    Packet {
+        leap_indicator: LeapIndicator::Unknown,
+        stratum: Stratum::UNSPECIFIED,
+        reference_id: ReferenceIdentifier::KissOfDeath(code),
+        ..response()
+    }
+}
@@ -0,0 +1,136 @@
+#![cfg(feature = "std")]
+
+use ntp::discipline::{ClockDiscipline, Command, State, Update};
+use ntp::protocol::{TimestampFormat, MAXFREQ, MAXPOLL, MINPOLL, WATCH};
+
+fn at(seconds: u32) -> TimestampFormat {
+    TimestampFormat { seconds, fraction: 0 }
+}
+
+fn adjust(update: Update) -> Vec<Command> {
+    match update {
+        Update::Adjust(commands) => commands,
+        update => panic!("expected commands, got {:?}", update),
+    }
+}
+
+// A discipline that has measured its frequency and is synchronized at time 1000.
+fn synchronized() -> ClockDiscipline {
+    let mut discipline = ClockDiscipline::new();
+    discipline.update(0.001, at(0));
+    discipline.update(0.001, at(1000));
+    assert_eq!(discipline.state(), State::Sync);
+    discipline
+}
+
+#[test]
+fn measures_frequency() {
+    let mut discipline = ClockDiscipline::new();
+    assert_eq!(discipline.update(0.010, at(0)), Update::Ignore);
+    assert_eq!(discipline.state(), State::Freq);
+    // Offsets are held until the stepout threshold.
+    assert_eq!(discipline.update(0.012, at(64)), Update::Ignore);
+
+    let commands = adjust(discipline.update(-0.010, at(1000)));
+    assert_eq!(commands[0], Command::Slew(-0.010));
+    match commands[1] {
+        Command::SetFrequency(frequency) => assert!((frequency + 20e-6).abs() < 1e-12),
+        command => panic!("expected frequency, got {:?}", command),
+    }
+    assert_eq!(discipline.state(), State::Sync);
+}
+
+#[test]
+fn steps_initial_offset() {
+    let mut discipline = ClockDiscipline::new();
+    assert_eq!(adjust(discipline.update(0.5, at(0))), [Command::Step(0.5)]);
+    assert_eq!(discipline.state(), State::Freq);
+    assert_eq!(discipline.poll(), MINPOLL);
+}
+
+#[test]
+fn starts_from_saved_frequency() {
+    let mut discipline = ClockDiscipline::with_frequency(12e-6);
+    assert_eq!(discipline.state(), State::Fset);
+    let commands = adjust(discipline.update(0.002, at(0)));
+    assert_eq!(commands, [Command::Slew(0.002), Command::SetFrequency(12e-6)]);
+    assert_eq!(discipline.state(), State::Sync);
+
+    // A large offset is stepped straight away, without waiting for the stepout threshold.
+    let mut discipline = ClockDiscipline::with_frequency(1.0);
+    assert_eq!(discipline.frequency(), MAXFREQ);
+    let step = adjust(discipline.update(-2.0, at(0)));
+    assert_eq!(step, [Command::Step(-2.0), Command::SetFrequency(MAXFREQ)]);
+}
+
+#[test]
+fn holds_spikes_until_stepout() {
+    let mut discipline = synchronized();
+    assert_eq!(discipline.update(0.3, at(1064)), Update::Ignore);
+    assert_eq!(discipline.state(), State::Spik);
+    assert_eq!(discipline.update(0.3, at(1128)), Update::Ignore);
+
+    // An inlier ends the spike.
+    adjust(discipline.update(0.002, at(1192)));
+    assert_eq!(discipline.state(), State::Sync);
+
+    // An outlier persisting past the stepout threshold is stepped.
+    assert_eq!(discipline.update(0.3, at(1256)), Update::Ignore);
+    let stepout = 1192 + WATCH as u32;
+    assert_eq!(discipline.update(0.3, at(stepout - 1)), Update::Ignore);
+    assert_eq!(adjust(discipline.update(0.3, at(stepout)))[0], Command::Step(0.3));
+    assert_eq!(discipline.state(), State::Sync);
+}
+
+#[test]
+fn panics_on_huge_offsets() {
+    let mut discipline = synchronized();
+    assert_eq!(discipline.update(-1500.0, at(1064)), Update::Panic);
+    assert_eq!(discipline.state(), State::Sync);
+}
+
+#[test]
+fn adjusts_poll_interval() {
+    let mut discipline = synchronized();
+    let mut t = 1000;
+    for _ in 0..20 {
+        t += 1 << discipline.poll();
+        discipline.update(0.0, at(t));
+    }
+    assert!(discipline.poll() > MINPOLL + 1);
+
+    // A persistent offset well above the jitter shortens the poll interval again.
+    let poll = discipline.poll();
+    for _ in 0..12 {
+        t += 1 << discipline.poll();
+        discipline.update(0.1, at(t));
+    }
+    assert!(discipline.poll() < poll);
+    assert!(discipline.poll() <= MAXPOLL);
+}
+
+// A local clock running 50 PPM fast is steered by the commands until it keeps time.
+#[test]
+fn disciplines_simulated_clock() {
+    let error = 50e-6;
+    let mut discipline = ClockDiscipline::new();
+    let mut offset = 0.05;
+    let mut frequency = 0.0;
+    let mut t = 0;
+    for _ in 0..200 {
+        let poll = 1u32 << discipline.poll();
+        if let Update::Adjust(commands) = discipline.update(offset, at(t)) {
+            for command in commands {
+                match command {
+                    Command::Step(step) | Command::Slew(step) => offset -= step,
+                    Command::SetFrequency(correction) => frequency = correction,
+                }
+            }
+        }
+        t += poll;
+        offset -= (error + frequency) * poll as f64;
+    }
+    assert!((discipline.frequency() + error).abs() < 1e-6);
+    assert!(offset.abs() < 0.001);
+    assert_eq!(discipline.state(), State::Sync);
+}
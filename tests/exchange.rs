@@ -0,0 +1,257 @@
+#![cfg(feature = "std")]
+
+use ntp::client::{Client, ResponseError};
+use ntp::exchange::{Action, Exchange, Protection};
+use ntp::protocol::{
+    KissOfDeath, LeapIndicator, Packet, ReferenceIdentifier, Stratum, TimestampFormat,
+};
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+mod common;
+
+fn server() -> SocketAddr {
+    "192.0.2.1:123".parse().unwrap()
+}
+
+fn timestamp(seconds: u32) -> TimestampFormat {
+    TimestampFormat { seconds, fraction: 0 }
+}
+
+fn quarters(seconds: u32, quarters: u32) -> TimestampFormat {
+    TimestampFormat { seconds, fraction: quarters << 30 }
+}
+
+// The response to the request `bytes`, received and transmitted at server time `at`.
+fn respond(bytes: &[u8], at: TimestampFormat) -> [u8; 48] {
+    let request = Packet::decode(bytes).unwrap();
+    let response = common::reply_to(&request, at);
+    let mut out = [0u8; 48];
+    response.encode(&mut out).unwrap();
+    out
+}
+
+fn expect_send(action: Action) -> Vec<u8> {
+    match action {
+        Action::Send { addr, bytes } => {
+            assert_eq!(addr, server());
+            bytes
+        }
+        action => panic!("expected send, got {:?}", action),
+    }
+}
+
+#[test]
+fn successful_exchange() {
+    let start = Instant::now();
+    let config = Client::new().timeout(Duration::from_secs(1));
+    let mut exchange = Exchange::new(config, server(), start);
+
+    let bytes = expect_send(exchange.poll(start, timestamp(100)));
+    assert_eq!(exchange.transmit_timestamp(), Some(timestamp(100)));
+    match exchange.poll(start, timestamp(100)) {
+        Action::Receive { deadline } => assert_eq!(deadline, Some(start + Duration::from_secs(1))),
+        action => panic!("expected receive, got {:?}", action),
+    }
+
+    let response = respond(&bytes, quarters(110, 1));
+    exchange.receive(server(), &response, quarters(100, 2), start);
+    assert!(exchange.is_done());
+    match exchange.poll(start, timestamp(102)) {
+        Action::Done(Ok(measurement)) => {
+            assert_eq!(measurement.t1, timestamp(100));
+            assert_eq!(measurement.t4, quarters(100, 2));
+            assert_eq!(measurement.offset, 10.0);
+            assert_eq!(measurement.delay, 0.5);
+        }
+        action => panic!("expected measurement, got {:?}", action),
+    }
+
+    // A finished exchange reports an error rather than repeating its result.
+    match exchange.poll(start, timestamp(102)) {
+        Action::Done(Err(_)) => (),
+        action => panic!("expected error, got {:?}", action),
+    }
+}
+
+#[test]
+fn retries_with_backoff_until_exhausted() {
+    let start = Instant::now();
+    let second = Duration::from_secs(1);
+    let config = Client::new().timeout(second).retries(2).backoff(second);
+    let mut exchange = Exchange::new(config, server(), start);
+
+    // The first attempt times out at its deadline and the retry waits for the backoff.
+    expect_send(exchange.poll(start, timestamp(0)));
+    let timeout = start + second;
+    match exchange.poll(timeout, timestamp(1)) {
+        Action::Sleep { until } => assert_eq!(until, timeout + second),
+        action => panic!("expected sleep, got {:?}", action),
+    }
+    expect_send(exchange.poll(timeout + second, timestamp(2)));
+    assert_eq!(exchange.transmit_timestamp(), Some(timestamp(2)));
+
+    // The backoff doubles for the next retry.
+    let timeout = timeout + 2 * second;
+    match exchange.poll(timeout, timestamp(3)) {
+        Action::Sleep { until } => assert_eq!(until, timeout + 2 * second),
+        action => panic!("expected sleep, got {:?}", action),
+    }
+    expect_send(exchange.poll(timeout + 2 * second, timestamp(5)));
+
+    // The last attempt fails the exchange with its error.
+    let err = io::Error::new(io::ErrorKind::ConnectionRefused, "refused");
+    exchange.fail(err, timeout + 2 * second);
+    match exchange.poll(timeout + 2 * second, timestamp(5)) {
+        Action::Done(Err(err)) => assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused),
+        action => panic!("expected error, got {:?}", action),
+    }
+}
+
+#[test]
+fn drops_bogus_datagrams() {
+    let start = Instant::now();
+    let mut exchange = Exchange::new(Client::new(), server(), start);
+    let bytes = expect_send(exchange.poll(start, timestamp(100)));
+    let response = respond(&bytes, timestamp(100));
+
+    // A spoofed reply, a truncated datagram and a stale reply to another request are dropped.
+    let spoofer: SocketAddr = "198.51.100.1:123".parse().unwrap();
+    exchange.receive(spoofer, &response, timestamp(100), start);
+    exchange.receive(server(), &response[..20], timestamp(100), start);
+    let mut stale = response;
+    stale[24] ^= 1;
+    exchange.receive(server(), &stale, timestamp(100), start);
+    assert!(!exchange.is_done());
+    assert!(matches!(exchange.poll(start, timestamp(100)), Action::Receive { .. }));
+
+    // The genuine reply is still accepted.
+    exchange.receive(server(), &response, timestamp(100), start);
+    match exchange.poll(start, timestamp(100)) {
+        Action::Done(Ok(measurement)) => assert_eq!(measurement.t1, timestamp(100)),
+        action => panic!("expected measurement, got {:?}", action),
+    }
+}
+
+#[test]
+fn times_out_with_last_dropped_datagram() {
+    let start = Instant::now();
+    let second = Duration::from_secs(1);
+    let mut exchange = Exchange::new(Client::new().timeout(second), server(), start);
+    let bytes = expect_send(exchange.poll(start, timestamp(100)));
+    let spoofer: SocketAddr = "198.51.100.1:123".parse().unwrap();
+    exchange.receive(spoofer, &respond(&bytes, timestamp(100)), timestamp(100), start);
+    match exchange.poll(start + second, timestamp(101)) {
+        Action::Done(Err(err)) => assert_eq!(
+            ResponseError::from_io_error(&err),
+            Some(ResponseError::UnexpectedSource(spoofer))
+        ),
+        action => panic!("expected rejection, got {:?}", action),
+    }
+}
+
+#[test]
+fn kiss_of_death_is_not_retried() {
+    let start = Instant::now();
+    let mut exchange = Exchange::new(Client::new().retries(3), server(), start);
+    let bytes = expect_send(exchange.poll(start, timestamp(100)));
+    let mut kiss = Packet::decode(&respond(&bytes, timestamp(100))).unwrap();
+    kiss.leap_indicator = LeapIndicator::Unknown;
+    kiss.stratum = Stratum::UNSPECIFIED;
+    kiss.reference_id = ReferenceIdentifier::KissOfDeath(KissOfDeath::Rstr);
+    let mut response = [0u8; 48];
+    kiss.encode(&mut response).unwrap();
+
+    exchange.receive(server(), &response, timestamp(100), start);
+    match exchange.poll(start, timestamp(100)) {
+        Action::Done(Err(err)) => assert_eq!(
+            ResponseError::from_io_error(&err),
+            Some(ResponseError::KissOfDeath(KissOfDeath::Rstr))
+        ),
+        action => panic!("expected rejection, got {:?}", action),
+    }
+}
+
+#[test]
+fn ignores_datagrams_when_not_waiting() {
+    let start = Instant::now();
+    let mut exchange = Exchange::new(Client::new(), server(), start);
+    exchange.receive(server(), &[0u8; 48], timestamp(0), start);
+    assert!(!exchange.is_done());
+    expect_send(exchange.poll(start, timestamp(0)));
+}
+
+// Tags requests with a trailing byte, and accepts only responses that echo it.
+struct Tag(u8);
+
+impl Protection for Tag {
+    fn protect(&mut self, request: &Packet) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0u8; 48];
+        request.encode(&mut bytes)?;
+        bytes.push(self.0);
+        Ok(bytes)
+    }
+
+    fn verify(&mut self, bytes: &[u8], _: &Packet) -> io::Result<()> {
+        if bytes.get(48) != Some(&self.0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "missing tag"));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn drops_unverified_responses() {
+    let start = Instant::now();
+    let mut exchange = Exchange::with_protection(Client::new(), server(), start, Tag(7));
+    let bytes = expect_send(exchange.poll(start, timestamp(100)));
+    assert_eq!(bytes.len(), 49);
+    assert_eq!(bytes[48], 7);
+
+    let mut response = respond(&bytes[..48], timestamp(100)).to_vec();
+    exchange.receive(server(), &response, timestamp(100), start);
+    assert!(!exchange.is_done());
+    response.push(7);
+    exchange.receive(server(), &response, timestamp(100), start);
+    assert!(matches!(exchange.poll(start, timestamp(100)), Action::Done(Ok(_))));
+}
+
+#[test]
+fn huge_backoff_and_timeout_do_not_overflow() {
+    let start = Instant::now();
+    let config = Client::new().timeout(Duration::MAX).retries(u32::MAX).backoff(Duration::MAX);
+    let mut exchange = Exchange::new(config, server(), start);
+
+    // A timeout too long to represent waits indefinitely.
+    expect_send(exchange.poll(start, timestamp(0)));
+    match exchange.poll(start, timestamp(0)) {
+        Action::Receive { deadline } => assert_eq!(deadline, None),
+        action => panic!("expected receive, got {:?}", action),
+    }
+
+    // A retry that could never be scheduled fails the exchange instead.
+    let err = io::Error::new(io::ErrorKind::ConnectionRefused, "refused");
+    exchange.fail(err, start);
+    match exchange.poll(start, timestamp(0)) {
+        Action::Done(Err(err)) => assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused),
+        action => panic!("expected error, got {:?}", action),
+    }
+
+    // The doubling backoff saturates over many retries.
+    let second = Duration::from_secs(1);
+    let config = Client::new().timeout(second).retries(u32::MAX).backoff(second);
+    let mut exchange = Exchange::new(config, server(), start);
+    let mut now = start;
+    for _ in 0..100 {
+        expect_send(exchange.poll(now, timestamp(0)));
+        let err = io::Error::new(io::ErrorKind::ConnectionRefused, "refused");
+        exchange.fail(err, now);
+        match exchange.poll(now, timestamp(0)) {
+            Action::Sleep { until } => now = until,
+            Action::Done(Err(_)) => return,
+            action => panic!("expected sleep or error, got {:?}", action),
+        }
+    }
+    panic!("expected the exchange to fail once the backoff saturates");
+}
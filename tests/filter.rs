@@ -0,0 +1,101 @@
+#![cfg(feature = "std")]
+
+use ntp::filter::{ClockFilter, Sample};
+use ntp::measurement::Measurement;
+use ntp::protocol::{Packet, TimestampFormat, MAXDISP, NSTAGE, TOLERANCE};
+
+mod common;
+
+const POLL: i8 = 6;
+
+fn sample(seconds: u32, offset: f64, delay: f64) -> Sample {
+    Sample {
+        offset,
+        delay,
+        dispersion: 0.001,
+        epoch: TimestampFormat { seconds, fraction: 0 },
+    }
+}
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-9
+}
+
+#[test]
+fn first_sample() {
+    let mut filter = ClockFilter::new();
+    assert!(filter.statistics().is_none());
+    let statistics = filter.update(sample(64, 0.01, 0.05), POLL).unwrap();
+    assert_eq!(statistics.offset, 0.01);
+    assert_eq!(statistics.delay, 0.05);
+    assert_eq!(statistics.epoch, TimestampFormat { seconds: 64, fraction: 0 });
+    // The empty stages count at the maximum dispersion.
+    let empty = MAXDISP * (0.5 - 0.5f64.powi(NSTAGE as i32));
+    assert!(close(statistics.dispersion, 0.0005 + empty));
+    assert_eq!(statistics.jitter, 2f64.powi(-20));
+    assert_eq!(filter.statistics(), Some(statistics));
+}
+
+#[test]
+fn chooses_lowest_delay() {
+    let mut filter = ClockFilter::new();
+    filter.update(sample(0, 0.010, 0.050), POLL).unwrap();
+    let statistics = filter.update(sample(64, 0.002, 0.020), POLL).unwrap();
+    assert_eq!(statistics.offset, 0.002);
+    assert_eq!(statistics.delay, 0.020);
+    assert!(close(statistics.jitter, 0.008));
+
+    filter.update(sample(128, 0.004, 0.030), POLL);
+    assert_eq!(filter.statistics().unwrap().offset, 0.002);
+}
+
+#[test]
+fn ages_dispersion() {
+    let mut filter = ClockFilter::new();
+    filter.update(sample(0, 0.0, 0.01), POLL);
+    filter.update(sample(1000, 0.0, 0.01), POLL);
+    let samples = filter.samples();
+    assert!(close(samples[0].dispersion, 0.001));
+    assert!(close(samples[1].dispersion, 0.001 + 1000.0 * TOLERANCE));
+    assert_eq!(samples[2].dispersion, MAXDISP);
+}
+
+#[test]
+fn uses_samples_once() {
+    let mut filter = ClockFilter::new();
+    let first = filter.update(sample(0, 0.001, 0.010), POLL).unwrap();
+    // A newer sample with a higher delay leaves the old one chosen, which was already used.
+    assert!(filter.update(sample(64, 0.003, 0.020), POLL).is_none());
+    assert_eq!(filter.statistics(), Some(first));
+}
+
+#[test]
+fn suppresses_popcorn_spikes() {
+    let mut filter = ClockFilter::new();
+    for (i, &offset) in [0.001, 0.002, 0.001, 0.002].iter().enumerate() {
+        filter.update(sample(64 * i as u32, offset, 0.02 - 0.001 * i as f64), POLL);
+    }
+    let last = filter.statistics().unwrap();
+    assert!(filter.update(sample(256, 0.5, 0.01), POLL).is_none());
+    assert_eq!(filter.statistics(), Some(last));
+
+    // Once the last sample used is two poll intervals old, the offset is believed.
+    let statistics = filter.update(sample(320, 0.5, 0.005), POLL).unwrap();
+    assert_eq!(statistics.offset, 0.5);
+}
+
+#[test]
+fn sample_from_measurement() {
+    let t = |seconds| TimestampFormat { seconds, fraction: 0 };
+    let packet = Packet {
+        reference_timestamp: t(101),
+        origin_timestamp: t(100),
+        ..common::response_at(t(102))
+    };
+    let measurement = Measurement::new(&packet, t(100));
+    let sample = Sample::from(&measurement);
+    assert_eq!(sample.offset, 2.0);
+    assert_eq!(sample.delay, 0.0);
+    assert_eq!(sample.dispersion, measurement.dispersion);
+    assert_eq!(sample.epoch, t(100));
+}
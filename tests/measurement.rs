@@ -0,0 +1,55 @@
+extern crate ntp;
+
+use ntp::measurement::{self, Measurement};
+use ntp::protocol::{Packet, ShortFormat, TimestampFormat};
+
+mod common;
+
+fn timestamp(seconds: u32, millis: u32) -> TimestampFormat {
+    let fraction = (millis as u64 * (1u64 << 32) / 1000) as u32;
+    TimestampFormat { seconds, fraction }
+}
+
+fn response(t1: TimestampFormat, t2: TimestampFormat, t3: TimestampFormat) -> Packet {
+    Packet {
+        poll: 6,
+        root_delay: ShortFormat { seconds: 0, fraction: 0x0800 },
+        root_dispersion: ShortFormat { seconds: 0, fraction: 0x0400 },
+        origin_timestamp: t1,
+        transmit_timestamp: t3,
+        ..common::response_at(t2)
+    }
+}
+
+fn assert_close(actual: f64, expected: f64) {
+    assert!((actual - expected).abs() < 1e-6, "{} != {}", actual, expected);
+}
+
+#[test]
+fn offset_and_delay() {
+    // The server is 1 s ahead, with 50 ms of network delay each way and 10 ms of processing.
+    let t1 = timestamp(3_800_000_000, 0);
+    let t2 = timestamp(3_800_000_001, 50);
+    let t3 = timestamp(3_800_000_001, 60);
+    let t4 = timestamp(3_800_000_000, 110);
+    let measurement = Measurement::new(&response(t1, t2, t3), t4);
+    assert_eq!((measurement.t1, measurement.t2, measurement.t3, measurement.t4), (t1, t2, t3, t4));
+    assert_close(measurement.offset, 1.0);
+    assert_close(measurement.delay, 0.1);
+    let dispersion = 2f64.powi(-20) + 15e-6 * 0.11;
+    assert_close(measurement.dispersion, dispersion);
+    let root_distance = (0.03125 + 0.1) / 2.0 + 0.015625 + dispersion;
+    assert_close(measurement.root_distance, root_distance);
+}
+
+#[test]
+fn offset_across_era_boundary() {
+    let t1 = timestamp(u32::MAX, 900);
+    let t2 = timestamp(0, 0);
+    let t3 = timestamp(0, 0);
+    let t4 = timestamp(0, 100);
+    let measurement = Measurement::new(&response(t1, t2, t3), t4);
+    assert_close(measurement.delay, 0.2);
+    assert_close(measurement.offset, 0.0);
+    assert_close(measurement::difference(t1, t4), -0.2);
+}
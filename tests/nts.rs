@@ -0,0 +1,340 @@
+#![cfg(feature = "nts")]
+
+extern crate ntp;
+extern crate rcgen;
+extern crate rustls;
+
+use ntp::client::{Client, ResponseError};
+use ntp::nts::ke::{self, Record};
+use ntp::nts::server::{KeServer, MasterKeys, NtpServer, MASTER_KEY_HISTORY};
+use ntp::nts::{self, AeadKey, Authenticator};
+use ntp::protocol::{
+    ConstPackedSizeBytes, ExtendedPacket, ExtensionField, ExtensionFieldType, KissOfDeath, Mode,
+    Packet, ReadBytes, ReferenceIdentifier, Stratum, TimestampFormat, WriteBytes,
+};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::pki_types::ServerName;
+use rustls::{ClientConnection, RootCertStore, ServerConfig, ServerConnection, StreamOwned};
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::net::{TcpListener, UdpSocket};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+mod common;
+
+const COOKIE_LEN: usize = 64;
+
+// A self-signed certificate for "localhost" together with a matching client configuration.
+fn tls_configs() -> (Arc<ServerConfig>, Arc<rustls::ClientConfig>) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+    let cert = CertificateDer::from(certified.cert.der().to_vec());
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der()));
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut server_config = ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.clone()], key)
+        .unwrap();
+    server_config.alpn_protocols = vec![nts::ALPN_NTSKE.to_vec()];
+    let mut roots = RootCertStore::empty();
+    roots.add(cert).unwrap();
+    (Arc::new(server_config), nts::client_config(roots).unwrap())
+}
+
+fn system_packet() -> Packet {
+    common::response_at(TimestampFormat::default())
+}
+
+// Run a stand-in NTS-KE server for a single connection that hands out `cookies` cookies and
+// directs the client to `ntp_port`. Returns the KE port and the exported keys.
+fn serve_key_exchange(
+    config: Arc<ServerConfig>,
+    ntp_port: u16,
+    cookies: u8,
+) -> (u16, mpsc::Receiver<(AeadKey, AeadKey)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let (sock, _) = listener.accept().unwrap();
+        let mut tls = StreamOwned::new(ServerConnection::new(config).unwrap(), sock);
+        while tls.read_bytes::<Record>().unwrap() != Record::EndOfMessage {}
+        let mut response = vec![];
+        response.write_bytes(Record::NextProtocol(vec![nts::PROTOCOL_NTPV4])).unwrap();
+        response.write_bytes(Record::AeadAlgorithm(vec![nts::AEAD_AES_SIV_CMAC_256])).unwrap();
+        response.write_bytes(Record::Server("127.0.0.1".to_owned())).unwrap();
+        response.write_bytes(Record::Port(ntp_port)).unwrap();
+        for i in 0..cookies {
+            response.write_bytes(Record::NewCookie(vec![i; COOKIE_LEN])).unwrap();
+        }
+        response.write_bytes(Record::EndOfMessage).unwrap();
+        tls.write_all(&response).unwrap();
+        tx.send(nts::exported_keys(&tls.conn).unwrap()).unwrap();
+        tls.conn.send_close_notify();
+        tls.flush().unwrap();
+    });
+    (port, rx)
+}
+
+// How a stand-in NTP server answers a request.
+#[derive(Clone, Copy, PartialEq)]
+enum Reply {
+    Genuine,
+    // The response authenticator is corrupted.
+    Tampered,
+    // An NTSN kiss-o'-death rejecting the cookie.
+    Nak,
+}
+
+// Answer one NTS-protected request with each of `replies` in turn.
+fn answer_request(sock: &UdpSocket, c2s: &AeadKey, s2c: &AeadKey, replies: &[Reply]) {
+    let mut buf = [0u8; 1024];
+    let (len, src) = sock.recv_from(&mut buf).unwrap();
+    let fields = nts::read_extension_fields(&buf[Packet::PACKED_SIZE_BYTES..len]).unwrap();
+    let authenticator = fields.last().unwrap();
+    let associated_data = &buf[..len - authenticator.packed_size_bytes()];
+    Authenticator::from_extension_field(authenticator)
+        .unwrap()
+        .open(c2s, associated_data)
+        .unwrap();
+    let unique_id = fields
+        .iter()
+        .find(|f| f.field_type == ExtensionFieldType::UNIQUE_IDENTIFIER)
+        .unwrap()
+        .clone();
+    let placeholders = fields
+        .iter()
+        .filter(|f| f.field_type == ExtensionFieldType::NTS_COOKIE_PLACEHOLDER)
+        .count();
+
+    let request = (&buf[..Packet::PACKED_SIZE_BYTES]).read_bytes::<Packet>().unwrap();
+    for &reply in replies {
+        let packet = match reply {
+            Reply::Nak => Packet {
+                origin_timestamp: request.transmit_timestamp,
+                ..common::kiss(KissOfDeath::Ntsn)
+            },
+            _ => common::reply_to(&request, ntp::unix_time::Instant::now().into()),
+        };
+        let mut response = ExtendedPacket::from(packet);
+        response.extension_fields.push(unique_id.clone());
+        if reply != Reply::Nak {
+            let mut plaintext = vec![];
+            for _ in 0..placeholders + 1 {
+                let cookie = vec![0xcc; COOKIE_LEN];
+                let cookie = ExtensionField::new(ExtensionFieldType::NTS_COOKIE, cookie);
+                plaintext.write_bytes(cookie).unwrap();
+            }
+            let associated_data = nts::associated_data(&response).unwrap();
+            let mut authenticator = Authenticator::seal(s2c, &plaintext, &associated_data);
+            if reply == Reply::Tampered {
+                authenticator.ciphertext[0] ^= 1;
+            }
+            response.extension_fields.push(authenticator.to_extension_field());
+        }
+        let mut bytes = vec![];
+        bytes.write_bytes(&response).unwrap();
+        sock.send_to(&bytes, src).unwrap();
+    }
+}
+
+#[test]
+fn record_roundtrip() {
+    let records = vec![
+        Record::NextProtocol(vec![0]),
+        Record::AeadAlgorithm(vec![15, 30]),
+        Record::NewCookie(vec![1, 2, 3]),
+        Record::Server("ntp.example.com".to_owned()),
+        Record::Port(123),
+        Record::Error(ke::ERROR_BAD_REQUEST),
+        Record::EndOfMessage,
+    ];
+    let mut bytes = vec![];
+    for record in &records {
+        bytes.write_bytes(record).unwrap();
+    }
+    assert_eq!(&bytes[..8], &[0x80, 1, 0, 2, 0, 0, 0x80, 4]);
+    let mut reader = &bytes[..];
+    for record in &records {
+        assert_eq!(&reader.read_bytes::<Record>().unwrap(), record);
+    }
+    assert!(reader.is_empty());
+}
+
+#[test]
+fn record_unknown_critical() {
+    let bytes = [0x80, 0x42, 0, 1, 7];
+    let record = (&bytes[..]).read_bytes::<Record>().unwrap();
+    assert_eq!(record, Record::Unknown { critical: true, record_type: 0x42, body: vec![7] });
+    assert!(record.is_critical());
+}
+
+#[test]
+fn record_server_must_be_ascii() {
+    let bytes = [0, 6, 0, 2, 0xc3, 0xa9];
+    let err = (&bytes[..]).read_bytes::<Record>().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn authenticator_field_roundtrip() {
+    let key = AeadKey::generate();
+    let authenticator = Authenticator::seal(&key, b"secret", b"header");
+    let field = authenticator.to_extension_field();
+    assert_eq!(field.value.len() % 4, 0);
+    let decoded = Authenticator::from_extension_field(&field).unwrap();
+    assert_eq!(decoded, authenticator);
+    assert_eq!(decoded.open(&key, b"header").unwrap(), b"secret");
+    assert!(decoded.open(&key, b"other header").is_err());
+}
+
+#[test]
+fn key_exchange_and_request() {
+    let (server_config, client_config) = tls_configs();
+    let ntp_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let ntp_port = ntp_sock.local_addr().unwrap().port();
+    let (ke_port, keys) = serve_key_exchange(server_config, ntp_port, 2);
+
+    let mut session =
+        ke::key_exchange(("127.0.0.1", ke_port), "localhost", client_config).unwrap();
+    assert_eq!(session.cookies(), 2);
+    assert_eq!(session.ntp_server(), ("127.0.0.1", ntp_port));
+    let (c2s, s2c) = keys.recv().unwrap();
+
+    let server = thread::spawn(move || {
+        answer_request(&ntp_sock, &c2s, &s2c, &[Reply::Tampered, Reply::Genuine]);
+        answer_request(&ntp_sock, &c2s, &s2c, &[Reply::Tampered]);
+        answer_request(&ntp_sock, &c2s, &s2c, &[Reply::Nak]);
+    });
+    // A forged response is dropped and the authentic one that follows is accepted.
+    let response = session.request().unwrap();
+    assert_eq!(response.packet.mode, Mode::Server);
+    // The request carried placeholders to top the supply back up to the target.
+    assert_eq!(session.cookies(), nts::COOKIE_TARGET);
+
+    let client = Client::new().timeout(Duration::from_millis(200));
+    let err = session.request_with(&client).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), "NTS authenticator verification failed");
+    assert_eq!(session.cookies(), nts::COOKIE_TARGET - 1);
+
+    // A rejected cookie is reported and not retried.
+    let err = session.request_with(&client.retries(2)).unwrap_err();
+    assert_eq!(
+        ResponseError::from_io_error(&err),
+        Some(ResponseError::KissOfDeath(KissOfDeath::Ntsn))
+    );
+    assert_eq!(session.cookies(), nts::COOKIE_TARGET - 2);
+    server.join().unwrap();
+}
+
+#[test]
+fn key_exchange_rejects_untrusted_certificate() {
+    let (server_config, _) = tls_configs();
+    let (_, client_config) = tls_configs();
+    let (ke_port, _keys) = serve_key_exchange(server_config, 123, 8);
+    assert!(ke::key_exchange(("127.0.0.1", ke_port), "localhost", client_config).is_err());
+}
+
+#[test]
+fn key_exchange_times_out() {
+    let (_, client_config) = tls_configs();
+    // The listener accepts connections into its backlog but never answers the handshake.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = Client::new().timeout(Duration::from_millis(100));
+    let err = ke::key_exchange_with(addr, "localhost", client_config, &client).unwrap_err();
+    assert!(
+        err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut,
+        "{:?}",
+        err
+    );
+}
+
+#[test]
+fn nts_server_roundtrip() {
+    let (server_config, client_config) = tls_configs();
+    let master_keys = MasterKeys::new(Duration::from_secs(3600));
+    let ntp_sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let ntp_port = ntp_sock.local_addr().unwrap().port();
+    let ntp_server = NtpServer::new(master_keys.clone(), system_packet());
+    thread::spawn(move || ntp_server.serve(&ntp_sock));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let ke_port = listener.local_addr().unwrap().port();
+    let ke_server = KeServer::new(server_config, master_keys)
+        .ntp_server(Some("127.0.0.1".to_owned()), Some(ntp_port));
+    thread::spawn(move || ke_server.serve(&listener));
+
+    let mut session =
+        ke::key_exchange(("127.0.0.1", ke_port), "localhost", client_config).unwrap();
+    assert_eq!(session.cookies(), nts::COOKIE_TARGET);
+    assert_eq!(session.ntp_server(), ("127.0.0.1", ntp_port));
+    for _ in 0..2 {
+        let response = session.request().unwrap();
+        assert_eq!(response.packet.mode, Mode::Server);
+        assert_eq!(response.packet.stratum, Stratum::PRIMARY);
+        assert_eq!(session.cookies(), nts::COOKIE_TARGET);
+    }
+}
+
+#[test]
+fn ke_server_rejects_request_without_aead_algorithm() {
+    let (server_config, client_config) = tls_configs();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let ke_server = KeServer::new(server_config, MasterKeys::new(Duration::from_secs(3600)))
+        .max_connections(1);
+    thread::spawn(move || ke_server.serve(&listener));
+
+    let name = ServerName::try_from("localhost").unwrap();
+    let conn = ClientConnection::new(client_config, name).unwrap();
+    let mut tls = StreamOwned::new(conn, std::net::TcpStream::connect(addr).unwrap());
+    let mut request = vec![];
+    request.write_bytes(Record::NextProtocol(vec![nts::PROTOCOL_NTPV4])).unwrap();
+    request.write_bytes(Record::EndOfMessage).unwrap();
+    tls.write_all(&request).unwrap();
+    assert_eq!(tls.read_bytes::<Record>().unwrap(), Record::Error(ke::ERROR_BAD_REQUEST));
+    assert_eq!(tls.read_bytes::<Record>().unwrap(), Record::EndOfMessage);
+}
+
+#[test]
+fn nts_server_kiss_of_death_on_bad_cookie() {
+    let server = NtpServer::new(MasterKeys::new(Duration::from_secs(3600)), system_packet());
+    let mut request = ExtendedPacket::from(system_packet());
+    request.packet.mode = Mode::Client;
+    let unique_id = ExtensionField::new(ExtensionFieldType::UNIQUE_IDENTIFIER, vec![7; 32]);
+    request.extension_fields.push(unique_id.clone());
+    let cookie = ExtensionField::new(ExtensionFieldType::NTS_COOKIE, vec![0; 100]);
+    request.extension_fields.push(cookie);
+    let key = AeadKey::generate();
+    let authenticator = Authenticator::seal(&key, &[], &nts::associated_data(&request).unwrap());
+    request.extension_fields.push(authenticator.to_extension_field());
+    let mut bytes = vec![];
+    bytes.write_bytes(&request).unwrap();
+
+    let response = server.respond(&bytes, TimestampFormat::default()).unwrap();
+    let response = (&response[..]).read_bytes::<ExtendedPacket>().unwrap();
+    assert_eq!(response.packet.stratum, Stratum::UNSPECIFIED);
+    assert_eq!(response.packet.reference_id, ReferenceIdentifier::KissOfDeath(KissOfDeath::Ntsn));
+    assert_eq!(response.extension_fields, vec![unique_id]);
+}
+
+#[test]
+fn master_key_rotation() {
+    let master_keys = MasterKeys::new(Duration::from_secs(3600));
+    let (c2s, s2c) = (AeadKey::generate(), AeadKey::generate());
+    let cookie = master_keys.seal_cookie(&c2s, &s2c);
+    assert_eq!(master_keys.open_cookie(&cookie).unwrap(), (c2s.clone(), s2c.clone()));
+    master_keys.rotate();
+    assert!(master_keys.open_cookie(&cookie).is_some());
+    for _ in 1..MASTER_KEY_HISTORY {
+        master_keys.rotate();
+    }
+    assert!(master_keys.open_cookie(&cookie).is_none());
+    let mut tampered = master_keys.seal_cookie(&c2s, &s2c);
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(master_keys.open_cookie(&tampered).is_none());
+}
@@ -0,0 +1,168 @@
+#![cfg(feature = "std")]
+
+use ntp::measurement::Measurement;
+use ntp::peer::PeerAssociation;
+use ntp::protocol::{
+    KissOfDeath, Packet, Stratum, TimestampFormat, BCOUNT, BTIME, MAXPOLL, MINPOLL, UNREACH,
+};
+use std::time::{Duration, Instant};
+
+mod common;
+
+const SYSTEM_POLL: u8 = 6;
+
+fn at(seconds: u32) -> TimestampFormat {
+    TimestampFormat { seconds, fraction: 0 }
+}
+
+fn secs(seconds: u64) -> Duration {
+    Duration::from_secs(seconds)
+}
+
+fn association(now: Instant) -> PeerAssociation {
+    PeerAssociation::new("192.0.2.1:123".parse().unwrap(), now)
+}
+
+// A response to a request sent at `seconds`, from a server echoing the poll exponent `poll`.
+fn measurement(seconds: u32, poll: i8) -> Measurement {
+    let packet = Packet {
+        poll,
+        origin_timestamp: at(seconds),
+        ..common::response_at(at(seconds))
+    };
+    Measurement::new(&packet, at(seconds))
+}
+
+// Whether `next` lies within the randomized poll interval with exponent `poll` after `outdate`.
+fn scheduled(next: Instant, outdate: Instant, poll: u8) -> bool {
+    let interval = secs(1 << poll);
+    next >= outdate + interval && next <= outdate + interval + interval / 16
+}
+
+#[test]
+fn shifts_reach_register() {
+    let start = Instant::now();
+    let mut peer = association(start);
+    assert_eq!(peer.next_poll(), start);
+    let mut now = start;
+    for (i, answered) in [true, false, true, true].iter().enumerate() {
+        assert!(peer.poll(now, at(64 * i as u32), SYSTEM_POLL));
+        if *answered {
+            peer.receive(&measurement(64 * i as u32, 0), now);
+        }
+        now += secs(64);
+    }
+    assert_eq!(peer.reach(), 0b1011);
+    assert!(peer.is_reachable());
+    assert_eq!(peer.unreach(), 0);
+    assert_eq!(peer.packet().unwrap().stratum, Stratum::PRIMARY);
+    assert!(peer.filter().statistics().is_some());
+
+    // Eight unanswered polls empty the register.
+    for i in 0..8 {
+        peer.poll(now, at(256 + 64 * i), SYSTEM_POLL);
+        now += secs(64);
+    }
+    assert_eq!(peer.reach(), 0);
+    assert!(!peer.is_reachable());
+    assert_eq!(peer.unreach(), 1);
+}
+
+#[test]
+fn follows_system_poll_while_reachable() {
+    let now = Instant::now();
+    let mut peer = association(now);
+    peer.poll(now, at(0), SYSTEM_POLL);
+    assert_eq!(peer.hpoll(), MINPOLL);
+    assert!(scheduled(peer.next_poll(), now, MINPOLL));
+    peer.receive(&measurement(0, 0), now);
+    // A zero poll exponent in the response is ignored.
+    assert_eq!(peer.ppoll(), MAXPOLL);
+
+    let now = now + secs(16);
+    peer.poll(now, at(16), 8);
+    assert_eq!(peer.hpoll(), 8);
+    assert!(scheduled(peer.next_poll(), now, 8));
+
+    // A shorter poll interval of the server preempts the longer one.
+    peer.receive(&measurement(16, 7), now);
+    assert_eq!(peer.ppoll(), 7);
+    assert!(scheduled(peer.next_poll(), now, 7));
+}
+
+#[test]
+fn backs_off_while_unreachable() {
+    let mut now = Instant::now();
+    let mut peer = association(now);
+    for i in 0..UNREACH {
+        peer.poll(now, at(i), SYSTEM_POLL);
+        now = peer.next_poll();
+    }
+    assert_eq!(peer.unreach(), UNREACH);
+    assert_eq!(peer.hpoll(), MINPOLL);
+
+    // Every further unanswered poll doubles the interval, up to MAXPOLL.
+    for poll in MINPOLL + 1..=MAXPOLL + 2 {
+        peer.poll(now, at(0), SYSTEM_POLL);
+        assert_eq!(peer.hpoll(), poll.min(MAXPOLL));
+        assert!(scheduled(peer.next_poll(), now, poll.min(MAXPOLL)));
+        now = peer.next_poll();
+    }
+
+    // An answer returns the association to the system poll interval on the next poll.
+    peer.receive(&measurement(0, 0), now);
+    peer.poll(now, at(0), SYSTEM_POLL);
+    assert_eq!((peer.unreach(), peer.hpoll()), (0, SYSTEM_POLL));
+}
+
+#[test]
+fn sends_initial_burst() {
+    let start = Instant::now();
+    let mut peer = association(start).iburst(true);
+    let mut now = start;
+    for _ in 0..BCOUNT {
+        assert!(peer.poll(now, at(0), SYSTEM_POLL));
+        now += secs(BTIME);
+    }
+    // The burst is over once BCOUNT requests have been sent.
+    let last = now - secs(BTIME);
+    assert!(scheduled(peer.next_poll(), start, MINPOLL));
+    assert!(peer.next_poll() > last);
+    assert_eq!(peer.unreach(), 1);
+
+    // The burst is not repeated for a server that never answered.
+    peer.poll(peer.next_poll(), at(16), SYSTEM_POLL);
+    assert_eq!(peer.unreach(), 2);
+    assert!(peer.next_poll() > last + secs(BTIME));
+}
+
+#[test]
+fn obeys_kiss_codes() {
+    let now = Instant::now();
+    let mut peer = association(now);
+    peer.poll(now, at(0), SYSTEM_POLL);
+    peer.kiss(KissOfDeath::Rate, now);
+    assert_eq!(peer.hpoll(), MINPOLL + 1);
+    assert!(scheduled(peer.next_poll(), now, MINPOLL + 1));
+    peer.kiss(KissOfDeath::Rate, now);
+    assert_eq!(peer.hpoll(), MINPOLL + 2);
+    assert_eq!(peer.minpoll(), MINPOLL + 2);
+
+    // The back-off outlasts the next poll of a reachable peer, even at a shorter system poll.
+    peer.receive(&measurement(0, 0), now);
+    let now = peer.next_poll();
+    peer.poll(now, at(64), MINPOLL);
+    assert!(peer.is_reachable());
+    assert_eq!(peer.hpoll(), MINPOLL + 2);
+    assert!(scheduled(peer.next_poll(), now, MINPOLL + 2));
+
+    // Unknown codes are informational.
+    peer.kiss(KissOfDeath::Ntsn, now);
+    assert_eq!(peer.demobilized(), None);
+
+    peer.kiss(KissOfDeath::Deny, now);
+    assert_eq!(peer.demobilized(), Some(KissOfDeath::Deny));
+    assert!(!peer.poll(peer.next_poll(), at(128), SYSTEM_POLL));
+    assert!(peer.receive(&measurement(128, 0), now).is_none());
+    assert_eq!(peer.reach(), 0b10);
+}
@@ -1,9 +1,23 @@
+#![cfg(feature = "std")]
+#![allow(clippy::needless_borrows_for_generic_args)]
+
 extern crate ntp;
 
 use ntp::protocol::{
     LeapIndicator, Mode, ShortFormat, PrimarySource, ReadBytes, Packet, ReferenceIdentifier,
-    ConstPackedSizeBytes, Stratum, TimestampFormat, Version, WriteBytes,
+    ConstPackedSizeBytes, Stratum, TimestampFormat, Version, WriteBytes, ExtendedPacket,
+    ExtensionField, ExtensionFieldType, Mac, KissAction, KissOfDeath, Ipv6Hash,
+    PacketView, ProtocolError, Field,
 };
+use std::convert::TryFrom;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+const PACKET_BYTES: [u8; 48] = [
+    20, 1, 3, 240, 0, 0, 0, 0, 0, 0, 0, 24, 67, 68, 77, 65, 215, 188, 128, 105, 198, 169,
+    46, 99, 215, 187, 177, 194, 159, 47, 120, 0, 215, 188, 128, 113, 45, 236, 230, 45, 215,
+    188, 128, 113, 46, 35, 158, 108,
+];
 
 #[test]
 fn packet_from_bytes() {
@@ -95,3 +109,369 @@ fn packet_conversion_roundtrip() {
     (&mut output[..]).write_bytes(&packet).unwrap();
     assert_eq!(&input[..], &output[..]);
 }
+
+#[test]
+fn extended_packet_conversion_roundtrip() {
+    let mut input = PACKET_BYTES.to_vec();
+    input.extend_from_slice(&[0x01, 0x04, 0, 16]);
+    input.extend_from_slice(&[7; 12]);
+    input.extend_from_slice(&[0x02, 0x04, 0, 28]);
+    input.extend_from_slice(&[9; 24]);
+    let extended = (&input[..]).read_bytes::<ExtendedPacket>().unwrap();
+    assert_eq!(extended.packet, (&PACKET_BYTES[..]).read_bytes::<Packet>().unwrap());
+    assert_eq!(
+        extended.extension_fields,
+        vec![
+            ExtensionField::new(ExtensionFieldType(0x0104), vec![7; 12]),
+            ExtensionField::new(ExtensionFieldType(0x0204), vec![9; 24]),
+        ]
+    );
+    assert_eq!(extended.packed_size_bytes(), input.len());
+    let mut output = vec![];
+    output.write_bytes(&extended).unwrap();
+    assert_eq!(input, output);
+}
+
+#[test]
+fn extension_field_padding() {
+    let packet = (&PACKET_BYTES[..]).read_bytes::<Packet>().unwrap();
+    let extended = ExtendedPacket {
+        packet,
+        extension_fields: vec![
+            ExtensionField::new(ExtensionFieldType(0x0104), vec![1, 2, 3, 4, 5]),
+            ExtensionField::new(ExtensionFieldType(0x0204), vec![6]),
+        ],
+        mac: None,
+    };
+    let mut output = vec![];
+    output.write_bytes(&extended).unwrap();
+    let fields = &output[Packet::PACKED_SIZE_BYTES..];
+    // Every field is padded to 16 octets except the last one, which is padded to 28.
+    assert_eq!(fields.len(), 16 + 28);
+    assert_eq!(&fields[..16], &[1, 4, 0, 16, 1, 2, 3, 4, 5, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(&fields[16..20], &[2, 4, 0, 28]);
+    assert_eq!(fields[20], 6);
+    assert!(fields[21..].iter().all(|&b| b == 0));
+    assert_eq!(extended.packed_size_bytes(), output.len());
+}
+
+#[test]
+fn extension_field_misaligned() {
+    let mut input = PACKET_BYTES.to_vec();
+    input.extend_from_slice(&[0x01, 0x04, 0, 18]);
+    input.extend_from_slice(&[0; 14]);
+    let err = (&input[..]).read_bytes::<ExtendedPacket>().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn extension_field_too_short() {
+    let mut input = PACKET_BYTES.to_vec();
+    input.extend_from_slice(&[0x01, 0x04, 0, 8, 0, 0, 0, 0]);
+    let err = (&input[..]).read_bytes::<ExtendedPacket>().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn extension_field_truncated() {
+    let mut input = PACKET_BYTES.to_vec();
+    input.extend_from_slice(&[0x01, 0x04, 0, 32]);
+    input.extend_from_slice(&[0; 12]);
+    let err = (&input[..]).read_bytes::<ExtendedPacket>().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+    let mut input = PACKET_BYTES.to_vec();
+    input.extend_from_slice(&[0x01, 0x04]);
+    let err = (&input[..]).read_bytes::<ExtendedPacket>().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn extended_packet_with_mac() {
+    let mut input = PACKET_BYTES.to_vec();
+    input.extend_from_slice(&[0x01, 0x04, 0, 16]);
+    input.extend_from_slice(&[7; 12]);
+    input.extend_from_slice(&[0, 0, 0, 42]);
+    input.extend_from_slice(&[3; 16]);
+    let extended = (&input[..]).read_bytes::<ExtendedPacket>().unwrap();
+    assert_eq!(extended.extension_fields.len(), 1);
+    assert_eq!(extended.mac, Some(Mac { key_id: 42, digest: vec![3; 16] }));
+    let mut output = vec![];
+    output.write_bytes(&extended).unwrap();
+    assert_eq!(input, output);
+}
+
+#[test]
+fn extended_packet_crypto_nak() {
+    let mut input = PACKET_BYTES.to_vec();
+    input.extend_from_slice(&[0, 0, 0, 0]);
+    let extended = (&input[..]).read_bytes::<ExtendedPacket>().unwrap();
+    assert!(extended.extension_fields.is_empty());
+    assert!(extended.mac.unwrap().is_crypto_nak());
+}
+
+#[test]
+fn last_extension_field_too_short_without_mac() {
+    let mut input = PACKET_BYTES.to_vec();
+    input.extend_from_slice(&[0x01, 0x04, 0, 16]);
+    input.extend_from_slice(&[7; 12]);
+    let err = (&input[..]).read_bytes::<ExtendedPacket>().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn kiss_of_death_packet() {
+    let mut input = PACKET_BYTES;
+    input[1] = 0;
+    input[12..16].copy_from_slice(b"RATE");
+    let packet = (&input[..]).read_bytes::<Packet>().unwrap();
+    assert_eq!(packet.stratum, Stratum::UNSPECIFIED);
+    assert_eq!(packet.reference_id, ReferenceIdentifier::KissOfDeath(KissOfDeath::Rate));
+
+    input[12..16].copy_from_slice(b"XKOD");
+    let packet = (&input[..]).read_bytes::<Packet>().unwrap();
+    let kod = KissOfDeath::Unknown(*b"XKOD");
+    assert_eq!(packet.reference_id, ReferenceIdentifier::KissOfDeath(kod));
+    let mut output = vec![];
+    output.write_bytes(packet).unwrap();
+    assert_eq!(&output[..], &input[..]);
+}
+
+#[test]
+fn kiss_code_registry() {
+    let codes = [
+        b"ACST", b"AUTH", b"AUTO", b"BCST", b"CRYP", b"DENY", b"DROP", b"RSTR", b"INIT", b"MCST",
+        b"NKEY", b"NTSN", b"RATE", b"RMOT", b"STEP",
+    ];
+    for code in codes.iter() {
+        let kod = KissOfDeath::from_bytes(**code);
+        assert!(kod != KissOfDeath::Unknown(**code));
+        assert_eq!(&kod.bytes(), *code);
+        assert_eq!(kod.to_string().as_bytes(), &code[..]);
+    }
+    assert_eq!(KissOfDeath::Deny.action(), KissAction::Demobilize);
+    assert_eq!(KissOfDeath::Rstr.action(), KissAction::Demobilize);
+    assert_eq!(KissOfDeath::Rate.action(), KissAction::ReduceRate);
+    assert_eq!(KissOfDeath::Step.action(), KissAction::Informational);
+    assert_eq!(KissOfDeath::Unknown(*b"XYZW").action(), KissAction::Informational);
+}
+
+#[test]
+fn kiss_code_not_decoded_for_primary_stratum() {
+    let mut input = PACKET_BYTES;
+    input[12..16].copy_from_slice(b"RATE");
+    let packet = (&input[..]).read_bytes::<Packet>().unwrap();
+    assert_eq!(packet.reference_id, ReferenceIdentifier::Unknown(*b"RATE"));
+}
+
+#[test]
+fn unsynchronized_and_reserved_strata() {
+    for &stratum in &[16, 17, 255] {
+        let mut input = PACKET_BYTES;
+        input[1] = stratum;
+        input[12..16].copy_from_slice(b"INIT");
+        let packet = (&input[..]).read_bytes::<Packet>().unwrap();
+        assert_eq!(packet.stratum, Stratum(stratum));
+        assert_eq!(packet.reference_id, ReferenceIdentifier::Unknown(*b"INIT"));
+        let mut output = vec![];
+        output.write_bytes(packet).unwrap();
+        assert_eq!(&output[..], &input[..]);
+    }
+}
+
+#[test]
+fn stratum_mapping() {
+    assert_eq!(Stratum::from_packet(Stratum::UNSPECIFIED), Stratum::MAX);
+    assert_eq!(Stratum::from_packet(Stratum(3)), Stratum(3));
+    assert_eq!(Stratum::MAX.to_packet(), Stratum::UNSPECIFIED);
+    assert_eq!(Stratum(200).to_packet(), Stratum::UNSPECIFIED);
+    assert_eq!(Stratum(15).to_packet(), Stratum(15));
+}
+
+#[test]
+fn reference_identifier_roundtrip() {
+    for &(stratum, code) in &[(1, b"XFAC"), (1, b"PTP\0"), (1, b"SHM\0"), (1, b"\xff\0\x01\x02")] {
+        let mut input = PACKET_BYTES;
+        input[1] = stratum;
+        input[12..16].copy_from_slice(code);
+        let packet = (&input[..]).read_bytes::<Packet>().unwrap();
+        assert_eq!(packet.reference_id.bytes(), *code);
+        let mut output = vec![];
+        output.write_bytes(packet).unwrap();
+        assert_eq!(&output[..], &input[..]);
+    }
+}
+
+#[test]
+fn reference_identifier_views() {
+    let xfac = ReferenceIdentifier::from_bytes(Stratum::PRIMARY, *b"XFAC");
+    assert_eq!(xfac, ReferenceIdentifier::Unknown(*b"XFAC"));
+    assert!(xfac.is_experimental());
+    assert_eq!(xfac.to_string(), "XFAC");
+
+    let gps = ReferenceIdentifier::from_bytes(Stratum::PRIMARY, *b"GPS\0");
+    assert_eq!(gps, ReferenceIdentifier::PrimarySource(PrimarySource::Gps));
+    assert!(!gps.is_experimental());
+    assert_eq!(gps.as_ascii().unwrap().as_str(), "GPS");
+    assert_eq!(gps.to_string(), "GPS");
+
+    let upstream = ReferenceIdentifier::from_bytes(Stratum(2), [192, 0, 2, 1]);
+    assert_eq!(upstream.as_ipv4(), Ipv4Addr::new(192, 0, 2, 1));
+    assert_eq!(upstream.to_string(), "192.0.2.1");
+    assert!(upstream.as_ascii().is_none());
+
+    let addr: Ipv6Addr = "2001:db8::1".parse().unwrap();
+    let hashed = ReferenceIdentifier::from_addr(IpAddr::V6(addr));
+    assert!(hashed.as_ipv6_hash().matches(&addr));
+    assert_eq!(hashed.as_ipv6_hash(), Ipv6Hash::new(&addr));
+    assert_eq!(hashed.as_ipv6_hash().to_string().len(), 8);
+    assert_eq!(Ipv6Hash::from(hashed.bytes()), hashed.as_ipv6_hash());
+    assert_eq!(hashed.as_ipv6_hash().bytes(), hashed.bytes());
+    let v4 = ReferenceIdentifier::from_addr(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+    assert_eq!(v4.bytes(), [10, 0, 0, 1]);
+
+    let garbage = ReferenceIdentifier::KissOfDeath(KissOfDeath::Unknown([0xff, 0, 1, 2]));
+    assert_eq!(garbage.to_string(), "\\xff\\x00\\x01\\x02");
+}
+
+#[test]
+fn packet_view_matches_packet() {
+    let packet = (&PACKET_BYTES[..]).read_bytes::<Packet>().unwrap();
+    let view = PacketView::new(&PACKET_BYTES[..]).unwrap();
+    assert_eq!(view.to_packet(), packet);
+    assert_eq!(Packet::from(&view), packet);
+    assert!(view.payload().is_empty());
+
+    let owned = PacketView::from(packet);
+    assert_eq!(owned.into_inner(), PACKET_BYTES);
+
+    // The version is read as sent, even if it is not a known version.
+    let mut buffer = PACKET_BYTES;
+    buffer[0] |= 0b111 << 3;
+    assert!(!PacketView::new(&buffer[..]).unwrap().version().is_known());
+}
+
+#[test]
+fn packet_view_in_place_edits() {
+    let mut buffer = PACKET_BYTES.to_vec();
+    buffer.extend_from_slice(&[1, 2, 3, 4]);
+    let timestamp = TimestampFormat { seconds: 0x01020304, fraction: 0x05060708 };
+    {
+        let mut view = PacketView::new(&mut buffer[..]).unwrap();
+        view.set_origin_timestamp(timestamp);
+        view.set_li_vn_mode(LeapIndicator::Unknown, Version::V4, Mode::Client);
+        assert_eq!(view.payload(), &[1, 2, 3, 4]);
+    }
+    assert_eq!(&buffer[24..32], &[1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(buffer[0], 0xe3);
+    let packet = (&buffer[..48]).read_bytes::<Packet>().unwrap();
+    assert_eq!(packet.origin_timestamp, timestamp);
+    assert_eq!(packet.mode, Mode::Client);
+
+    let mut other = [0u8; 48];
+    let view = PacketView::from_packet(&mut other[..], &packet).unwrap();
+    assert_eq!(view.to_packet(), packet);
+}
+
+#[test]
+fn packet_view_too_short() {
+    let err = PacketView::new(&PACKET_BYTES[..47]).unwrap_err();
+    let expected = ProtocolError::Truncated {
+        field: Field::TransmitTimestamp,
+        offset: 40,
+        needed: 8,
+        available: 7,
+    };
+    assert_eq!(err, expected);
+    assert_eq!(io::Error::from(err).kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn packet_slice_encoding() {
+    let packet = Packet::decode(&PACKET_BYTES).unwrap();
+    assert_eq!(packet, (&PACKET_BYTES[..]).read_bytes::<Packet>().unwrap());
+    let mut buffer = [0u8; 64];
+    assert_eq!(packet.encode(&mut buffer).unwrap(), Packet::PACKED_SIZE_BYTES);
+    assert_eq!(&buffer[..48], &PACKET_BYTES[..]);
+    assert!(packet.encode(&mut buffer[..40]).is_err());
+}
+
+#[test]
+fn header_enum_conversions() {
+    assert_eq!(Mode::try_from(4), Ok(Mode::Server));
+    let err = ProtocolError::Invalid { field: Field::Mode, offset: 0, value: 8 };
+    assert_eq!(Mode::try_from(8), Err(err));
+    assert_eq!(LeapIndicator::try_from(3), Ok(LeapIndicator::Unknown));
+    assert_eq!(PrimarySource::try_from(0x47505300), Ok(PrimarySource::Gps));
+    assert!(PrimarySource::try_from(0x58464143).is_err());
+}
+
+#[test]
+fn typed_decode_errors() {
+    let err = Packet::decode(&PACKET_BYTES[..13]).unwrap_err();
+    assert_eq!(err.field(), Field::ReferenceId);
+    assert_eq!(err.offset(), 12);
+    assert!(err.is_truncated());
+
+    let mut bytes = PACKET_BYTES.to_vec();
+    bytes.extend_from_slice(&[0, 1, 0, 30]);
+    bytes.extend_from_slice(&[0; 28]);
+    let err = ExtendedPacket::decode(&bytes).unwrap_err();
+    let expected = ProtocolError::Invalid {
+        field: Field::ExtensionFieldLength,
+        offset: 50,
+        value: 30,
+    };
+    assert_eq!(err, expected);
+
+    // The typed error survives the conversion to `io::Error` when reading.
+    let io_err = (&bytes[..]).read_bytes::<ExtendedPacket>().unwrap_err();
+    assert_eq!(io_err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(ProtocolError::from_io_error(&io_err), Some(expected));
+
+    bytes[51] = 36;
+    let err = ExtendedPacket::decode(&bytes).unwrap_err();
+    let expected = ProtocolError::Truncated {
+        field: Field::ExtensionFieldValue,
+        offset: 52,
+        needed: 32,
+        available: 28,
+    };
+    assert_eq!(err, expected);
+}
+
+#[test]
+fn typed_encode_errors() {
+    let packet = (&PACKET_BYTES[..]).read_bytes::<Packet>().unwrap();
+    let long_field = ExtensionField::new(ExtensionFieldType(0x0204), vec![0; 65532]);
+    let mut extended = ExtendedPacket {
+        packet,
+        extension_fields: vec![
+            ExtensionField::new(ExtensionFieldType(0x0104), vec![7; 12]),
+            long_field.clone(),
+        ],
+        mac: None,
+    };
+    let io_err = vec![].write_bytes(&extended).unwrap_err();
+    assert_eq!(io_err.kind(), io::ErrorKind::InvalidData);
+    let expected = ProtocolError::Invalid {
+        field: Field::ExtensionFieldLength,
+        offset: 66,
+        value: 65536,
+    };
+    assert_eq!(ProtocolError::from_io_error(&io_err), Some(expected));
+
+    let io_err = vec![].write_bytes(&long_field).unwrap_err();
+    let expected = ProtocolError::Invalid {
+        field: Field::ExtensionFieldLength,
+        offset: 2,
+        value: 65536,
+    };
+    assert_eq!(ProtocolError::from_io_error(&io_err), Some(expected));
+
+    extended.extension_fields.pop();
+    extended.mac = Some(Mac { key_id: 42, digest: vec![3; 8] });
+    let io_err = vec![].write_bytes(&extended).unwrap_err();
+    let expected = ProtocolError::Invalid { field: Field::Mac, offset: 64, value: 12 };
+    assert_eq!(ProtocolError::from_io_error(&io_err), Some(expected));
+}
@@ -0,0 +1,89 @@
+#![cfg(feature = "std")]
+
+use ntp::client::Client;
+use ntp::clock::ManualClock;
+use ntp::protocol::{Packet, TimestampFormat};
+use ntp::query::Query;
+use ntp::transport::{ScriptedReply, ScriptedTransport};
+use std::io;
+use std::time::Duration;
+
+mod common;
+
+const SERVERS: [&str; 3] = ["192.0.2.1:123", "192.0.2.2:123", "192.0.2.3:123"];
+
+// The local time, at which the clock of the client is stopped.
+const NOW: TimestampFormat = TimestampFormat { seconds: 1000, fraction: 0 };
+
+// A valid response from a primary server whose clock is `offset` seconds ahead of ours.
+fn response(offset: f64) -> Packet {
+    let at = NOW.seconds as u64 * 1_000_000 + (offset * 1e6) as u64;
+    let time = TimestampFormat {
+        seconds: (at / 1_000_000) as u32,
+        fraction: (((at % 1_000_000) << 32) / 1_000_000) as u32,
+    };
+    common::response_at(time)
+}
+
+fn query() -> Query {
+    let client = Client::new()
+        .timeout(Duration::from_millis(100))
+        .clock(ManualClock::new(NOW));
+    Query::new(client).samples(1).spacing(Duration::from_secs(0))
+}
+
+#[test]
+fn rejects_falseticker() {
+    let mut transport = ScriptedTransport::new()
+        .reply(ScriptedReply::new(response(0.001)))
+        .reply(ScriptedReply::new(response(0.002)))
+        .reply(ScriptedReply::new(response(10.0)));
+    let report = query().run_with(&mut transport, &SERVERS).unwrap();
+
+    let truechimers: Vec<bool> = report.servers.iter().map(|s| s.truechimer).collect();
+    assert_eq!(truechimers, [true, true, false]);
+    let survivors: Vec<bool> = report.servers.iter().map(|s| s.survivor).collect();
+    assert_eq!(survivors, [true, true, false]);
+    let estimate = report.estimate.unwrap();
+    assert_eq!((estimate.truechimers, estimate.survivors), (2, 2));
+    assert_eq!(estimate.system_peer, SERVERS[0].parse().unwrap());
+    assert!((estimate.offset - 0.0015).abs() < 1e-5);
+    assert!(estimate.error > 0.0005 && estimate.error < 0.01);
+}
+
+#[test]
+fn keeps_lowest_delay_sample() {
+    // Answer the three samples to one server after decreasing and then increasing delays.
+    let client = Client::new().timeout(Duration::from_millis(200));
+    let packet = common::response();
+    let mut transport = ScriptedTransport::new();
+    for delay in &[40, 0, 20] {
+        let reply = ScriptedReply::new(packet).delay(Duration::from_millis(*delay));
+        transport = transport.reply(reply);
+    }
+    let report = Query::new(client)
+        .samples(3)
+        .spacing(Duration::from_millis(1))
+        .run_with(&mut transport, &SERVERS[..1])
+        .unwrap();
+
+    let server = &report.servers[0];
+    assert_eq!((server.sent, server.measurements.len()), (3, 3));
+    assert!(server.best.unwrap().delay < 0.02);
+    assert!(server.error.is_none());
+}
+
+#[test]
+fn requires_majority() {
+    let mut transport = ScriptedTransport::new()
+        .reply(ScriptedReply::new(response(0.0)))
+        .silence()
+        .reply(ScriptedReply::new(response(10.0)));
+    let report = query().run_with(&mut transport, &SERVERS).unwrap();
+
+    assert!(report.estimate.is_none());
+    assert!(report.servers.iter().all(|s| !s.truechimer));
+    let silent = &report.servers[1];
+    assert_eq!((silent.sent, silent.measurements.len()), (1, 0));
+    assert_eq!(silent.error.as_ref().unwrap().kind(), io::ErrorKind::TimedOut);
+}
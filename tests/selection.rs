@@ -0,0 +1,97 @@
+#![cfg(feature = "std")]
+
+use ntp::filter::PeerStatistics;
+use ntp::protocol::{Packet, ShortFormat, Stratum, TimestampFormat, TOLERANCE};
+use ntp::selection::{select, Candidate, Verdict};
+
+mod common;
+
+use Verdict::{Falseticker, Truechimer, Unfit};
+
+fn candidate(low: f64, high: f64) -> Candidate {
+    Candidate {
+        offset: (low + high) / 2.0,
+        jitter: 0.0,
+        root_distance: (high - low) / 2.0,
+        stratum: Stratum::SECONDARY_MIN,
+    }
+}
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-9
+}
+
+// The intervals of RFC 5905 figure 13: A, B and C intersect, while D lies apart from them.
+#[test]
+fn rfc_figure() {
+    let candidates = [
+        candidate(-0.25, 0.5),
+        candidate(0.0, 0.75),
+        candidate(-0.125, 0.375),
+        candidate(0.625, 1.0),
+    ];
+    let selection = select(&candidates).unwrap();
+    assert_eq!(selection.low, 0.0);
+    assert_eq!(selection.high, 0.375);
+    assert_eq!(selection.verdicts, [Truechimer, Truechimer, Truechimer, Falseticker]);
+}
+
+// Marzullo's example of the intervals 8..12, 11..13 and 10..12, scaled down to seconds. All three
+// intersect in 11..12, but the midpoint of the first lies outside it, so one falseticker is
+// allowed for and the intersection of any two becomes 10..12, which holds every midpoint.
+#[test]
+fn marzullo_example() {
+    let sixteenths = |low, high| candidate(low / 16.0, high / 16.0);
+    let candidates = [sixteenths(8.0, 12.0), sixteenths(11.0, 13.0), sixteenths(10.0, 12.0)];
+    let selection = select(&candidates).unwrap();
+    assert_eq!(selection.low, 10.0 / 16.0);
+    assert_eq!(selection.high, 12.0 / 16.0);
+    assert_eq!(selection.verdicts, [Truechimer; 3]);
+    assert_eq!(selection.truechimers().collect::<Vec<_>>(), [0, 1, 2]);
+}
+
+#[test]
+fn no_majority() {
+    let candidates = [candidate(0.0, 0.25), candidate(0.5, 0.75)];
+    assert_eq!(select(&candidates), None);
+    assert_eq!(select(&[]), None);
+
+    let selection = select(&[candidate(0.0, 0.25)]).unwrap();
+    assert_eq!(selection.verdicts, [Truechimer]);
+}
+
+#[test]
+fn excludes_unfit_candidates() {
+    let mut unsynchronized = candidate(0.0, 0.25);
+    unsynchronized.stratum = Stratum::UNSYNCHRONIZED;
+    let distant = candidate(-1.0, 1.5);
+    let candidates = [unsynchronized, distant, candidate(0.0, 0.5), candidate(0.25, 0.75)];
+    let selection = select(&candidates).unwrap();
+    assert_eq!(selection.verdicts, [Unfit, Unfit, Truechimer, Truechimer]);
+}
+
+#[test]
+fn candidate_root_distance() {
+    let short = |seconds, fraction| ShortFormat { seconds, fraction };
+    let packet = Packet {
+        stratum: Stratum::SECONDARY_MIN,
+        poll: 6,
+        root_delay: short(0, 0x1000),
+        root_dispersion: short(0, 0x0800),
+        ..common::response_at(TimestampFormat::default())
+    };
+    let statistics = PeerStatistics {
+        offset: 0.25,
+        delay: 0.0375,
+        dispersion: 0.002,
+        jitter: 0.001,
+        epoch: TimestampFormat { seconds: 1000, fraction: 0 },
+    };
+    let now = TimestampFormat { seconds: 1100, fraction: 0 };
+    let candidate = Candidate::new(&packet, &statistics, now);
+    assert_eq!(candidate.offset, 0.25);
+    assert_eq!(candidate.jitter, 0.001);
+    assert_eq!(candidate.stratum, Stratum::SECONDARY_MIN);
+    let expected = (0.0625 + 0.0375) / 2.0 + 0.03125 + 0.002 + 100.0 * TOLERANCE + 0.001;
+    assert!(close(candidate.root_distance, expected));
+}
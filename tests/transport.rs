@@ -0,0 +1,110 @@
+#![cfg(feature = "std")]
+
+use ntp::client::{Client, ResponseError};
+use ntp::protocol::{KissOfDeath, Mode, Packet};
+use ntp::transport::{ScriptedReply, ScriptedTransport};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+mod common;
+
+use common::{kiss, response};
+
+const SERVER: &str = "192.0.2.1:123";
+
+fn client() -> Client {
+    Client::new()
+        .timeout(Duration::from_millis(100))
+        .backoff(Duration::from_millis(10))
+}
+
+// Unwrap the rejection reason from the result of a request.
+fn rejection<T>(result: io::Result<T>) -> ResponseError {
+    let err = result.err().expect("response was not rejected");
+    ResponseError::from_io_error(&err).expect("error is not a rejection")
+}
+
+#[test]
+fn scripted_request() {
+    let mut transport = ScriptedTransport::new().reply(ScriptedReply::new(response()));
+    let measurement = client().request_with(&mut transport, SERVER).unwrap();
+    assert!(measurement.offset.abs() < 0.1);
+
+    let (ref bytes, addr) = transport.sent()[0];
+    assert_eq!(addr, SERVER.parse::<SocketAddr>().unwrap());
+    let request = Packet::decode(bytes).unwrap();
+    assert_eq!(request.mode, Mode::Client);
+    assert_eq!(measurement.packet.origin_timestamp, request.transmit_timestamp);
+}
+
+#[test]
+fn delayed_reply() {
+    let delay = Duration::from_millis(30);
+    let reply = ScriptedReply::new(response()).delay(delay);
+    let mut transport = ScriptedTransport::new().reply(reply);
+    let measurement = client().request_with(&mut transport, SERVER).unwrap();
+    assert!(measurement.delay >= 0.03);
+}
+
+#[test]
+fn late_reply_times_out() {
+    let delay = Duration::from_millis(300);
+    let reply = ScriptedReply::new(response()).delay(delay);
+    let mut transport = ScriptedTransport::new().reply(reply);
+    let err = client().request_with(&mut transport, SERVER).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+}
+
+#[test]
+fn retries_after_silence() {
+    let mut transport = ScriptedTransport::new()
+        .silence()
+        .reply(ScriptedReply::new(response()));
+    client().retries(1).request_with(&mut transport, SERVER).unwrap();
+    assert_eq!(transport.sent().len(), 2);
+}
+
+#[test]
+fn drops_spoofed_reply() {
+    // The genuine reply arriving after a spoofed or stale one is accepted.
+    let spoofer: SocketAddr = "198.51.100.1:123".parse().unwrap();
+    let genuine = ScriptedReply::new(response()).delay(Duration::from_millis(10));
+    let bogus = [
+        ScriptedReply::new(response()).source(spoofer),
+        ScriptedReply::new(response()).keep_origin(),
+    ];
+    for reply in bogus.iter() {
+        let replies = vec![reply.clone(), genuine.clone()];
+        let mut transport = ScriptedTransport::new().replies(replies);
+        client().request_with(&mut transport, SERVER).unwrap();
+        assert_eq!(transport.sent().len(), 1);
+    }
+
+    // Without a genuine reply the attempt times out with the reason the last datagram was
+    // dropped.
+    let reply = ScriptedReply::new(response()).source(spoofer);
+    let mut transport = ScriptedTransport::new().reply(reply);
+    let result = client().request_with(&mut transport, SERVER);
+    assert_eq!(rejection(result), ResponseError::UnexpectedSource(spoofer));
+
+    let reply = ScriptedReply::new(response()).keep_origin();
+    let mut transport = ScriptedTransport::new().reply(reply);
+    let result = client().request_with(&mut transport, SERVER);
+    assert_eq!(rejection(result), ResponseError::OriginMismatch);
+}
+
+#[test]
+fn rejects_kiss_of_death() {
+    let reply = ScriptedReply::new(kiss(KissOfDeath::Deny));
+    let mut transport = ScriptedTransport::new().reply(reply);
+    let result = client().request_with(&mut transport, SERVER);
+    assert_eq!(rejection(result), ResponseError::KissOfDeath(KissOfDeath::Deny));
+
+    // The request is not sent again, even with retries left.
+    let reply = ScriptedReply::new(kiss(KissOfDeath::Rate));
+    let mut transport = ScriptedTransport::new().reply(reply);
+    let result = client().retries(2).request_with(&mut transport, SERVER);
+    assert_eq!(rejection(result), ResponseError::KissOfDeath(KissOfDeath::Rate));
+    assert_eq!(transport.sent().len(), 1);
+}
@@ -0,0 +1,57 @@
+#![cfg(feature = "std")]
+
+use ntp::protocol::{ShortFormat, TimestampFormat};
+use ntp::unix_time::{Instant, EPOCH_DELTA};
+
+const NANOS: [i32; 6] = [0, 1, 250_000_000, 500_000_001, 999_999_998, 999_999_999];
+
+fn parts(t: Instant) -> (i64, i32) {
+    (t.secs(), t.subsec_nanos())
+}
+
+#[test]
+fn timestamp_conversions_are_exact() {
+    let t: TimestampFormat = Instant::new(1, 500_000_000).into();
+    assert_eq!(t, TimestampFormat { seconds: EPOCH_DELTA as u32 + 1, fraction: 1 << 31 });
+    let t = TimestampFormat { seconds: EPOCH_DELTA as u32 + 1, fraction: 1 << 30 };
+    assert_eq!(parts(t.into()), (1, 250_000_000));
+
+    // The largest fraction rounds up to the next second.
+    let t = TimestampFormat { seconds: EPOCH_DELTA as u32, fraction: u32::MAX };
+    assert_eq!(parts(t.into()), (1, 0));
+}
+
+#[test]
+fn instant_timestamp_roundtrip() {
+    for &secs in &[0, 1, 1_700_000_000] {
+        for &nanos in &NANOS {
+            let t: TimestampFormat = Instant::new(secs, nanos).into();
+            assert_eq!(parts(t.into()), (secs, nanos));
+        }
+    }
+    // Before the epoch both components count down.
+    for &nanos in &NANOS {
+        let t: TimestampFormat = Instant::new(-1, -nanos).into();
+        assert_eq!(parts(t.into()), (-1, -nanos));
+    }
+}
+
+#[test]
+fn short_format_instant_roundtrip() {
+    for &seconds in &[0, 1, u16::MAX] {
+        for &fraction in &[0, 1, 1 << 15, u16::MAX - 1, u16::MAX] {
+            let t = ShortFormat { seconds, fraction };
+            let instant: Instant = t.into();
+            assert_eq!(ShortFormat::from(instant), t);
+        }
+    }
+}
+
+#[test]
+fn timestamps_before_the_epoch() {
+    // NTP times before 1970 have a fraction that counts up, unlike an **Instant**.
+    let t = TimestampFormat { seconds: EPOCH_DELTA as u32 - 2, fraction: 3 << 30 };
+    assert_eq!(parts(t.into()), (-1, -250_000_000));
+    let t = TimestampFormat { seconds: 0, fraction: 1 << 31 };
+    assert_eq!(parts(t.into()), (-EPOCH_DELTA + 1, -500_000_000));
+}